@@ -2,6 +2,11 @@ use std::error::Error;
 
 use serde::{Deserialize, Serialize};
 
+use crate::stream_format::StreamFormat;
+
+/// Header describing the samples in a `TorchPacket`, in `StreamFormat`'s text form.
+const FORMAT_HEADER: &str = "X-Audia-Format";
+
 #[derive(Serialize)]
 pub(crate) struct TorchPacket<T> {
     pub(crate) data_packet: Vec<T>,
@@ -20,6 +25,7 @@ lazy_static! {
 
 pub(crate) async fn send_to_python<T>(
     input: TorchPacket<T>,
+    format: &StreamFormat,
 ) -> Result<TextPredictions, Box<dyn Error>>
     where
         T: Serialize,
@@ -27,6 +33,7 @@ pub(crate) async fn send_to_python<T>(
     let resp = CLIENT
         .post("http://127.0.0.1:8000/uncompressed")
         .timeout(std::time::Duration::from_millis(500))
+        .header(FORMAT_HEADER, format.to_string())
         .json(&input)
         .send()
         .await?
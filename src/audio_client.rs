@@ -1,6 +1,6 @@
 use std::mem::MaybeUninit;
 
-use log::debug;
+use log::{debug, warn};
 use serde::Serialize;
 use winapi::shared::mmreg::WAVEFORMATEX;
 use winapi::um::audioclient::{IAudioCaptureClient, IAudioClient, IID_IAudioCaptureClient};
@@ -12,7 +12,7 @@ use winapi::um::mmdeviceapi::IMMDevice;
 use crate::capture_client::RecordingAudioClient;
 use crate::device::Device;
 use crate::stream_format;
-use crate::stream_format::StreamFormat;
+use crate::stream_format::{FormatError, StreamFormat};
 
 const REFTIME_PER_SEC: i64 = 10_000_000;
 
@@ -22,7 +22,9 @@ pub(crate) struct UninitialisedAudioClientWrapper {
 
 pub(crate) struct IAudioClientWrapper {
     pub(crate) iaudio_client: *mut IAudioClient,
-    format: Option<StreamFormat>,
+    /// The mix format, or why it can't be captured. The client is still initialised for
+    /// unsupported formats so the device can be reported.
+    format: Option<Result<StreamFormat, FormatError>>,
     raw_format: Option<WAVEFORMATEX>,
 }
 
@@ -34,7 +36,7 @@ impl IAudioClientWrapper {
     }
 
     pub(crate) fn buffer_duration(&self) -> Result<std::time::Duration, anyhow::Error> {
-        if let Some(format) = &self.format {
+        if let Some(Ok(format)) = &self.format {
             let res = self.get_buffer_size() as i64 / format.n_sample_per_sec as i64;
             Ok(::std::time::Duration::from_secs(res as u64))
         } else {
@@ -62,8 +64,12 @@ impl IAudioClientWrapper {
         self.raw_format
     }
 
-    pub(crate) fn get_format(&self) -> Option<StreamFormat> {
-        self.format
+    pub(crate) fn get_format(&self) -> Result<StreamFormat, anyhow::Error> {
+        match &self.format {
+            Some(Ok(format)) => Ok(*format),
+            Some(Err(e)) => Err(anyhow!(e.clone())),
+            None => Err(anyhow!("Format was not set for Audio Client")),
+        }
     }
 
     pub(crate) fn record<T>(&self) -> RecordingAudioClient<T>
@@ -94,7 +100,7 @@ impl IAudioClientWrapper {
                     audio_client: &self,
                     capture_client,
                     buffer: None,
-                    format: self.format.unwrap(),
+                    format: self.get_format().unwrap(),
                 },
             }
         }
@@ -149,7 +155,10 @@ impl UninitialisedAudioClientWrapper {
                 Err(e) => panic!(format!("[Audio GetMixFormat] - {x}", x = e)),
                 Ok(_) => {
                     wrapper.raw_format = Some(*mix_fmt);
-                    wrapper.format = Some(wrapper.raw_format.unwrap().into());
+                    wrapper.format = Some(StreamFormat::from_raw(mix_fmt));
+                    if let Some(Err(e)) = &wrapper.format {
+                        warn!("Mix format can't be captured: {}", e);
+                    }
                     let hr_result = wrapper.iaudio_client.as_ref().unwrap().Initialize(
                         AUDCLNT_SHAREMODE_SHARED,
                         AUDCLNT_STREAMFLAGS_LOOPBACK,
@@ -31,11 +31,15 @@ impl AudioSink {
     {
         let data = data.as_slice().unwrap().to_vec();
         let data_len = data.len();
-        match block_on(send_to_python(TorchPacket {
-            data_packet: data,
-            data_size: data_len / 2,
-            channels: 2,
-        })) {
+        let format = self.format.expect("Format must be set before sending to the model");
+        match block_on(send_to_python(
+            TorchPacket {
+                data_packet: data,
+                data_size: data_len / 2,
+                channels: 2,
+            },
+            &format,
+        )) {
             Ok(response) => {
                 dbg!(response.text);
             }
@@ -1,10 +1,12 @@
 use futures::StreamExt;
 
+use crate::dsp::ProcessingSample;
 use crate::stream_format;
 use crate::stream_format::{Sample, SampleFormat};
 
 const BUFFER_SECS: usize = 2;
-const BUFFER_LENGTH: usize = 192_000 * BUFFER_SECS;
+/// Samples an `ExtensibleBuffer` holds; `extend` takes at most this many at once.
+pub(crate) const BUFFER_LENGTH: usize = 192_000 * BUFFER_SECS;
 
 pub(crate) struct Buffer {
     data: *mut (),
@@ -27,8 +29,7 @@ impl<T> ExtensibleBuffer<T>
     where
         T: hound::Sample + stream_format::Sample,
 {
-    fn new(data: Vec<T>, sample_format: SampleFormat) -> ExtensibleBuffer<T> {
-        dbg!("New Buffer");
+    pub(crate) fn new(data: Vec<T>, sample_format: SampleFormat) -> ExtensibleBuffer<T> {
         let len: usize = data.len();
         ExtensibleBuffer {
             data,
@@ -44,8 +45,10 @@ impl<T> ExtensibleBuffer<T>
         sample_format: SampleFormat,
     ) -> Self {
         assert!(len <= BUFFER_LENGTH);
+        // The pointer is owned by the capture client and released with `ReleaseBuffer`, so the
+        // samples must be copied rather than adopted by the `Vec`.
         ExtensibleBuffer {
-            data: Vec::from_raw_parts(data, len, len),
+            data: std::slice::from_raw_parts(data, len).to_vec(),
             len,
             max_len: BUFFER_LENGTH,
             sample_format,
@@ -75,29 +78,21 @@ impl<T> ExtensibleBuffer<T>
     // FIXME: Issue with Extension Causing a Pointer Error.
     pub(crate) fn extend(&mut self, data: &[T], data_len: usize) {
         if !data.is_empty() {
-            if !self.has_unused_buffer() {
-                dbg!("No Unused Buffer");
+            if data_len >= self.max_len {
+                // Only the newest `max_len` samples of a packet this long fit.
+                self.data.clear();
+                self.data.extend_from_slice(&data[data_len - self.max_len..data_len]);
+                self.len = self.max_len;
+            } else if !self.has_unused_buffer() {
                 let start_index = self.max_len - data_len;
                 self.data.rotate_left(data_len);
-                dbg!("Rotated");
-                dbg!(self.max_len);
-                dbg!(start_index);
-                dbg!(data.len());
                 self.data.splice(start_index.., data.iter().cloned());
-                dbg!("Splice");
             } else {
-                dbg!("Has Unused Buffer");
-                dbg!(self.len);
-                dbg!(self.max_len);
                 let usable_elements = std::cmp::min(data_len, self.len_unused_buffer());
-                dbg!(usable_elements);
                 if data_len <= usable_elements {
-                    dbg!("Less than usable elements");
-
                     self.data.extend_from_slice(&data);
                     self.len = self.len + data_len;
                 } else {
-                    dbg!("More data than usable elements");
                     self.data.extend_from_slice(&data[..usable_elements]);
                     self.len = self.len + usable_elements;
                     self.extend(&data[usable_elements..], data_len - usable_elements);
@@ -113,6 +108,15 @@ impl<T> ExtensibleBuffer<T>
             Some(&self.data[0..self.len])
         }
     }
+
+    /// The most recent `n_samples` samples added to the buffer.
+    ///
+    /// Writers receive the whole rolling buffer, so this is how they pick out the packet that
+    /// has just arrived.
+    pub(crate) fn latest(&self, n_samples: usize) -> &[T] {
+        let len = std::cmp::min(self.len, self.data.len());
+        &self.data[len - std::cmp::min(n_samples, len)..len]
+    }
 }
 
 impl Buffer {
@@ -148,9 +152,237 @@ impl Buffer {
         if T::FORMAT == self.sample_format {
             unsafe { Some(std::slice::from_raw_parts(self.data as *const T, self.len)) }
         } else {
-            dbg!(self.sample_format);
-            dbg!(T::FORMAT);
             None
         }
     }
 }
+
+/// Window applied to each frame yielded by a `SlidingWindow`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum WindowFunction {
+    Rectangular,
+    Hann,
+    Hamming,
+    Blackman,
+}
+
+impl WindowFunction {
+    /// Coefficients for a window of `len` frames.
+    ///
+    /// Uses the periodic form, matching `torch.hann_window` and friends, so frames can be handed
+    /// straight to an STFT.
+    pub(crate) fn coefficients(&self, len: usize) -> Vec<ProcessingSample> {
+        use std::f64::consts::PI;
+        (0..len)
+            .map(|n| {
+                let phase = 2.0 * PI * n as ProcessingSample / len as ProcessingSample;
+                match *self {
+                    WindowFunction::Rectangular => 1.0,
+                    WindowFunction::Hann => 0.5 - 0.5 * phase.cos(),
+                    WindowFunction::Hamming => 0.54 - 0.46 * phase.cos(),
+                    WindowFunction::Blackman => {
+                        0.42 - 0.5 * phase.cos() + 0.08 * (2.0 * phase).cos()
+                    }
+                }
+            })
+            .collect()
+    }
+}
+
+/// A single window of interleaved samples produced by a `SlidingWindow`.
+#[derive(Debug)]
+pub(crate) struct WindowedFrame {
+    /// Index of the first frame in the window, counted from the start of the stream.
+    pub start: u64,
+    pub samples: Vec<ProcessingSample>,
+}
+
+/// Splits an interleaved stream into fixed length, possibly overlapping, windows.
+///
+/// Samples are pushed as packets arrive from the capture client; anything that doesn't yet fill
+/// a window is kept until the next packet, so windows line up regardless of packet size.
+pub(crate) struct SlidingWindow {
+    window_length: usize,
+    hop: usize,
+    n_channels: usize,
+    coefficients: Vec<ProcessingSample>,
+    pending: Vec<ProcessingSample>,
+    /// Samples still to be discarded when the hop is longer than the window.
+    skip: usize,
+    start: u64,
+}
+
+impl SlidingWindow {
+    /// # Arguments
+    /// * `window_length` - Frames per window
+    /// * `hop` - Frames between the starts of consecutive windows
+    /// * `n_channels` - Channels in the interleaved input
+    /// * `window` - Optional window function applied to every channel of each frame
+    pub(crate) fn new(
+        window_length: usize,
+        hop: usize,
+        n_channels: usize,
+        window: Option<WindowFunction>,
+    ) -> Self {
+        assert!(window_length > 0 && hop > 0 && n_channels > 0);
+        SlidingWindow {
+            window_length,
+            hop,
+            n_channels,
+            coefficients: window
+                .unwrap_or(WindowFunction::Rectangular)
+                .coefficients(window_length),
+            pending: Vec::with_capacity(window_length * n_channels),
+            skip: 0,
+            start: 0,
+        }
+    }
+
+    pub(crate) fn window_length(&self) -> usize {
+        self.window_length
+    }
+
+    pub(crate) fn hop(&self) -> usize {
+        self.hop
+    }
+
+    /// Add interleaved samples to the stream.
+    pub(crate) fn push<T>(&mut self, data: &[T])
+        where
+            T: Sample,
+    {
+        let skipped = std::cmp::min(self.skip, data.len());
+        self.skip -= skipped;
+        self.pending
+            .extend(data[skipped..].iter().map(|sample| sample.to_f64()));
+    }
+
+    /// Iterate over every complete window currently available.
+    pub(crate) fn frames(&mut self) -> Frames<'_> {
+        Frames { window: self }
+    }
+}
+
+pub(crate) struct Frames<'a> {
+    window: &'a mut SlidingWindow,
+}
+
+impl Iterator for Frames<'_> {
+    type Item = WindowedFrame;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let window = &mut *self.window;
+        let window_samples = window.window_length * window.n_channels;
+        if window.pending.len() < window_samples {
+            return None;
+        }
+        let samples = window.pending[..window_samples]
+            .chunks_exact(window.n_channels)
+            .zip(window.coefficients.iter())
+            .flat_map(|(frame, coefficient)| frame.iter().map(move |x| x * coefficient))
+            .collect();
+        let frame = WindowedFrame {
+            start: window.start,
+            samples,
+        };
+
+        let hop_samples = window.hop * window.n_channels;
+        let drained = std::cmp::min(hop_samples, window.pending.len());
+        window.pending.drain(..drained);
+        window.skip = hop_samples - drained;
+        window.start += window.hop as u64;
+        Some(frame)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn small_buffer(max_len: usize) -> ExtensibleBuffer<f32> {
+        ExtensibleBuffer {
+            data: vec![],
+            len: 0,
+            max_len,
+            sample_format: SampleFormat::F32,
+        }
+    }
+
+    fn ramp(range: std::ops::Range<usize>) -> Vec<f32> {
+        range.map(|i| i as f32).collect()
+    }
+
+    fn collect(window: &mut SlidingWindow, packets: &[Vec<f32>]) -> Vec<WindowedFrame> {
+        let mut frames = vec![];
+        for packet in packets {
+            window.push(packet);
+            frames.extend(window.frames());
+        }
+        frames
+    }
+
+    #[test]
+    fn rolls_over_when_full() {
+        let mut buffer = small_buffer(4);
+        buffer.extend(&ramp(0..3), 3);
+        buffer.extend(&ramp(3..6), 3);
+        assert_eq!(buffer.as_slice(), Some(&[2.0, 3.0, 4.0, 5.0][..]));
+        assert_eq!(buffer.latest(3), &[3.0, 4.0, 5.0]);
+    }
+
+    #[test]
+    fn keeps_the_newest_samples_of_a_packet_longer_than_the_buffer() {
+        let mut buffer = small_buffer(4);
+        buffer.extend(&ramp(0..10), 10);
+        assert_eq!(buffer.as_slice(), Some(&[6.0, 7.0, 8.0, 9.0][..]));
+        buffer.extend(&ramp(10..20), 10);
+        assert_eq!(buffer.latest(4), &[16.0, 17.0, 18.0, 19.0]);
+        buffer.extend(&ramp(20..21), 1);
+        assert_eq!(buffer.latest(4), &[17.0, 18.0, 19.0, 20.0]);
+    }
+
+    #[test]
+    fn windows_span_packets() {
+        let mut window = SlidingWindow::new(4, 2, 1, None);
+        let frames = collect(&mut window, &[ramp(0..3), ramp(3..5), ramp(5..6), ramp(6..9)]);
+        assert_eq!(frames.iter().map(|frame| frame.start).collect::<Vec<_>>(), vec![0, 2, 4]);
+        for frame in &frames {
+            let start = frame.start as f64;
+            assert_eq!(frame.samples, vec![start, start + 1.0, start + 2.0, start + 3.0]);
+        }
+    }
+
+    #[test]
+    fn skips_samples_between_windows_when_the_hop_is_longer() {
+        // Stereo, so each frame is two samples.
+        let mut window = SlidingWindow::new(2, 5, 2, None);
+        let frames = collect(&mut window, &[ramp(0..7), ramp(7..13), ramp(13..24)]);
+        assert_eq!(frames.iter().map(|frame| frame.start).collect::<Vec<_>>(), vec![0, 5, 10]);
+        for frame in &frames {
+            let first = 2.0 * frame.start as f64;
+            assert_eq!(frame.samples, vec![first, first + 1.0, first + 2.0, first + 3.0]);
+        }
+    }
+
+    #[test]
+    fn applies_the_window_to_every_channel() {
+        let mut window = SlidingWindow::new(4, 4, 2, Some(WindowFunction::Hann));
+        let frames = collect(&mut window, &[vec![1.0; 8]]);
+        let expected = [0.0, 0.0, 0.5, 0.5, 1.0, 1.0, 0.5, 0.5];
+        assert_eq!(frames.len(), 1);
+        for (sample, expected) in frames[0].samples.iter().zip(expected.iter()) {
+            assert!((sample - expected).abs() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn window_functions_are_periodic() {
+        let close = |a: &[ProcessingSample], b: &[ProcessingSample]| {
+            a.iter().zip(b).all(|(a, b)| (a - b).abs() < 1e-12)
+        };
+        assert_eq!(WindowFunction::Rectangular.coefficients(3), vec![1.0; 3]);
+        assert!(close(&WindowFunction::Hann.coefficients(4), &[0.0, 0.5, 1.0, 0.5]));
+        assert!(close(&WindowFunction::Hamming.coefficients(4), &[0.08, 0.54, 1.0, 0.54]));
+        assert!(close(&WindowFunction::Blackman.coefficients(4), &[0.0, 0.34, 1.0, 0.34]));
+    }
+}
@@ -75,9 +75,8 @@ impl<T> RecordingAudioClient<'_, T>
 
         match self.buffer {
             Some(ref mut internal_buffer) => unsafe {
-                let mut data: Vec<T> = Vec::with_capacity(len);
-                std::ptr::copy(buffer as *const T, data.as_mut_ptr(), len);
-                (*internal_buffer).extend(&data, len);
+                let data = std::slice::from_raw_parts(buffer as *const T, len);
+                (*internal_buffer).extend(data, len);
             },
             None => unsafe {
                 self.buffer = Some(ExtensibleBuffer::from_raw_parts(
@@ -0,0 +1,174 @@
+use winapi::shared::ksmedia::*;
+
+/// Speaker positions, in the order of their `SPEAKER_*` flags in a channel mask.
+///
+/// Interleaved channels are ordered the same way, so the nth set bit of a mask is the speaker
+/// for the nth channel.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Speaker {
+    FrontLeft,
+    FrontRight,
+    FrontCenter,
+    LowFrequency,
+    BackLeft,
+    BackRight,
+    FrontLeftOfCenter,
+    FrontRightOfCenter,
+    BackCenter,
+    SideLeft,
+    SideRight,
+    TopCenter,
+    TopFrontLeft,
+    TopFrontCenter,
+    TopFrontRight,
+    TopBackLeft,
+    TopBackCenter,
+    TopBackRight,
+}
+
+const SPEAKERS: [Speaker; 18] = [
+    Speaker::FrontLeft,
+    Speaker::FrontRight,
+    Speaker::FrontCenter,
+    Speaker::LowFrequency,
+    Speaker::BackLeft,
+    Speaker::BackRight,
+    Speaker::FrontLeftOfCenter,
+    Speaker::FrontRightOfCenter,
+    Speaker::BackCenter,
+    Speaker::SideLeft,
+    Speaker::SideRight,
+    Speaker::TopCenter,
+    Speaker::TopFrontLeft,
+    Speaker::TopFrontCenter,
+    Speaker::TopFrontRight,
+    Speaker::TopBackLeft,
+    Speaker::TopBackCenter,
+    Speaker::TopBackRight,
+];
+
+impl Speaker {
+    pub(crate) fn mask(&self) -> u32 {
+        match *self {
+            Speaker::FrontLeft => SPEAKER_FRONT_LEFT,
+            Speaker::FrontRight => SPEAKER_FRONT_RIGHT,
+            Speaker::FrontCenter => SPEAKER_FRONT_CENTER,
+            Speaker::LowFrequency => SPEAKER_LOW_FREQUENCY,
+            Speaker::BackLeft => SPEAKER_BACK_LEFT,
+            Speaker::BackRight => SPEAKER_BACK_RIGHT,
+            Speaker::FrontLeftOfCenter => SPEAKER_FRONT_LEFT_OF_CENTER,
+            Speaker::FrontRightOfCenter => SPEAKER_FRONT_RIGHT_OF_CENTER,
+            Speaker::BackCenter => SPEAKER_BACK_CENTER,
+            Speaker::SideLeft => SPEAKER_SIDE_LEFT,
+            Speaker::SideRight => SPEAKER_SIDE_RIGHT,
+            Speaker::TopCenter => SPEAKER_TOP_CENTER,
+            Speaker::TopFrontLeft => SPEAKER_TOP_FRONT_LEFT,
+            Speaker::TopFrontCenter => SPEAKER_TOP_FRONT_CENTER,
+            Speaker::TopFrontRight => SPEAKER_TOP_FRONT_RIGHT,
+            Speaker::TopBackLeft => SPEAKER_TOP_BACK_LEFT,
+            Speaker::TopBackCenter => SPEAKER_TOP_BACK_CENTER,
+            Speaker::TopBackRight => SPEAKER_TOP_BACK_RIGHT,
+        }
+    }
+
+    /// Conventional abbreviation, e.g. `FL` or `LFE`.
+    pub(crate) fn short_name(&self) -> &'static str {
+        match *self {
+            Speaker::FrontLeft => "FL",
+            Speaker::FrontRight => "FR",
+            Speaker::FrontCenter => "FC",
+            Speaker::LowFrequency => "LFE",
+            Speaker::BackLeft => "BL",
+            Speaker::BackRight => "BR",
+            Speaker::FrontLeftOfCenter => "FLC",
+            Speaker::FrontRightOfCenter => "FRC",
+            Speaker::BackCenter => "BC",
+            Speaker::SideLeft => "SL",
+            Speaker::SideRight => "SR",
+            Speaker::TopCenter => "TC",
+            Speaker::TopFrontLeft => "TFL",
+            Speaker::TopFrontCenter => "TFC",
+            Speaker::TopFrontRight => "TFR",
+            Speaker::TopBackLeft => "TBL",
+            Speaker::TopBackCenter => "TBC",
+            Speaker::TopBackRight => "TBR",
+        }
+    }
+}
+
+/// Which speaker each channel of a stream feeds.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct ChannelLayout {
+    n_channels: u32,
+    mask: u32,
+}
+
+impl ChannelLayout {
+    pub(crate) const MONO: ChannelLayout = ChannelLayout {
+        n_channels: 1,
+        mask: KSAUDIO_SPEAKER_MONO,
+    };
+    pub(crate) const STEREO: ChannelLayout = ChannelLayout {
+        n_channels: 2,
+        mask: KSAUDIO_SPEAKER_STEREO,
+    };
+    pub(crate) const SURROUND_5_1: ChannelLayout = ChannelLayout {
+        n_channels: 6,
+        mask: KSAUDIO_SPEAKER_5POINT1,
+    };
+    pub(crate) const SURROUND_7_1: ChannelLayout = ChannelLayout {
+        n_channels: 8,
+        mask: KSAUDIO_SPEAKER_7POINT1_SURROUND,
+    };
+
+    /// Layout for `n_channels` with the given channel mask.
+    ///
+    /// A zero mask, as found on plain `WAVEFORMATEX` formats, falls back to the layout Windows
+    /// assumes for that channel count.
+    pub(crate) fn new(n_channels: u32, mask: u32) -> Self {
+        let mask = if mask == 0 {
+            ChannelLayout::default_mask(n_channels)
+        } else {
+            mask
+        };
+        ChannelLayout { n_channels, mask }
+    }
+
+    fn default_mask(n_channels: u32) -> u32 {
+        match n_channels {
+            1 => KSAUDIO_SPEAKER_MONO,
+            2 => KSAUDIO_SPEAKER_STEREO,
+            3 => KSAUDIO_SPEAKER_2POINT1,
+            4 => KSAUDIO_SPEAKER_QUAD,
+            6 => KSAUDIO_SPEAKER_5POINT1,
+            8 => KSAUDIO_SPEAKER_7POINT1_SURROUND,
+            _ => 0,
+        }
+    }
+
+    pub(crate) fn n_channels(&self) -> u32 {
+        self.n_channels
+    }
+
+    pub(crate) fn mask(&self) -> u32 {
+        self.mask
+    }
+
+    /// Speaker for each channel. Channels beyond those named in the mask have no position.
+    pub(crate) fn speakers(&self) -> Vec<Option<Speaker>> {
+        let mut positions = SPEAKERS
+            .iter()
+            .filter(|speaker| self.mask & speaker.mask() != 0)
+            .map(|speaker| Some(*speaker));
+        (0..self.n_channels)
+            .map(|_| positions.next().flatten())
+            .collect()
+    }
+
+    /// Channel feeding `speaker`, if any.
+    pub(crate) fn channel_of(&self, speaker: Speaker) -> Option<usize> {
+        self.speakers()
+            .iter()
+            .position(|position| *position == Some(speaker))
+    }
+}
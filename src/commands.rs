@@ -0,0 +1,46 @@
+use std::io::BufRead;
+use std::path::Path;
+use std::thread::JoinHandle;
+
+use log::{error, info, warn};
+use serde::Serialize;
+
+use crate::stream_format;
+use crate::writer::meter_writer::MeterHandle;
+use crate::writer::replay_writer::ReplayHandle;
+
+/// Act on commands entered on stdin during a capture.
+///
+/// `replay [path]` saves the replay history and `levels` logs the meter's latest reading, when
+/// the respective writers are in use.
+pub(crate) fn listen_on_stdin<T>(
+    replay: Option<ReplayHandle<T>>,
+    meter: Option<MeterHandle>,
+) -> JoinHandle<()>
+    where
+        T: hound::Sample + stream_format::Sample + Serialize + Copy + Send + 'static,
+{
+    std::thread::spawn(move || {
+        let stdin = std::io::stdin();
+        for line in stdin.lock().lines() {
+            let line = match line {
+                Ok(line) => line,
+                Err(_) => break,
+            };
+            let mut command = line.split_whitespace();
+            match (command.next(), &replay, &meter) {
+                (Some("replay"), Some(replay), _) => {
+                    if let Err(e) = replay.dump(command.next().map(Path::new)) {
+                        error!("Failed to save replay - {}", e);
+                    }
+                }
+                (Some("levels"), _, Some(meter)) => match meter.describe() {
+                    Some(levels) => info!("Levels (RMS/peak/true peak dBFS): {}", levels),
+                    None => info!("No levels measured yet"),
+                },
+                (Some(other), _, _) => warn!("Unknown command `{}`", other),
+                (None, _, _) => {}
+            }
+        }
+    })
+}
@@ -0,0 +1,332 @@
+use std::str::FromStr;
+
+use anyhow::Error;
+
+use crate::channel_layout::{ChannelLayout, Speaker};
+use crate::dsp::ProcessingSample;
+
+/// -3dB, the ITU-R BS.775 gain for centre and surround channels folded into stereo.
+const MINUS_3DB: ProcessingSample = std::f64::consts::FRAC_1_SQRT_2;
+
+/// Linear mix from one set of channels to another.
+///
+/// Each output channel is a weighted sum of the input channels of the same frame. Custom
+/// matrices are written one output channel per row, rows separated by `;` and coefficients by
+/// `,`, e.g. `0.5,0.5` for an even stereo to mono mix or `1,0;1,0` to copy left to both.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct MixMatrix {
+    n_inputs: usize,
+    n_outputs: usize,
+    /// Row-major, one row of `n_inputs` coefficients per output channel.
+    coefficients: Vec<ProcessingSample>,
+}
+
+impl MixMatrix {
+    /// A user supplied matrix, given as one row of `n_inputs` coefficients per output channel.
+    pub(crate) fn new(
+        n_inputs: usize,
+        n_outputs: usize,
+        coefficients: Vec<ProcessingSample>,
+    ) -> Result<Self, Error> {
+        if n_inputs == 0 || n_outputs == 0 {
+            bail!("Mix matrix needs at least one input and one output channel");
+        }
+        if coefficients.len() != n_inputs * n_outputs {
+            bail!(
+                "Mix matrix from {} to {} channels needs {} coefficients, got {}",
+                n_inputs,
+                n_outputs,
+                n_inputs * n_outputs,
+                coefficients.len()
+            );
+        }
+        Ok(MixMatrix {
+            n_inputs,
+            n_outputs,
+            coefficients,
+        })
+    }
+
+    pub(crate) fn identity(n_channels: usize) -> Self {
+        let mut matrix = MixMatrix::zeroed(n_channels, n_channels);
+        for channel in 0..n_channels {
+            matrix.set(channel, channel, 1.0);
+        }
+        matrix
+    }
+
+    /// Standard mix between two layouts.
+    ///
+    /// Downmixes use the ITU-R BS.775 coefficients: centre and surrounds fold into the front
+    /// pair at -3dB and LFE is dropped. Mono is taken as the average of the stereo downmix.
+    /// Mono sources upmix by copying to the front speakers; other upmixes route matching
+    /// speakers and leave the rest silent.
+    pub(crate) fn between(from: &ChannelLayout, to: &ChannelLayout) -> Self {
+        if from == to {
+            return MixMatrix::identity(from.n_channels() as usize);
+        }
+        if from.n_channels() == 1 {
+            return MixMatrix::from_mono(to);
+        }
+        if to.n_channels() == 1 && from.n_channels() > 2 {
+            return MixMatrix::between(from, &ChannelLayout::STEREO)
+                .then(&MixMatrix::between(&ChannelLayout::STEREO, to));
+        }
+
+        let inputs = from.speakers();
+        let outputs = to.speakers();
+        let mut matrix = MixMatrix::zeroed(inputs.len(), outputs.len());
+        if outputs.len() == 1 {
+            for input in 0..inputs.len() {
+                matrix.set(0, input, 1.0 / inputs.len() as ProcessingSample);
+            }
+            return matrix;
+        }
+        for (input, speaker) in inputs.iter().enumerate() {
+            if let Some(speaker) = speaker {
+                for (output, gain) in fold(*speaker, &outputs) {
+                    matrix.set(output, input, gain);
+                }
+            }
+        }
+        matrix
+    }
+
+    fn from_mono(to: &ChannelLayout) -> Self {
+        let outputs = to.speakers();
+        let mut matrix = MixMatrix::zeroed(1, outputs.len());
+        let targets: Vec<usize> = match to.channel_of(Speaker::FrontCenter) {
+            Some(center) => vec![center],
+            None => [Speaker::FrontLeft, Speaker::FrontRight]
+                .iter()
+                .filter_map(|speaker| to.channel_of(*speaker))
+                .collect(),
+        };
+        if targets.is_empty() {
+            (0..outputs.len()).for_each(|output| matrix.set(output, 0, 1.0));
+        } else {
+            targets.iter().for_each(|output| matrix.set(*output, 0, 1.0));
+        }
+        matrix
+    }
+
+    fn zeroed(n_inputs: usize, n_outputs: usize) -> Self {
+        MixMatrix {
+            n_inputs,
+            n_outputs,
+            coefficients: vec![0.0; n_inputs * n_outputs],
+        }
+    }
+
+    fn set(&mut self, output: usize, input: usize, gain: ProcessingSample) {
+        self.coefficients[output * self.n_inputs + input] = gain;
+    }
+
+    pub(crate) fn n_inputs(&self) -> usize {
+        self.n_inputs
+    }
+
+    pub(crate) fn n_outputs(&self) -> usize {
+        self.n_outputs
+    }
+
+    /// The matrix equivalent to applying `self` and then `next`.
+    pub(crate) fn then(&self, next: &MixMatrix) -> MixMatrix {
+        assert_eq!(self.n_outputs, next.n_inputs);
+        let mut matrix = MixMatrix::zeroed(self.n_inputs, next.n_outputs);
+        for output in 0..next.n_outputs {
+            for input in 0..self.n_inputs {
+                let gain = (0..self.n_outputs)
+                    .map(|middle| {
+                        next.coefficients[output * next.n_inputs + middle]
+                            * self.coefficients[middle * self.n_inputs + input]
+                    })
+                    .sum();
+                matrix.set(output, input, gain);
+            }
+        }
+        matrix
+    }
+
+    /// Mix interleaved frames. Any trailing partial frame is ignored.
+    pub(crate) fn apply(&self, input: &[ProcessingSample]) -> Vec<ProcessingSample> {
+        let frames = input.len() / self.n_inputs;
+        let mut output = Vec::with_capacity(frames * self.n_outputs);
+        for frame in input.chunks_exact(self.n_inputs) {
+            for row in self.coefficients.chunks_exact(self.n_inputs) {
+                output.push(row.iter().zip(frame).map(|(gain, x)| gain * x).sum());
+            }
+        }
+        output
+    }
+}
+
+impl FromStr for MixMatrix {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let rows = s
+            .split(';')
+            .map(|row| {
+                row.split(',')
+                    .map(|gain| {
+                        gain.trim()
+                            .parse::<ProcessingSample>()
+                            .map_err(|_| anyhow!("Mix coefficient `{}` isn't a number", gain))
+                    })
+                    .collect::<Result<Vec<_>, Error>>()
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+        let n_inputs = rows[0].len();
+        if rows.iter().any(|row| row.len() != n_inputs) {
+            bail!("Every row of mix matrix `{}` needs the same number of coefficients", s);
+        }
+        MixMatrix::new(n_inputs, rows.len(), rows.concat())
+    }
+}
+
+/// Where `speaker` goes when downmixing into `outputs`, as (output channel, gain) pairs.
+fn fold(speaker: Speaker, outputs: &[Option<Speaker>]) -> Vec<(usize, ProcessingSample)> {
+    let find = |target: Speaker| outputs.iter().position(|output| *output == Some(target));
+    if let Some(output) = find(speaker) {
+        return vec![(output, 1.0)];
+    }
+    // The first of `targets` present in the output, with its gain.
+    let first_of = |targets: &[(Speaker, ProcessingSample)]| {
+        targets
+            .iter()
+            .find_map(|(target, gain)| find(*target).map(|output| vec![(output, *gain)]))
+            .unwrap_or_default()
+    };
+    let pair = |left: Speaker, right: Speaker, gain: ProcessingSample| {
+        [left, right]
+            .iter()
+            .filter_map(|speaker| find(*speaker))
+            .map(|output| (output, gain))
+            .collect::<Vec<_>>()
+    };
+    match speaker {
+        Speaker::LowFrequency => vec![],
+        Speaker::FrontCenter => pair(Speaker::FrontLeft, Speaker::FrontRight, MINUS_3DB),
+        Speaker::FrontLeft | Speaker::FrontRight => {
+            first_of(&[(Speaker::FrontCenter, MINUS_3DB)])
+        }
+        Speaker::FrontLeftOfCenter => {
+            first_of(&[(Speaker::FrontLeft, 1.0), (Speaker::FrontCenter, 1.0)])
+        }
+        Speaker::FrontRightOfCenter => {
+            first_of(&[(Speaker::FrontRight, 1.0), (Speaker::FrontCenter, 1.0)])
+        }
+        // Side and back surrounds stand in for each other before folding into the front.
+        Speaker::SideLeft => {
+            first_of(&[(Speaker::BackLeft, 1.0), (Speaker::FrontLeft, MINUS_3DB)])
+        }
+        Speaker::SideRight => {
+            first_of(&[(Speaker::BackRight, 1.0), (Speaker::FrontRight, MINUS_3DB)])
+        }
+        Speaker::BackLeft => {
+            first_of(&[(Speaker::SideLeft, 1.0), (Speaker::FrontLeft, MINUS_3DB)])
+        }
+        Speaker::BackRight => {
+            first_of(&[(Speaker::SideRight, 1.0), (Speaker::FrontRight, MINUS_3DB)])
+        }
+        Speaker::BackCenter => {
+            let back = pair(Speaker::BackLeft, Speaker::BackRight, MINUS_3DB);
+            if back.is_empty() {
+                pair(Speaker::FrontLeft, Speaker::FrontRight, 0.5)
+            } else {
+                back
+            }
+        }
+        // Height channels fold onto the speaker underneath them.
+        Speaker::TopFrontLeft => fold(Speaker::FrontLeft, outputs),
+        Speaker::TopFrontRight => fold(Speaker::FrontRight, outputs),
+        Speaker::TopFrontCenter | Speaker::TopCenter => fold(Speaker::FrontCenter, outputs),
+        Speaker::TopBackLeft => fold(Speaker::BackLeft, outputs),
+        Speaker::TopBackRight => fold(Speaker::BackRight, outputs),
+        Speaker::TopBackCenter => fold(Speaker::BackCenter, outputs),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const C: ProcessingSample = MINUS_3DB;
+
+    fn assert_matrix(matrix: &MixMatrix, rows: &[&[ProcessingSample]]) {
+        assert_eq!(matrix.n_outputs(), rows.len());
+        for (output, row) in rows.iter().enumerate() {
+            assert_eq!(matrix.n_inputs(), row.len());
+            for (input, expected) in row.iter().enumerate() {
+                let gain = matrix.coefficients[output * matrix.n_inputs + input];
+                assert!(
+                    (gain - expected).abs() < 1e-12,
+                    "output {} input {}: {} != {}",
+                    output,
+                    input,
+                    gain,
+                    expected
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn folds_7_1_into_stereo() {
+        // FL FR FC LFE BL BR SL SR
+        let matrix = MixMatrix::between(&ChannelLayout::new(8, 0x63f), &ChannelLayout::STEREO);
+        assert_matrix(
+            &matrix,
+            &[
+                &[1.0, 0.0, C, 0.0, C, 0.0, C, 0.0],
+                &[0.0, 1.0, C, 0.0, 0.0, C, 0.0, C],
+            ],
+        );
+    }
+
+    #[test]
+    fn folds_5_1_into_stereo() {
+        // FL FR FC LFE BL BR
+        let matrix = MixMatrix::between(&ChannelLayout::new(6, 0x3f), &ChannelLayout::STEREO);
+        assert_matrix(&matrix, &[&[1.0, 0.0, C, 0.0, C, 0.0], &[0.0, 1.0, C, 0.0, 0.0, C]]);
+    }
+
+    #[test]
+    fn averages_stereo_into_mono() {
+        let matrix = MixMatrix::between(&ChannelLayout::STEREO, &ChannelLayout::MONO);
+        assert_matrix(&matrix, &[&[0.5, 0.5]]);
+        assert_eq!(matrix.apply(&[1.0, 0.0, 0.25, 0.75]), vec![0.5, 0.5]);
+    }
+
+    #[test]
+    fn folds_5_1_into_mono_through_stereo() {
+        let matrix = MixMatrix::between(&ChannelLayout::new(6, 0x3f), &ChannelLayout::MONO);
+        assert_matrix(&matrix, &[&[0.5, 0.5, C, 0.0, C / 2.0, C / 2.0]]);
+    }
+
+    #[test]
+    fn composes_like_applying_in_turn() {
+        let first: MixMatrix = "1,2;0,1;3,0".parse().unwrap();
+        let second: MixMatrix = "1,0,1;0,2,0".parse().unwrap();
+        let both = first.then(&second);
+        assert_matrix(&both, &[&[4.0, 2.0], &[0.0, 2.0]]);
+
+        let input = [0.5, -1.0, 0.25, 2.0];
+        assert_eq!(both.apply(&input), second.apply(&first.apply(&input)));
+        assert_eq!(first.then(&MixMatrix::identity(3)), first);
+    }
+
+    #[test]
+    fn parses_custom_matrices() {
+        let matrix: MixMatrix = "1, 0; 0.5, 0.5".parse().unwrap();
+        assert_eq!(matrix, MixMatrix::new(2, 2, vec![1.0, 0.0, 0.5, 0.5]).unwrap());
+        assert_eq!(matrix.apply(&[1.0, 0.0]), vec![1.0, 0.5]);
+
+        for bad in &["", "1,x", "1,0;1", "1;;1"] {
+            assert!(bad.parse::<MixMatrix>().is_err(), "{:?} parsed", bad);
+        }
+        assert!(MixMatrix::new(2, 1, vec![1.0]).is_err());
+        assert!(MixMatrix::new(0, 1, vec![]).is_err());
+    }
+}
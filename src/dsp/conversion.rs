@@ -0,0 +1,197 @@
+use crate::stream_format::{Sample, SampleFormat};
+
+/// How values are placed onto the integer grid of the target format.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Rounding {
+    Truncate,
+    Nearest,
+}
+
+/// Noise added before rounding when the bit depth is lowered.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Dither {
+    None,
+    /// Triangular PDF noise of ±1 LSB, which decorrelates the rounding error from the signal.
+    Tpdf,
+    /// TPDF dither with first-order error feedback, moving the noise floor towards high
+    /// frequencies where it is less audible.
+    NoiseShaped,
+}
+
+/// Converts between sample formats, dithering when precision would otherwise be truncated.
+///
+/// Keeps per-channel state, so a converter should be used for a single interleaved stream.
+pub(crate) struct SampleConverter {
+    rounding: Rounding,
+    dither: Dither,
+    n_channels: usize,
+    /// Channel of the next sample, carried across calls to `convert`.
+    channel: usize,
+    /// Quantisation error of the previous sample on each channel, used for noise shaping.
+    error: Vec<f64>,
+    rng: u32,
+    clipped: u64,
+}
+
+impl SampleConverter {
+    pub(crate) fn new(n_channels: usize, rounding: Rounding, dither: Dither) -> Self {
+        assert!(n_channels > 0);
+        SampleConverter {
+            rounding,
+            dither,
+            n_channels,
+            channel: 0,
+            error: vec![0.0; n_channels],
+            rng: 0x9E37_79B9,
+            clipped: 0,
+        }
+    }
+
+    /// Round to nearest with TPDF dither, the usual choice when exporting to a lower bit depth.
+    pub(crate) fn for_export(n_channels: usize) -> Self {
+        SampleConverter::new(n_channels, Rounding::Nearest, Dither::Tpdf)
+    }
+
+    /// Number of samples that had to be clipped to fit the target format so far, not counting
+    /// those within a step of full scale.
+    pub(crate) fn clipped_samples(&self) -> u64 {
+        self.clipped
+    }
+
+    /// Convert interleaved samples from `S` to `T`.
+    pub(crate) fn convert<S, T>(&mut self, input: &[S]) -> Vec<T>
+        where
+            S: Sample,
+            T: Sample,
+    {
+        // Companding has its own non-uniform quantiser, so there's nothing to round or dither.
+        if T::FORMAT.is_float() || T::FORMAT.is_companded() || is_lossless(S::FORMAT, T::FORMAT)
+        {
+            self.channel = (self.channel + input.len()) % self.n_channels;
+            return input.iter().map(|sample| T::from(sample)).collect();
+        }
+        let bits = T::FORMAT.valid_bits() as u32;
+        input
+            .iter()
+            .map(|sample| {
+                let quantised = self.quantise(sample.to_f64(), bits);
+                T::from(&quantised)
+            })
+            .collect()
+    }
+
+    /// Quantise a sample in [-1, 1] to `bits`, returning it left-justified in an i32.
+    fn quantise(&mut self, sample: f64, bits: u32) -> i32 {
+        let scale = (1u64 << (bits - 1)) as f64;
+        let channel = self.channel;
+        self.channel = (self.channel + 1) % self.n_channels;
+
+        let mut value = sample * scale;
+        if self.dither == Dither::NoiseShaped {
+            value -= self.error[channel];
+        }
+        let dither = match self.dither {
+            Dither::None => 0.0,
+            Dither::Tpdf | Dither::NoiseShaped => self.random() - self.random(),
+        };
+        let rounded = match self.rounding {
+            Rounding::Truncate => (value + dither).floor(),
+            Rounding::Nearest => (value + dither).round(),
+        };
+        let clamped = rounded.max(-scale).min(scale - 1.0);
+        // Full scale +1.0 lands one step above the largest code, and dither can push a sample a
+        // step past either end, so only count what's out of range by more than that.
+        if value > scale || value < -scale - 1.0 {
+            self.clipped += 1;
+        }
+        // Only the rounding error is fed back; feeding back clipping would push the next samples
+        // further out of range.
+        self.error[channel] = rounded - value;
+        ((clamped as i64) << (32 - bits)) as i32
+    }
+
+    /// Uniform noise in [0, 1) from a xorshift generator; dither doesn't need anything stronger.
+    fn random(&mut self) -> f64 {
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 17;
+        self.rng ^= self.rng << 5;
+        self.rng as f64 / (u32::MAX as f64 + 1.0)
+    }
+}
+
+/// Whether every value of `from` can be represented exactly in `to`.
+pub(crate) fn is_lossless(from: SampleFormat, to: SampleFormat) -> bool {
+    match (from, to) {
+        (_, SampleFormat::F64) => true,
+        (SampleFormat::F64, _) => false,
+        (SampleFormat::F32, SampleFormat::F32) => true,
+        (SampleFormat::F32, _) => false,
+        (_, SampleFormat::F32) => from.linear_bits() <= 24,
+        _ if to.is_companded() => from == to,
+        _ => from.linear_bits() <= to.valid_bits(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STEP: f64 = 1.0 / 32768.0;
+
+    fn to_i16(converter: &mut SampleConverter, input: &[f64]) -> Vec<i16> {
+        converter.convert::<f64, i16>(input)
+    }
+
+    #[test]
+    fn rounds_to_nearest_or_down() {
+        let input = [1.6 * STEP, 1.4 * STEP, -1.4 * STEP, -1.6 * STEP];
+        let mut nearest = SampleConverter::new(1, Rounding::Nearest, Dither::None);
+        assert_eq!(to_i16(&mut nearest, &input), vec![2, 1, -1, -2]);
+        let mut truncate = SampleConverter::new(1, Rounding::Truncate, Dither::None);
+        assert_eq!(to_i16(&mut truncate, &input), vec![1, 1, -2, -2]);
+    }
+
+    #[test]
+    fn tpdf_dither_stays_within_a_step() {
+        let mut converter = SampleConverter::for_export(2);
+        let output = to_i16(&mut converter, &[10.3 * STEP; 20000]);
+        assert!(output.iter().all(|sample| (9..=11).contains(sample)));
+        assert!(output.contains(&9) && output.contains(&11));
+        let mean = output.iter().map(|sample| *sample as f64).sum::<f64>() / 20000.0;
+        assert!((mean - 10.3).abs() < 0.05, "mean {}", mean);
+        assert_eq!(converter.clipped_samples(), 0);
+    }
+
+    #[test]
+    fn noise_shaping_feeds_the_error_back() {
+        // With the error fed back the output's running sum tracks the input's, per channel.
+        let mut converter = SampleConverter::new(2, Rounding::Nearest, Dither::NoiseShaped);
+        let input: Vec<f64> = (0..10000)
+            .map(|i| if i % 2 == 0 { 0.3 * STEP } else { -7.7 * STEP })
+            .collect();
+        let output = to_i16(&mut converter, &input);
+        for (channel, expected) in [(0, 0.3), (1, -7.7)].iter() {
+            let mut total = 0.0;
+            for (i, sample) in output.iter().skip(*channel).step_by(2).enumerate() {
+                total += *sample as f64 - expected;
+                assert!(total.abs() < 2.0, "channel {} drifted by {} at {}", channel, total, i);
+            }
+        }
+    }
+
+    #[test]
+    fn counts_only_real_clipping() {
+        let mut converter = SampleConverter::new(1, Rounding::Nearest, Dither::None);
+        let full_scale = [1.0, 1.0 - STEP / 4.0, -1.0, -1.0 - STEP / 2.0];
+        assert_eq!(to_i16(&mut converter, &full_scale), vec![32767, 32767, -32768, -32768]);
+        assert_eq!(converter.clipped_samples(), 0);
+        let over = [1.0 + 2.0 * STEP, 1.5, -1.5];
+        assert_eq!(to_i16(&mut converter, &over), vec![32767, 32767, -32768]);
+        assert_eq!(converter.clipped_samples(), 3);
+
+        let mut dithered = SampleConverter::for_export(1);
+        to_i16(&mut dithered, &[1.0; 1000]);
+        to_i16(&mut dithered, &[-1.0; 1000]);
+        assert_eq!(dithered.clipped_samples(), 0);
+    }
+}
@@ -0,0 +1,141 @@
+//! ITU-T G.711 A-law and μ-law companding, after the reference implementation.
+
+const SIGN_BIT: u8 = 0x80;
+const QUANT_MASK: u8 = 0x0F;
+const SEG_MASK: u8 = 0x70;
+const SEG_SHIFT: u8 = 4;
+
+/// Upper bound of each A-law segment, on 13-bit magnitudes.
+const SEG_A_END: [i32; 8] = [0x1F, 0x3F, 0x7F, 0xFF, 0x1FF, 0x3FF, 0x7FF, 0xFFF];
+/// Upper bound of each μ-law segment, on biased 14-bit magnitudes.
+const SEG_U_END: [i32; 8] = [0x3F, 0x7F, 0xFF, 0x1FF, 0x3FF, 0x7FF, 0xFFF, 0x1FFF];
+
+const ULAW_BIAS: i32 = 0x84;
+const ULAW_CLIP: i32 = 8159;
+
+fn segment(value: i32, ends: &[i32; 8]) -> usize {
+    ends.iter().position(|end| value <= *end).unwrap_or(ends.len())
+}
+
+/// Compress a 16-bit linear sample to A-law. Only the top 13 bits are used.
+pub(crate) fn alaw_encode(sample: i16) -> u8 {
+    let mut pcm = sample as i32 >> 3;
+    let mask = if pcm >= 0 {
+        0xD5
+    } else {
+        pcm = -pcm - 1;
+        0x55
+    };
+    let seg = segment(pcm, &SEG_A_END);
+    if seg >= 8 {
+        return 0x7F ^ mask;
+    }
+    let mantissa = if seg < 2 { pcm >> 1 } else { pcm >> seg };
+    let code = ((seg as i32) << SEG_SHIFT) | (mantissa & QUANT_MASK as i32);
+    code as u8 ^ mask
+}
+
+/// Expand an A-law code to a 16-bit linear sample.
+pub(crate) fn alaw_decode(code: u8) -> i16 {
+    let code = code ^ 0x55;
+    let mut value = ((code & QUANT_MASK) as i32) << 4;
+    match (code & SEG_MASK) >> SEG_SHIFT {
+        0 => value += 8,
+        1 => value += 0x108,
+        seg => {
+            value += 0x108;
+            value <<= seg - 1;
+        }
+    }
+    if code & SIGN_BIT != 0 {
+        value as i16
+    } else {
+        -value as i16
+    }
+}
+
+/// Compress a 16-bit linear sample to μ-law. Only the top 14 bits are used.
+pub(crate) fn mulaw_encode(sample: i16) -> u8 {
+    let mut pcm = sample as i32 >> 2;
+    let mask = if pcm < 0 {
+        pcm = -pcm;
+        0x7F
+    } else {
+        0xFF
+    };
+    pcm = std::cmp::min(pcm, ULAW_CLIP) + (ULAW_BIAS >> 2);
+    let seg = segment(pcm, &SEG_U_END);
+    if seg >= 8 {
+        return 0x7F ^ mask;
+    }
+    let code = ((seg as i32) << SEG_SHIFT) | ((pcm >> (seg + 1)) & QUANT_MASK as i32);
+    code as u8 ^ mask
+}
+
+/// Expand a μ-law code to a 16-bit linear sample.
+pub(crate) fn mulaw_decode(code: u8) -> i16 {
+    let code = !code;
+    let mut value = (((code & QUANT_MASK) as i32) << 3) + ULAW_BIAS;
+    value <<= (code & SEG_MASK) >> SEG_SHIFT;
+    if code & SIGN_BIT != 0 {
+        (ULAW_BIAS - value) as i16
+    } else {
+        (value - ULAW_BIAS) as i16
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Codes and levels from the Sun reference implementation.
+
+    #[test]
+    fn encodes_reference_samples() {
+        let cases: [(i16, u8, u8); 9] = [
+            (0, 0xD5, 0xFF),
+            (100, 0xD3, 0xF2),
+            (-100, 0x53, 0x72),
+            (1000, 0xFA, 0xCE),
+            (-1000, 0x7A, 0x4E),
+            (12345, 0xBD, 0x97),
+            (-12345, 0x3D, 0x17),
+            (i16::MAX, 0xAA, 0x80),
+            (i16::MIN, 0x2A, 0x00),
+        ];
+        for &(sample, alaw, mulaw) in cases.iter() {
+            assert_eq!(alaw_encode(sample), alaw, "A-law of {}", sample);
+            assert_eq!(mulaw_encode(sample), mulaw, "μ-law of {}", sample);
+        }
+    }
+
+    #[test]
+    fn decodes_reference_codes() {
+        let cases: [(u8, i16, i16); 10] = [
+            (0xAA, 32256, 5372),
+            (0x2A, -32256, -5372),
+            (0xD5, 8, 716),
+            (0x55, -8, -716),
+            (0x80, 5504, 32124),
+            (0x00, -5504, -32124),
+            (0xFF, 848, 0),
+            (0x7F, -848, 0),
+            (0xFA, 1008, 40),
+            (0xCE, 440, 988),
+        ];
+        for &(code, alaw, mulaw) in cases.iter() {
+            assert_eq!(alaw_decode(code), alaw, "A-law code {:#04x}", code);
+            assert_eq!(mulaw_decode(code), mulaw, "μ-law code {:#04x}", code);
+        }
+    }
+
+    #[test]
+    fn every_code_survives_decoding_and_encoding() {
+        for code in 0..=255u8 {
+            assert_eq!(alaw_encode(alaw_decode(code)), code, "A-law code {:#04x}", code);
+            // μ-law has two zeros; the negative one comes back positive.
+            let expected = if code == 0x7F { 0xFF } else { code };
+            assert_eq!(mulaw_encode(mulaw_decode(code)), expected, "μ-law code {:#04x}", code);
+        }
+    }
+}
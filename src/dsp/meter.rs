@@ -0,0 +1,129 @@
+use serde::Serialize;
+
+use crate::dsp::ProcessingSample;
+use crate::stream_format::Sample;
+
+/// Taps per phase of the true-peak interpolator.
+const TAPS: usize = 12;
+
+/// The 4× oversampling interpolator of ITU-R BS.1770-4 Annex 2, one row per phase.
+const TRUE_PEAK_PHASES: [[ProcessingSample; TAPS]; 4] = [
+    [
+        0.0017089843750, 0.0109863281250, -0.0196533203125, 0.0332031250000, -0.0594482421875,
+        0.1373291015625, 0.9721679687500, -0.1022949218750, 0.0476074218750, -0.0266113281250,
+        0.0148925781250, -0.0083007812500,
+    ],
+    [
+        -0.0291748046875, 0.0292968750000, -0.0517578125000, 0.0891113281250, -0.1665039062500,
+        0.4650878906250, 0.7797851562500, -0.2003173828125, 0.1015625000000, -0.0582275390625,
+        0.0330810546875, -0.0189208984375,
+    ],
+    [
+        -0.0189208984375, 0.0330810546875, -0.0582275390625, 0.1015625000000, -0.2003173828125,
+        0.7797851562500, 0.4650878906250, -0.1665039062500, 0.0891113281250, -0.0517578125000,
+        0.0292968750000, -0.0291748046875,
+    ],
+    [
+        -0.0083007812500, 0.0148925781250, -0.0266113281250, 0.0476074218750, -0.1022949218750,
+        0.9721679687500, 0.1373291015625, -0.0594482421875, 0.0332031250000, -0.0196533203125,
+        0.0109863281250, 0.0017089843750,
+    ],
+];
+
+/// Levels of one channel over a metering window, in dBFS. Digital silence is `-inf`.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+pub(crate) struct ChannelLevels {
+    pub(crate) rms: f32,
+    /// Largest sample.
+    pub(crate) peak: f32,
+    /// Largest value between samples as well, as a DAC would reconstruct it.
+    pub(crate) true_peak: f32,
+}
+
+/// Running totals for one channel.
+#[derive(Clone)]
+struct ChannelState {
+    sum_of_squares: ProcessingSample,
+    peak: ProcessingSample,
+    true_peak: ProcessingSample,
+    /// The last `TAPS` samples, newest first.
+    history: [ProcessingSample; TAPS],
+}
+
+/// Per-channel RMS, sample peak and 4× oversampled true peak over fixed length windows.
+///
+/// Samples can be pushed in packets of any size; readings come out once per window.
+pub(crate) struct Meter {
+    n_channels: usize,
+    window_frames: usize,
+    frames: usize,
+    channels: Vec<ChannelState>,
+}
+
+impl Meter {
+    pub(crate) fn new(n_channels: usize, window_frames: usize) -> Self {
+        assert!(n_channels > 0 && window_frames > 0);
+        let state = ChannelState {
+            sum_of_squares: 0.0,
+            peak: 0.0,
+            true_peak: 0.0,
+            history: [0.0; TAPS],
+        };
+        Meter {
+            n_channels,
+            window_frames,
+            frames: 0,
+            channels: vec![state; n_channels],
+        }
+    }
+
+    /// Meter interleaved samples, returning the levels of every window they complete.
+    pub(crate) fn push<S: Sample>(&mut self, samples: &[S]) -> Vec<Vec<ChannelLevels>> {
+        let mut readings = vec![];
+        for frame in samples.chunks_exact(self.n_channels) {
+            for (state, sample) in self.channels.iter_mut().zip(frame) {
+                let x = sample.to_f64();
+                state.sum_of_squares += x * x;
+                state.peak = state.peak.max(x.abs());
+                state.history.rotate_right(1);
+                state.history[0] = x;
+                for phase in TRUE_PEAK_PHASES.iter() {
+                    let y: ProcessingSample =
+                        phase.iter().zip(state.history.iter()).map(|(c, x)| c * x).sum();
+                    state.true_peak = state.true_peak.max(y.abs());
+                }
+            }
+            self.frames += 1;
+            if self.frames == self.window_frames {
+                readings.push(self.take_levels());
+            }
+        }
+        readings
+    }
+
+    /// Levels of the window so far, starting a new one.
+    fn take_levels(&mut self) -> Vec<ChannelLevels> {
+        let frames = self.frames as ProcessingSample;
+        self.frames = 0;
+        self.channels
+            .iter_mut()
+            .map(|state| {
+                let levels = ChannelLevels {
+                    rms: (10.0 * (state.sum_of_squares / frames).log10()) as f32,
+                    peak: dbfs(state.peak),
+                    // The interpolator doesn't pass samples through exactly, so never report a
+                    // true peak below the sample peak.
+                    true_peak: dbfs(state.true_peak.max(state.peak)),
+                };
+                state.sum_of_squares = 0.0;
+                state.peak = 0.0;
+                state.true_peak = 0.0;
+                levels
+            })
+            .collect()
+    }
+}
+
+fn dbfs(amplitude: ProcessingSample) -> f32 {
+    (20.0 * amplitude.log10()) as f32
+}
@@ -0,0 +1,22 @@
+use crate::stream_format::Sample;
+
+pub(crate) mod channel_mix;
+pub(crate) mod conversion;
+pub(crate) mod g711;
+pub(crate) mod meter;
+pub(crate) mod pipeline;
+pub(crate) mod resampler;
+
+/// Sample type used inside the processing pipeline, whatever format the device delivers.
+///
+/// Long chains of filters accumulate rounding error quickly in f32, so everything between the
+/// capture client and the final conversion to a writer's format runs at double precision.
+pub(crate) type ProcessingSample = f64;
+
+/// Convert interleaved samples into the processing format.
+pub(crate) fn to_processing<S>(input: &[S]) -> Vec<ProcessingSample>
+    where
+        S: Sample,
+{
+    input.iter().map(|sample| sample.to_f64()).collect()
+}
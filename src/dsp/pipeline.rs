@@ -0,0 +1,176 @@
+use anyhow::Error;
+
+use crate::dsp::channel_mix::MixMatrix;
+use crate::dsp::conversion::{Dither, Rounding, SampleConverter};
+use crate::dsp::resampler::{Resampler, ResamplerQuality};
+use crate::dsp::{to_processing, ProcessingSample};
+use crate::stream_format::{Sample, StreamFormat};
+
+/// Converts a captured stream into another sample format, rate and channel layout.
+///
+/// Samples are lifted to the processing format, mixed, resampled and finally quantised, with
+/// mixing done on whichever side of the resampler has fewer channels.
+pub(crate) struct FormatConverter {
+    source: StreamFormat,
+    target: StreamFormat,
+    mix: Option<MixMatrix>,
+    resampler: Option<Resampler>,
+    quantiser: SampleConverter,
+}
+
+impl FormatConverter {
+    pub(crate) fn new(source: StreamFormat, target: StreamFormat) -> Self {
+        FormatConverter::with_options(source, target, ResamplerQuality::Medium, Dither::Tpdf)
+    }
+
+    /// Convert with a custom `mix`, which must map the source channels to the target's.
+    pub(crate) fn with_mix(
+        source: StreamFormat,
+        target: StreamFormat,
+        mix: MixMatrix,
+    ) -> Result<Self, Error> {
+        if mix.n_inputs() != source.n_channels as usize
+            || mix.n_outputs() != target.n_channels as usize
+        {
+            bail!(
+                "Mix matrix is from {} to {} channels, but the conversion is from {} to {}",
+                mix.n_inputs(),
+                mix.n_outputs(),
+                source.n_channels,
+                target.n_channels
+            );
+        }
+        let mut converter = FormatConverter::new(source, target);
+        converter.mix = Some(mix);
+        Ok(converter)
+    }
+
+    pub(crate) fn with_options(
+        source: StreamFormat,
+        target: StreamFormat,
+        quality: ResamplerQuality,
+        dither: Dither,
+    ) -> Self {
+        let mix = if source.channel_layout() == target.channel_layout() {
+            None
+        } else {
+            Some(MixMatrix::between(
+                &source.channel_layout(),
+                &target.channel_layout(),
+            ))
+        };
+        let resampler = if source.n_sample_per_sec == target.n_sample_per_sec {
+            None
+        } else {
+            Some(Resampler::new(
+                source.n_sample_per_sec,
+                target.n_sample_per_sec,
+                std::cmp::min(source.n_channels, target.n_channels) as usize,
+                quality,
+            ))
+        };
+        FormatConverter {
+            source,
+            target,
+            mix,
+            resampler,
+            quantiser: SampleConverter::new(target.n_channels as usize, Rounding::Nearest, dither),
+        }
+    }
+
+    pub(crate) fn source(&self) -> StreamFormat {
+        self.source
+    }
+
+    pub(crate) fn target(&self) -> StreamFormat {
+        self.target
+    }
+
+    /// Samples clipped while quantising to the target format so far.
+    pub(crate) fn clipped_samples(&self) -> u64 {
+        self.quantiser.clipped_samples()
+    }
+
+    /// Convert interleaved samples in the source format.
+    pub(crate) fn convert<S, T>(&mut self, input: &[S]) -> Vec<T>
+        where
+            S: Sample,
+            T: Sample,
+    {
+        if self.mix.is_none() && self.resampler.is_none() {
+            return self.quantiser.convert(input);
+        }
+        let processed = self.process(to_processing(input), false);
+        self.quantiser.convert(&processed)
+    }
+
+    /// Finish the stream, returning the samples the resampler was still holding back.
+    pub(crate) fn flush<T>(&mut self) -> Vec<T>
+        where
+            T: Sample,
+    {
+        if self.resampler.is_none() {
+            return vec![];
+        }
+        let processed = self.process(vec![], true);
+        self.quantiser.convert(&processed)
+    }
+
+    fn process(&mut self, input: Vec<ProcessingSample>, flush: bool) -> Vec<ProcessingSample> {
+        let downmix = self.target.n_channels < self.source.n_channels;
+        let mut samples = input;
+        if downmix {
+            samples = self.mix(samples);
+        }
+        if let Some(ref mut resampler) = self.resampler {
+            samples = if flush {
+                resampler.flush()
+            } else {
+                resampler.process(&samples)
+            };
+        }
+        if !downmix {
+            samples = self.mix(samples);
+        }
+        samples
+    }
+
+    fn mix(&self, samples: Vec<ProcessingSample>) -> Vec<ProcessingSample> {
+        match self.mix {
+            Some(ref mix) => mix.apply(&samples),
+            None => samples,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::channel_layout::ChannelLayout;
+    use crate::stream_format::SampleFormat;
+
+    use super::*;
+
+    #[test]
+    fn mixes_with_a_custom_matrix() {
+        let source = StreamFormat::new(SampleFormat::F32, 48000, ChannelLayout::STEREO);
+        let target = StreamFormat::new(SampleFormat::F32, 48000, ChannelLayout::MONO);
+        let left_only = "1,0".parse().unwrap();
+        let mut converter = FormatConverter::with_mix(source, target, left_only).unwrap();
+        let mono: Vec<f32> = converter.convert(&[0.5f32, -0.5, 0.25, 1.0]);
+        assert_eq!(mono, vec![0.5, 0.25]);
+
+        let mut standard = FormatConverter::new(source, target);
+        let mono: Vec<f32> = standard.convert(&[0.5f32, -0.5, 0.25, 1.0]);
+        assert_eq!(mono, vec![0.0, 0.625]);
+    }
+
+    #[test]
+    fn rejects_matrices_of_the_wrong_size() {
+        let source = StreamFormat::new(SampleFormat::F32, 48000, ChannelLayout::STEREO);
+        let target = StreamFormat::new(SampleFormat::F32, 16000, ChannelLayout::MONO);
+        for bad in &["1", "1,0;0,1", "1,0,0"] {
+            let mix = bad.parse().unwrap();
+            assert!(FormatConverter::with_mix(source, target, mix).is_err(), "{} accepted", bad);
+        }
+    }
+}
@@ -0,0 +1,290 @@
+use std::f64::consts::PI;
+
+use crate::dsp::ProcessingSample;
+
+/// Largest number of filter phases kept in memory. Rate pairs with a larger interpolation
+/// factor, e.g. 44100Hz to 47999Hz, use the stored phase at or just before the exact one.
+const MAX_PHASES: u64 = 1024;
+
+/// Trade off between CPU use and how much aliasing/passband roll off is tolerated.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum ResamplerQuality {
+    Low,
+    Medium,
+    High,
+}
+
+impl ResamplerQuality {
+    /// Half the filter length, in input samples, when not decimating.
+    fn half_taps(&self) -> usize {
+        match *self {
+            ResamplerQuality::Low => 8,
+            ResamplerQuality::Medium => 16,
+            ResamplerQuality::High => 32,
+        }
+    }
+
+    /// Kaiser window beta, setting the stopband attenuation.
+    fn beta(&self) -> f64 {
+        match *self {
+            ResamplerQuality::Low => 6.0,
+            ResamplerQuality::Medium => 8.6,
+            ResamplerQuality::High => 10.5,
+        }
+    }
+
+    /// Cutoff as a fraction of the lower Nyquist frequency.
+    fn rolloff(&self) -> f64 {
+        match *self {
+            ResamplerQuality::Low => 0.85,
+            ResamplerQuality::Medium => 0.91,
+            ResamplerQuality::High => 0.945,
+        }
+    }
+}
+
+/// Streaming polyphase windowed-sinc sample rate converter.
+///
+/// Converts by the exact rational ratio between the two rates. Input can be pushed in packets
+/// of any size; filter history is carried between calls so the output is the same as
+/// converting the whole stream at once.
+pub(crate) struct Resampler {
+    n_channels: usize,
+    up: u64,
+    down: u64,
+    half_taps: usize,
+    phases: u64,
+    /// `phases` rows of `2 * half_taps` coefficients.
+    filter: Vec<ProcessingSample>,
+    /// Interleaved input still needed by upcoming outputs.
+    buffer: Vec<ProcessingSample>,
+    /// Stream index of the first frame in `buffer`. Negative indices are the silence before
+    /// the stream starts.
+    buffer_start: i64,
+    input_frames: u64,
+    output_frames: u64,
+}
+
+impl Resampler {
+    pub(crate) fn new(
+        from_rate: u32,
+        to_rate: u32,
+        n_channels: usize,
+        quality: ResamplerQuality,
+    ) -> Self {
+        assert!(from_rate > 0 && to_rate > 0 && n_channels > 0);
+        let divisor = gcd(from_rate as u64, to_rate as u64);
+        let up = to_rate as u64 / divisor;
+        let down = from_rate as u64 / divisor;
+
+        // When decimating the cutoff drops, so the filter has to be longer to keep the same
+        // transition band relative to the output rate.
+        let decimation = ((down + up - 1) / up) as usize;
+        let half_taps = quality.half_taps() * decimation;
+        let phases = std::cmp::min(up, MAX_PHASES);
+        let cutoff = 0.5 * quality.rolloff() * (up as f64 / down as f64).min(1.0);
+        let filter = design_filter(half_taps, phases, cutoff, quality.beta());
+
+        Resampler {
+            n_channels,
+            up,
+            down,
+            half_taps,
+            phases,
+            filter,
+            buffer: vec![0.0; half_taps * n_channels],
+            buffer_start: -(half_taps as i64),
+            input_frames: 0,
+            output_frames: 0,
+        }
+    }
+
+    /// Output frames per input frame.
+    pub(crate) fn ratio(&self) -> f64 {
+        self.up as f64 / self.down as f64
+    }
+
+    /// Resample interleaved frames, returning every output frame that can be computed so far.
+    pub(crate) fn process(&mut self, input: &[ProcessingSample]) -> Vec<ProcessingSample> {
+        if self.up == self.down {
+            return input.to_vec();
+        }
+        let frames = input.len() / self.n_channels;
+        self.buffer.extend_from_slice(&input[..frames * self.n_channels]);
+        self.input_frames += frames as u64;
+        self.produce()
+    }
+
+    /// Finish the stream, returning the outputs still held back waiting for future input.
+    pub(crate) fn flush(&mut self) -> Vec<ProcessingSample> {
+        if self.up == self.down {
+            return vec![];
+        }
+        let padding = vec![0.0; (self.half_taps + 1) * self.n_channels];
+        self.buffer.extend_from_slice(&padding);
+        self.produce()
+    }
+
+    fn produce(&mut self) -> Vec<ProcessingSample> {
+        let taps = 2 * self.half_taps;
+        let buffered_frames = (self.buffer.len() / self.n_channels) as i64;
+        let last_frame = self.buffer_start + buffered_frames - 1;
+        // Outputs past the end of the input only exist to pad the final partial frame.
+        let expected = (self.input_frames * self.up + self.down - 1) / self.down;
+
+        let mut output = vec![];
+        while self.output_frames < expected {
+            let position = self.output_frames * self.down;
+            let base = (position / self.up) as i64;
+            let newest = base + self.half_taps as i64;
+            if newest > last_frame {
+                break;
+            }
+            let phase = (position % self.up) * self.phases / self.up;
+            let coefficients = &self.filter[phase as usize * taps..(phase as usize + 1) * taps];
+            let newest_offset = (newest - self.buffer_start) as usize;
+            for channel in 0..self.n_channels {
+                let sample = coefficients
+                    .iter()
+                    .enumerate()
+                    .map(|(k, coefficient)| {
+                        coefficient * self.buffer[(newest_offset - k) * self.n_channels + channel]
+                    })
+                    .sum();
+                output.push(sample);
+            }
+            self.output_frames += 1;
+        }
+
+        // Drop frames that no future output reaches.
+        let next_base = (self.output_frames * self.down / self.up) as i64;
+        let oldest_needed = next_base + self.half_taps as i64 + 1 - taps as i64;
+        let unused = std::cmp::max(
+            0,
+            std::cmp::min(oldest_needed, last_frame + 1) - self.buffer_start,
+        );
+        self.buffer.drain(..unused as usize * self.n_channels);
+        self.buffer_start += unused;
+        output
+    }
+}
+
+fn gcd(a: u64, b: u64) -> u64 {
+    if b == 0 {
+        a
+    } else {
+        gcd(b, a % b)
+    }
+}
+
+/// Kaiser windowed sinc, split into `phases` fractional delays of `2 * half_taps` taps.
+///
+/// `cutoff` is in cycles per input sample. Each phase is normalised to unity gain at DC.
+fn design_filter(half_taps: usize, phases: u64, cutoff: f64, beta: f64) -> Vec<ProcessingSample> {
+    let taps = 2 * half_taps;
+    let mut filter = Vec::with_capacity(phases as usize * taps);
+    for phase in 0..phases {
+        let fraction = phase as f64 / phases as f64;
+        let start = filter.len();
+        for k in 0..taps {
+            let x = k as f64 + fraction - half_taps as f64;
+            let position = (1.0 - (x / half_taps as f64).powi(2)).max(0.0);
+            let window = bessel_i0(beta * position.sqrt()) / bessel_i0(beta);
+            filter.push(2.0 * cutoff * sinc(2.0 * cutoff * x) * window);
+        }
+        let gain: f64 = filter[start..].iter().sum();
+        filter[start..].iter_mut().for_each(|coefficient| *coefficient /= gain);
+    }
+    filter
+}
+
+fn sinc(x: f64) -> f64 {
+    if x == 0.0 {
+        1.0
+    } else {
+        (PI * x).sin() / (PI * x)
+    }
+}
+
+/// Zeroth order modified Bessel function of the first kind, by its power series.
+fn bessel_i0(x: f64) -> f64 {
+    let mut sum = 1.0;
+    let mut term = 1.0;
+    let mut k = 1.0;
+    while term > sum * 1e-12 {
+        term *= (x / (2.0 * k)).powi(2);
+        sum += term;
+        k += 1.0;
+    }
+    sum
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tone(frequency: f64, rate: u32, n_frames: usize) -> Vec<ProcessingSample> {
+        (0..n_frames)
+            .map(|n| (2.0 * PI * frequency * n as f64 / rate as f64).sin())
+            .collect()
+    }
+
+    /// Resample `input` in packets of varying numbers of frames, then flush.
+    fn stream(resampler: &mut Resampler, input: &[ProcessingSample]) -> Vec<ProcessingSample> {
+        let mut output = vec![];
+        let mut rest = input;
+        for size in [1, 441, 1000, 7, 4800].iter().cycle() {
+            if rest.is_empty() {
+                break;
+            }
+            let size = size * resampler.n_channels;
+            let (packet, remaining) = rest.split_at(std::cmp::min(size, rest.len()));
+            output.extend(resampler.process(packet));
+            rest = remaining;
+        }
+        output.extend(resampler.flush());
+        output
+    }
+
+    fn rms(samples: &[ProcessingSample]) -> f64 {
+        (samples.iter().map(|x| x * x).sum::<f64>() / samples.len() as f64).sqrt()
+    }
+
+    #[test]
+    fn streams_to_the_exact_output_length() {
+        for &(from, to, n_channels) in &[(48000, 16000, 1), (44100, 48000, 2), (8000, 48000, 1)] {
+            let input = vec![0.25; from as usize * n_channels];
+            let mut resampler = Resampler::new(from, to, n_channels, ResamplerQuality::Medium);
+            let output = stream(&mut resampler, &input);
+            assert_eq!(output.len(), to as usize * n_channels, "{} to {}", from, to);
+
+            let mut whole = Resampler::new(from, to, n_channels, ResamplerQuality::Medium);
+            let mut at_once = whole.process(&input);
+            at_once.extend(whole.flush());
+            assert_eq!(output, at_once);
+        }
+    }
+
+    #[test]
+    fn passes_dc_at_unity_gain() {
+        for &(from, to) in &[(48000, 16000), (44100, 48000), (44100, 47999)] {
+            let mut resampler = Resampler::new(from, to, 1, ResamplerQuality::Medium);
+            let output = stream(&mut resampler, &vec![0.5; from as usize / 10]);
+            // Skip the filter's ramp in from the silence before the stream and out at the end.
+            let settled = &output[100..output.len() - 100];
+            assert!(settled.iter().all(|x| (x - 0.5).abs() < 1e-6), "{} to {}", from, to);
+        }
+    }
+
+    #[test]
+    fn rejects_tones_above_the_new_nyquist() {
+        let mut resampler = Resampler::new(48000, 16000, 1, ResamplerQuality::Medium);
+        let output = stream(&mut resampler, &tone(12000.0, 48000, 48000));
+        assert!(rms(&output[100..output.len() - 100]) < 1e-3);
+
+        let mut resampler = Resampler::new(48000, 16000, 1, ResamplerQuality::Medium);
+        let output = stream(&mut resampler, &tone(1000.0, 48000, 48000));
+        let level = rms(&output[100..output.len() - 100]);
+        assert!((level - std::f64::consts::FRAC_1_SQRT_2).abs() < 0.01);
+    }
+}
@@ -0,0 +1,116 @@
+/// MSB-first bit packer for FLAC frames.
+pub(crate) struct BitWriter {
+    bytes: Vec<u8>,
+    /// Bits not yet written out, in the low `n_bits` bits.
+    accumulator: u64,
+    n_bits: u32,
+}
+
+impl BitWriter {
+    pub(crate) fn new() -> Self {
+        BitWriter {
+            bytes: Vec::new(),
+            accumulator: 0,
+            n_bits: 0,
+        }
+    }
+
+    pub(crate) fn len_bits(&self) -> usize {
+        self.bytes.len() * 8 + self.n_bits as usize
+    }
+
+    /// Write the low `bits` bits of `value`.
+    pub(crate) fn write(&mut self, value: u64, bits: u32) {
+        debug_assert!(bits <= 32);
+        if bits == 0 {
+            return;
+        }
+        self.accumulator = (self.accumulator << bits) | (value & ((1u64 << bits) - 1));
+        self.n_bits += bits;
+        while self.n_bits >= 8 {
+            self.n_bits -= 8;
+            self.bytes.push((self.accumulator >> self.n_bits) as u8);
+        }
+        self.accumulator &= (1u64 << self.n_bits) - 1;
+    }
+
+    /// Write `value` as a two's complement number of `bits` bits.
+    pub(crate) fn write_signed(&mut self, value: i64, bits: u32) {
+        if bits > 32 {
+            self.write((value >> 32) as u64, bits - 32);
+            self.write(value as u64, 32);
+        } else {
+            self.write(value as u64, bits);
+        }
+    }
+
+    /// `count` zeros followed by a one.
+    pub(crate) fn write_unary(&mut self, mut count: u64) {
+        while count >= 32 {
+            self.write(0, 32);
+            count -= 32;
+        }
+        self.write(1, count as u32 + 1);
+    }
+
+    /// Rice code of a signed residual with parameter `k`.
+    pub(crate) fn write_rice(&mut self, value: i64, k: u32) {
+        let folded = zigzag(value);
+        self.write_unary(folded >> k);
+        self.write(folded, k);
+    }
+
+    /// Write everything written to `other`, without padding it to a byte boundary.
+    pub(crate) fn append(&mut self, other: &BitWriter) {
+        if self.n_bits == 0 {
+            self.bytes.extend_from_slice(&other.bytes);
+        } else {
+            other.bytes.iter().for_each(|byte| self.write(*byte as u64, 8));
+        }
+        self.write(other.accumulator, other.n_bits);
+    }
+
+    /// Pad with zeros up to the next byte boundary.
+    pub(crate) fn align(&mut self) {
+        if self.n_bits > 0 {
+            self.write(0, 8 - self.n_bits);
+        }
+    }
+
+    /// The written bytes, padded to a whole byte.
+    pub(crate) fn into_bytes(mut self) -> Vec<u8> {
+        self.align();
+        self.bytes
+    }
+}
+
+/// Map signed values onto unsigned ones, interleaving negatives: 0, -1, 1, -2, ...
+pub(crate) fn zigzag(value: i64) -> u64 {
+    ((value << 1) ^ (value >> 63)) as u64
+}
+
+/// CRC-8 with polynomial x^8 + x^2 + x + 1, protecting frame headers.
+pub(crate) fn crc8(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |crc, byte| {
+        (0..8).fold(crc ^ byte, |crc, _| {
+            if crc & 0x80 != 0 {
+                (crc << 1) ^ 0x07
+            } else {
+                crc << 1
+            }
+        })
+    })
+}
+
+/// CRC-16 with polynomial x^16 + x^15 + x^2 + 1, protecting whole frames.
+pub(crate) fn crc16(data: &[u8]) -> u16 {
+    data.iter().fold(0u16, |crc, byte| {
+        (0..8).fold(crc ^ ((*byte as u16) << 8), |crc, _| {
+            if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x8005
+            } else {
+                crc << 1
+            }
+        })
+    })
+}
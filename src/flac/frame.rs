@@ -0,0 +1,365 @@
+use crate::flac::bit_writer::{crc16, crc8, zigzag, BitWriter};
+
+/// Highest order of the fixed polynomial predictors.
+const MAX_FIXED_ORDER: usize = 4;
+/// Largest Rice parameter with the 4-bit parameter encoding; larger ones need 5 bits.
+const MAX_RICE_PARAM_4BIT: u32 = 14;
+const MAX_RICE_PARAM_5BIT: u32 = 30;
+
+/// Encoder settings for a compression level, 0 (fastest) to 8 (smallest).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) struct CompressionLevel {
+    pub(crate) level: u8,
+    pub(crate) block_size: usize,
+    max_fixed_order: usize,
+    max_partition_order: u32,
+    stereo_decorrelation: bool,
+}
+
+impl CompressionLevel {
+    pub(crate) const DEFAULT: u8 = 5;
+
+    pub(crate) fn new(level: u8) -> Self {
+        let level = level.min(8);
+        CompressionLevel {
+            level,
+            block_size: if level <= 2 { 1152 } else { 4096 },
+            max_fixed_order: match level {
+                0 => 1,
+                1 | 2 => 2,
+                3 | 4 => 3,
+                _ => MAX_FIXED_ORDER,
+            },
+            max_partition_order: match level {
+                0..=2 => 3,
+                3..=5 => 5,
+                _ => 6,
+            },
+            stereo_decorrelation: level > 0,
+        }
+    }
+}
+
+impl Default for CompressionLevel {
+    fn default() -> Self {
+        CompressionLevel::new(CompressionLevel::DEFAULT)
+    }
+}
+
+/// How a stereo pair is stored, FLAC's channel assignment.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Stereo {
+    Independent,
+    LeftSide,
+    SideRight,
+    MidSide,
+}
+
+/// Encode one frame from `channels`, each holding the same number of right-justified samples.
+pub(crate) fn encode_frame(
+    channels: &[Vec<i64>],
+    bits_per_sample: u32,
+    sample_rate: u32,
+    frame_number: u64,
+    level: &CompressionLevel,
+) -> Vec<u8> {
+    let block_size = channels[0].len();
+    let (assignment, subframes) = if channels.len() == 2
+        && level.stereo_decorrelation
+        && bits_per_sample < 32
+    {
+        encode_stereo(&channels[0], &channels[1], bits_per_sample, level)
+    } else {
+        let subframes = channels
+            .iter()
+            .map(|channel| encode_subframe(channel, bits_per_sample, level))
+            .collect();
+        (Stereo::Independent, subframes)
+    };
+
+    let mut header = BitWriter::new();
+    header.write(0b1111_1111_1111_1000, 16);
+    let (block_size_code, block_size_extra) = block_size_code(block_size);
+    header.write(block_size_code, 4);
+    let (rate_code, rate_extra) = sample_rate_code(sample_rate);
+    header.write(rate_code, 4);
+    header.write(
+        match assignment {
+            Stereo::Independent => channels.len() as u64 - 1,
+            Stereo::LeftSide => 8,
+            Stereo::SideRight => 9,
+            Stereo::MidSide => 10,
+        },
+        4,
+    );
+    header.write(sample_size_code(bits_per_sample), 3);
+    header.write(0, 1);
+    write_utf8(&mut header, frame_number);
+    if let Some((value, bits)) = block_size_extra {
+        header.write(value, bits);
+    }
+    if let Some((value, bits)) = rate_extra {
+        header.write(value, bits);
+    }
+    let header = header.into_bytes();
+    let mut frame = BitWriter::new();
+    header.iter().for_each(|byte| frame.write(*byte as u64, 8));
+    frame.write(crc8(&header) as u64, 8);
+    for subframe in &subframes {
+        frame.append(subframe);
+    }
+    let mut frame = frame.into_bytes();
+    let crc = crc16(&frame);
+    frame.extend_from_slice(&crc.to_be_bytes());
+    frame
+}
+
+/// Try each channel assignment, keeping the smallest.
+fn encode_stereo(
+    left: &[i64],
+    right: &[i64],
+    bits_per_sample: u32,
+    level: &CompressionLevel,
+) -> (Stereo, Vec<BitWriter>) {
+    let side: Vec<i64> = left.iter().zip(right).map(|(l, r)| l - r).collect();
+    let mid: Vec<i64> = left.iter().zip(right).map(|(l, r)| (l + r) >> 1).collect();
+    let left = encode_subframe(left, bits_per_sample, level);
+    let right = encode_subframe(right, bits_per_sample, level);
+    let side = encode_subframe(&side, bits_per_sample + 1, level);
+    let mid = encode_subframe(&mid, bits_per_sample, level);
+
+    let sizes = [
+        (Stereo::Independent, left.len_bits() + right.len_bits()),
+        (Stereo::LeftSide, left.len_bits() + side.len_bits()),
+        (Stereo::SideRight, side.len_bits() + right.len_bits()),
+        (Stereo::MidSide, mid.len_bits() + side.len_bits()),
+    ];
+    let (best, _) = sizes.iter().min_by_key(|(_, bits)| *bits).unwrap();
+    let subframes = match best {
+        Stereo::Independent => vec![left, right],
+        Stereo::LeftSide => vec![left, side],
+        Stereo::SideRight => vec![side, right],
+        Stereo::MidSide => vec![mid, side],
+    };
+    (*best, subframes)
+}
+
+/// The smallest of the constant, fixed predictor and verbatim encodings of `samples`.
+fn encode_subframe(samples: &[i64], bits_per_sample: u32, level: &CompressionLevel) -> BitWriter {
+    if samples.iter().all(|sample| *sample == samples[0]) {
+        let mut subframe = BitWriter::new();
+        subframe.write(0b0000_0000, 8);
+        subframe.write_signed(samples[0], bits_per_sample);
+        return subframe;
+    }
+
+    let verbatim_bits = 8 + samples.len() * bits_per_sample as usize;
+    let best_fixed = (0..=std::cmp::min(level.max_fixed_order, samples.len() - 1))
+        .filter_map(|order| {
+            let residual = fixed_residual(samples, order)?;
+            let partitions = choose_partitions(&residual, order, samples.len(), level);
+            Some((order, residual, partitions))
+        })
+        .min_by_key(|(order, _, partitions)| {
+            order * bits_per_sample as usize + partitions.estimated_bits
+        });
+
+    if let Some((order, residual, partitions)) = best_fixed {
+        let mut subframe = BitWriter::new();
+        subframe.write(0b0001_0000 | (order as u64) << 1, 8);
+        for warm_up in &samples[..order] {
+            subframe.write_signed(*warm_up, bits_per_sample);
+        }
+        write_residual(&mut subframe, &residual, order, samples.len(), &partitions);
+        if subframe.len_bits() < verbatim_bits {
+            return subframe;
+        }
+    }
+
+    let mut subframe = BitWriter::new();
+    subframe.write(0b0000_0010, 8);
+    for sample in samples {
+        subframe.write_signed(*sample, bits_per_sample);
+    }
+    subframe
+}
+
+/// Prediction error of the fixed polynomial predictor of `order`, after its warm up samples.
+///
+/// None if the residual doesn't fit the 32 bits FLAC allows.
+fn fixed_residual(samples: &[i64], order: usize) -> Option<Vec<i64>> {
+    let mut residual = samples.to_vec();
+    for _ in 0..order {
+        for i in (1..residual.len()).rev() {
+            residual[i] -= residual[i - 1];
+        }
+    }
+    let residual = residual.split_off(order);
+    let fits = residual
+        .iter()
+        .all(|value| *value >= i32::MIN as i64 && *value <= i32::MAX as i64);
+    if fits {
+        Some(residual)
+    } else {
+        None
+    }
+}
+
+struct Partitions {
+    order: u32,
+    parameters: Vec<u32>,
+    estimated_bits: usize,
+}
+
+/// Pick the partition order and per-partition Rice parameters that minimise the size.
+fn choose_partitions(
+    residual: &[i64],
+    predictor_order: usize,
+    block_size: usize,
+    level: &CompressionLevel,
+) -> Partitions {
+    let mut best: Option<Partitions> = None;
+    for order in 0..=level.max_partition_order {
+        let n_partitions = 1usize << order;
+        if block_size % n_partitions != 0 || block_size / n_partitions <= predictor_order {
+            break;
+        }
+        let partition_len = block_size / n_partitions;
+        let mut parameters = Vec::with_capacity(n_partitions);
+        let mut estimated_bits = 6;
+        let mut start = 0;
+        for partition in 0..n_partitions {
+            let len = if partition == 0 {
+                partition_len - predictor_order
+            } else {
+                partition_len
+            };
+            let values = &residual[start..start + len];
+            start += len;
+            let (parameter, bits) = rice_parameter(values);
+            parameters.push(parameter);
+            estimated_bits += bits;
+        }
+        let escape_bits = if parameters.iter().any(|k| *k > MAX_RICE_PARAM_4BIT) { 5 } else { 4 };
+        estimated_bits += escape_bits * n_partitions;
+        if best.as_ref().map_or(true, |best| estimated_bits < best.estimated_bits) {
+            best = Some(Partitions {
+                order,
+                parameters,
+                estimated_bits,
+            });
+        }
+    }
+    best.unwrap()
+}
+
+/// Best Rice parameter for `values`, with the exact number of bits it codes them in.
+fn rice_parameter(values: &[i64]) -> (u32, usize) {
+    let folded: Vec<u64> = values.iter().map(|value| zigzag(*value)).collect();
+    let cost = |k: u32| -> usize {
+        folded.iter().map(|value| (value >> k) as usize + 1 + k as usize).sum()
+    };
+    let mean = folded.iter().sum::<u64>() / std::cmp::max(folded.len(), 1) as u64;
+    // The optimum is within one of log2 of the mean.
+    let guess = (64 - mean.leading_zeros()).min(MAX_RICE_PARAM_5BIT);
+    (guess.saturating_sub(1)..=std::cmp::min(guess + 1, MAX_RICE_PARAM_5BIT))
+        .map(|k| (k, cost(k)))
+        .min_by_key(|(_, bits)| *bits)
+        .unwrap()
+}
+
+fn write_residual(
+    subframe: &mut BitWriter,
+    residual: &[i64],
+    predictor_order: usize,
+    block_size: usize,
+    partitions: &Partitions,
+) {
+    let wide = partitions.parameters.iter().any(|k| *k > MAX_RICE_PARAM_4BIT);
+    subframe.write(if wide { 1 } else { 0 }, 2);
+    subframe.write(partitions.order as u64, 4);
+    let partition_len = block_size >> partitions.order;
+    let mut start = 0;
+    for (partition, parameter) in partitions.parameters.iter().enumerate() {
+        let len = if partition == 0 {
+            partition_len - predictor_order
+        } else {
+            partition_len
+        };
+        subframe.write(*parameter as u64, if wide { 5 } else { 4 });
+        for value in &residual[start..start + len] {
+            subframe.write_rice(*value, *parameter);
+        }
+        start += len;
+    }
+}
+
+/// Header code for the block size, with the value to append when there's no dedicated code.
+fn block_size_code(block_size: usize) -> (u64, Option<(u64, u32)>) {
+    match block_size {
+        192 => (1, None),
+        576 | 1152 | 2304 | 4608 => (2 + (block_size / 576).trailing_zeros() as u64, None),
+        256 | 512 | 1024 | 2048 | 4096 | 8192 | 16384 | 32768 => {
+            (8 + (block_size / 256).trailing_zeros() as u64, None)
+        }
+        size if size <= 256 => (6, Some((size as u64 - 1, 8))),
+        size => (7, Some((size as u64 - 1, 16))),
+    }
+}
+
+/// Header code for the sample rate, with the value to append when there's no dedicated code.
+fn sample_rate_code(sample_rate: u32) -> (u64, Option<(u64, u32)>) {
+    match sample_rate {
+        88_200 => (1, None),
+        176_400 => (2, None),
+        192_000 => (3, None),
+        8_000 => (4, None),
+        16_000 => (5, None),
+        22_050 => (6, None),
+        24_000 => (7, None),
+        32_000 => (8, None),
+        44_100 => (9, None),
+        48_000 => (10, None),
+        96_000 => (11, None),
+        rate if rate % 1000 == 0 && rate / 1000 <= 255 => (12, Some((rate as u64 / 1000, 8))),
+        rate if rate <= 65_535 => (13, Some((rate as u64, 16))),
+        rate if rate % 10 == 0 && rate / 10 <= 65_535 => (14, Some((rate as u64 / 10, 16))),
+        // Taken from STREAMINFO.
+        _ => (0, None),
+    }
+}
+
+fn sample_size_code(bits_per_sample: u32) -> u64 {
+    match bits_per_sample {
+        8 => 1,
+        12 => 2,
+        16 => 4,
+        20 => 5,
+        24 => 6,
+        // 32 bits has its own code since RFC 9639, but older decoders treat it as reserved.
+        // Taken from STREAMINFO.
+        _ => 0,
+    }
+}
+
+/// Frame numbers are coded like UTF-8 code points, extended up to 36 bits.
+fn write_utf8(writer: &mut BitWriter, value: u64) {
+    if value < 0x80 {
+        writer.write(value, 8);
+        return;
+    }
+    let n_bytes = match value {
+        0..=0x7ff => 2,
+        0x800..=0xffff => 3,
+        0x1_0000..=0x1f_ffff => 4,
+        0x20_0000..=0x3ff_ffff => 5,
+        0x400_0000..=0x7fff_ffff => 6,
+        _ => 7,
+    };
+    let continuation_bits = 6 * (n_bytes - 1);
+    let lead_marker = (0xff00u64 >> n_bytes) & 0xff;
+    writer.write(lead_marker | (value >> continuation_bits), 8);
+    for byte in (0..n_bytes - 1).rev() {
+        writer.write(0x80 | ((value >> (6 * byte)) & 0x3f), 8);
+    }
+}
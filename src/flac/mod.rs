@@ -0,0 +1,284 @@
+use std::io::{Seek, SeekFrom, Write};
+
+use anyhow::Error;
+
+use crate::flac::bit_writer::BitWriter;
+use crate::flac::frame::{encode_frame, CompressionLevel};
+use crate::flac::md5::Md5;
+
+pub(crate) mod bit_writer;
+pub(crate) mod frame;
+mod md5;
+
+const STREAMINFO: u64 = 0;
+const VORBIS_COMMENT: u64 = 4;
+/// STREAMINFO is rewritten on close, straight after `fLaC` and its block header.
+const STREAMINFO_OFFSET: u64 = 8;
+
+const VENDOR: &str = concat!("Audia ", env!("CARGO_PKG_VERSION"));
+
+/// Encodes a FLAC stream of right-justified integer samples.
+///
+/// Samples are buffered into blocks of the level's block size. STREAMINFO can only be
+/// completed once the totals are known, so the output must be seekable.
+pub(crate) struct FlacEncoder<W>
+    where
+        W: Write + Seek,
+{
+    output: W,
+    level: CompressionLevel,
+    sample_rate: u32,
+    bits_per_sample: u32,
+    /// One buffer per channel, holding the part of the next block received so far.
+    pending: Vec<Vec<i64>>,
+    frame_number: u64,
+    total_samples: u64,
+    min_frame_size: u32,
+    max_frame_size: u32,
+    md5: Md5,
+}
+
+impl<W> FlacEncoder<W>
+    where
+        W: Write + Seek,
+{
+    /// Start a stream, writing its metadata with `tags` as the Vorbis comments.
+    pub(crate) fn new(
+        output: W,
+        sample_rate: u32,
+        n_channels: usize,
+        bits_per_sample: u32,
+        level: CompressionLevel,
+        tags: &[(String, String)],
+    ) -> Result<Self, Error> {
+        if !(1..=8).contains(&n_channels) {
+            bail!("FLAC supports 1 to 8 channels, not {}", n_channels);
+        }
+        if !(4..=32).contains(&bits_per_sample) {
+            bail!("FLAC supports 4 to 32 bit samples, not {}", bits_per_sample);
+        }
+        if sample_rate == 0 || sample_rate >= 1 << 20 {
+            bail!("FLAC can't store a sample rate of {}Hz", sample_rate);
+        }
+        let mut encoder = FlacEncoder {
+            output,
+            level,
+            sample_rate,
+            bits_per_sample,
+            pending: vec![Vec::with_capacity(level.block_size); n_channels],
+            frame_number: 0,
+            total_samples: 0,
+            min_frame_size: 0,
+            max_frame_size: 0,
+            md5: Md5::new(),
+        };
+        encoder.write_metadata(tags)?;
+        Ok(encoder)
+    }
+
+    fn write_metadata(&mut self, tags: &[(String, String)]) -> Result<(), Error> {
+        let stream_info = self.stream_info([0; 16]);
+        let comments = vorbis_comment(tags);
+        self.output.write_all(b"fLaC")?;
+        self.output.write_all(&metadata_header(false, STREAMINFO, stream_info.len()))?;
+        self.output.write_all(&stream_info)?;
+        self.output.write_all(&metadata_header(true, VORBIS_COMMENT, comments.len()))?;
+        self.output.write_all(&comments)?;
+        Ok(())
+    }
+
+    /// Add interleaved samples, writing every block that fills up.
+    pub(crate) fn write(&mut self, samples: &[i64]) -> Result<(), Error> {
+        let n_channels = self.pending.len();
+        let bytes_per_sample = ((self.bits_per_sample + 7) / 8) as usize;
+        let mut bytes = Vec::with_capacity(samples.len() * bytes_per_sample);
+        for frame in samples.chunks_exact(n_channels) {
+            for (channel, sample) in frame.iter().enumerate() {
+                self.pending[channel].push(*sample);
+                bytes.extend_from_slice(&sample.to_le_bytes()[..bytes_per_sample]);
+            }
+            if self.pending[0].len() == self.level.block_size {
+                self.write_block()?;
+            }
+        }
+        self.md5.update(&bytes);
+        Ok(())
+    }
+
+    /// Write the final partial block and complete STREAMINFO, returning the output.
+    pub(crate) fn finish(mut self) -> Result<W, Error> {
+        if !self.pending[0].is_empty() {
+            self.write_block()?;
+        }
+        let md5 = std::mem::replace(&mut self.md5, Md5::new()).finish();
+        let stream_info = self.stream_info(md5);
+        let end = self.output.seek(SeekFrom::Current(0))?;
+        self.output.seek(SeekFrom::Start(STREAMINFO_OFFSET))?;
+        self.output.write_all(&stream_info)?;
+        self.output.seek(SeekFrom::Start(end))?;
+        self.output.flush()?;
+        Ok(self.output)
+    }
+
+    pub(crate) fn total_samples(&self) -> u64 {
+        self.total_samples
+    }
+
+    fn write_block(&mut self) -> Result<(), Error> {
+        let frame = encode_frame(
+            &self.pending,
+            self.bits_per_sample,
+            self.sample_rate,
+            self.frame_number,
+            &self.level,
+        );
+        self.output.write_all(&frame)?;
+        let size = frame.len() as u32;
+        self.min_frame_size = if self.frame_number == 0 {
+            size
+        } else {
+            self.min_frame_size.min(size)
+        };
+        self.max_frame_size = self.max_frame_size.max(size);
+        self.frame_number += 1;
+        self.total_samples += self.pending[0].len() as u64;
+        self.pending.iter_mut().for_each(|channel| channel.clear());
+        Ok(())
+    }
+
+    fn stream_info(&self, md5: [u8; 16]) -> Vec<u8> {
+        // Only the last block may be shorter, and it doesn't count towards the minimum.
+        let block_size = if self.total_samples > 0 && self.frame_number == 1 {
+            self.total_samples.max(16)
+        } else {
+            self.level.block_size as u64
+        };
+        let mut info = BitWriter::new();
+        info.write(block_size, 16);
+        info.write(block_size, 16);
+        info.write(self.min_frame_size as u64, 24);
+        info.write(self.max_frame_size as u64, 24);
+        info.write(self.sample_rate as u64, 20);
+        info.write(self.pending.len() as u64 - 1, 3);
+        info.write(self.bits_per_sample as u64 - 1, 5);
+        info.write(self.total_samples >> 32, 4);
+        info.write(self.total_samples, 32);
+        let mut info = info.into_bytes();
+        info.extend_from_slice(&md5);
+        info
+    }
+}
+
+fn metadata_header(is_last: bool, block_type: u64, len: usize) -> Vec<u8> {
+    let mut header = BitWriter::new();
+    header.write(is_last as u64, 1);
+    header.write(block_type, 7);
+    header.write(len as u64, 24);
+    header.into_bytes()
+}
+
+/// VORBIS_COMMENT block body: little-endian lengths, unlike the rest of FLAC.
+fn vorbis_comment(tags: &[(String, String)]) -> Vec<u8> {
+    let mut body = Vec::new();
+    body.extend_from_slice(&(VENDOR.len() as u32).to_le_bytes());
+    body.extend_from_slice(VENDOR.as_bytes());
+    body.extend_from_slice(&(tags.len() as u32).to_le_bytes());
+    for (key, value) in tags {
+        let comment = format!("{}={}", key.to_ascii_uppercase(), value);
+        body.extend_from_slice(&(comment.len() as u32).to_le_bytes());
+        body.extend_from_slice(comment.as_bytes());
+    }
+    body
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    /// Interleaved test signal: a sine, noise, a silent stretch and full-scale steps, so every
+    /// predictor and subframe type gets used.
+    fn signal(n_frames: usize, n_channels: usize, bits: u32) -> Vec<i64> {
+        let max = (1i64 << (bits - 1)) - 1;
+        let mut seed = 0x1234_5678u32;
+        let mut noise = move || {
+            seed = seed.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+            (seed >> 8) as i64 % (max / 64) - max / 128
+        };
+        (0..n_frames)
+            .flat_map(|n| (0..n_channels).map(move |channel| (n, channel)))
+            .map(|(n, channel)| match n / 4096 {
+                1 => 0,
+                3 => {
+                    if n % 2 == 0 {
+                        max
+                    } else {
+                        -max - 1
+                    }
+                }
+                _ => {
+                    let phase = n as f64 * (0.01 + 0.02 * channel as f64);
+                    (phase.sin() * max as f64 * 0.5) as i64
+                }
+            })
+            .map(|sample| (sample + noise()).max(-max - 1).min(max))
+            .collect()
+    }
+
+    fn encode(samples: &[i64], n_channels: usize, bits: u32, level: u8) -> Vec<u8> {
+        let tags = [("DEVICE".to_string(), "Mic".to_string())];
+        let mut encoder = FlacEncoder::new(
+            Cursor::new(vec![]),
+            44100,
+            n_channels,
+            bits,
+            CompressionLevel::new(level),
+            &tags,
+        )
+            .unwrap();
+        // Uneven writes, so blocks are filled across calls.
+        for chunk in samples.chunks(1000 * n_channels + n_channels) {
+            encoder.write(chunk).unwrap();
+        }
+        encoder.finish().unwrap().into_inner()
+    }
+
+    #[test]
+    fn decodes_to_the_input_at_every_level() {
+        for &n_channels in &[1, 2] {
+            for &bits in &[16, 24] {
+                let samples = signal(4096 * 4 + 100, n_channels, bits);
+                for level in 0..=8 {
+                    let flac = encode(&samples, n_channels, bits, level);
+                    let mut reader = claxon::FlacReader::new(Cursor::new(flac)).unwrap();
+                    let info = reader.streaminfo();
+                    assert_eq!(info.channels, n_channels as u32);
+                    assert_eq!(info.bits_per_sample, bits);
+                    assert_eq!(info.samples, Some((samples.len() / n_channels) as u64));
+                    assert_eq!(reader.get_tag("DEVICE").collect::<Vec<_>>(), ["Mic"]);
+                    let decoded: Vec<i64> =
+                        reader.samples().map(|sample| sample.unwrap() as i64).collect();
+                    assert!(
+                        decoded == samples,
+                        "{} channels of {} bits at level {} don't round trip",
+                        n_channels,
+                        bits,
+                        level
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn signs_the_decoded_audio() {
+        let samples = signal(5000, 2, 16);
+        let flac = encode(&samples, 2, 16, 5);
+        let reader = claxon::FlacReader::new(Cursor::new(flac)).unwrap();
+        let mut md5 = Md5::new();
+        for sample in &samples {
+            md5.update(&(*sample as i16).to_le_bytes());
+        }
+        assert_eq!(reader.streaminfo().md5sum, md5.finish());
+    }
+}
@@ -0,0 +1,117 @@
+//! Pushing live audio, levels and transcripts to WebSocket clients such as dashboards.
+//!
+//! Writers and the ASR connector publish `LiveEvent`s to a `LiveHub`; the server in `server`
+//! forwards each client the events and channels it subscribed to.
+
+use std::str::FromStr;
+use std::sync::Arc;
+
+use anyhow::Error;
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast;
+
+use crate::dsp::meter::ChannelLevels;
+use crate::opus::OpusSettings;
+use crate::stream_format::StreamFormat;
+
+pub(crate) mod server;
+
+/// Events a slow client may fall behind by before it starts missing them.
+const EVENT_BACKLOG: usize = 256;
+
+/// How live audio is sent to clients.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum LiveAudio {
+    /// Interleaved 16-bit little-endian PCM at the capture rate.
+    Pcm,
+    /// One Opus packet per message, mono or stereo at 48kHz.
+    Opus(OpusSettings),
+}
+
+impl FromStr for LiveAudio {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "pcm" => Ok(LiveAudio::Pcm),
+            "opus" => Ok(LiveAudio::Opus(OpusSettings::default())),
+            _ => Err(anyhow!("Unknown live audio encoding `{}`, expected `pcm` or `opus`", s)),
+        }
+    }
+}
+
+/// Kinds of event a client can subscribe to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum EventKind {
+    Audio,
+    Levels,
+    Transcript,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum LiveEvent {
+    /// Interleaved samples of every channel.
+    Pcm(Vec<i16>),
+    Opus(Vec<u8>),
+    /// Levels of each channel, in capture order. Digital silence, `-inf` dBFS, is sent to
+    /// clients as `null`.
+    Levels(Vec<ChannelLevels>),
+    Transcript(String),
+}
+
+impl LiveEvent {
+    pub(crate) fn kind(&self) -> EventKind {
+        match self {
+            LiveEvent::Pcm(_) | LiveEvent::Opus(_) => EventKind::Audio,
+            LiveEvent::Levels(_) => EventKind::Levels,
+            LiveEvent::Transcript(_) => EventKind::Transcript,
+        }
+    }
+}
+
+/// Fans events out to every connected client. Cloning gives another handle to the same hub.
+#[derive(Clone)]
+pub(crate) struct LiveHub {
+    format: StreamFormat,
+    audio: LiveAudio,
+    sender: broadcast::Sender<Arc<LiveEvent>>,
+}
+
+impl LiveHub {
+    pub(crate) fn new(format: StreamFormat, audio: LiveAudio) -> Self {
+        let (sender, _) = broadcast::channel(EVENT_BACKLOG);
+        LiveHub {
+            format,
+            audio,
+            sender,
+        }
+    }
+
+    /// Format of the capture the events describe.
+    pub(crate) fn format(&self) -> StreamFormat {
+        self.format
+    }
+
+    pub(crate) fn audio(&self) -> LiveAudio {
+        self.audio
+    }
+
+    /// Whether anyone is connected, so publishers can skip work nobody will see.
+    pub(crate) fn has_clients(&self) -> bool {
+        self.sender.receiver_count() > 0
+    }
+
+    pub(crate) fn publish(&self, event: LiveEvent) {
+        // Only fails when no client is connected, and then there's nobody to tell.
+        let _ = self.sender.send(Arc::new(event));
+    }
+
+    pub(crate) fn publish_transcript(&self, text: &str) {
+        self.publish(LiveEvent::Transcript(text.to_string()));
+    }
+
+    pub(crate) fn subscribe(&self) -> broadcast::Receiver<Arc<LiveEvent>> {
+        self.sender.subscribe()
+    }
+}
@@ -0,0 +1,238 @@
+use std::net::SocketAddr;
+
+use anyhow::Error;
+use futures::{SinkExt, StreamExt};
+use log::{debug, error, info, warn};
+use serde::{Deserialize, Serialize};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::broadcast::error::RecvError;
+use tokio_tungstenite::tungstenite::Message;
+
+use crate::channel_layout::ChannelLayout;
+use crate::dsp::meter::ChannelLevels;
+use crate::live::{EventKind, LiveAudio, LiveEvent, LiveHub};
+use crate::opus::OPUS_RATE;
+use crate::stream_format::StreamFormat;
+use crate::writer::channel_split_writer::{channel_name, parse_channels};
+
+/// What a client is sent. New clients get levels and transcripts for every channel.
+struct Subscription {
+    events: Vec<EventKind>,
+    /// Channels in capture order, used for PCM audio and levels.
+    channels: Vec<usize>,
+}
+
+/// A client changing its subscription, e.g. `{"events": ["audio", "levels"], "channels":
+/// "FL,FR"}`. Channels are speaker names or numbers counting from one, or `all`; fields left
+/// out keep their current value.
+#[derive(Deserialize)]
+struct SubscribeRequest {
+    events: Option<Vec<EventKind>>,
+    channels: Option<String>,
+}
+
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum ServerMessage<'a> {
+    /// Sent on connecting.
+    Hello {
+        format: String,
+        channels: Vec<String>,
+        audio: AudioDescription,
+    },
+    /// Sent on connecting and whenever the subscription changes. PCM audio carries these
+    /// channels, interleaved in this order.
+    Subscribed {
+        events: &'a [EventKind],
+        channels: Vec<String>,
+    },
+    Levels {
+        channels: Vec<NamedLevel>,
+    },
+    Transcript {
+        text: &'a str,
+    },
+    Error {
+        message: String,
+    },
+}
+
+/// How binary messages carry audio.
+#[derive(Serialize)]
+#[serde(tag = "encoding", rename_all = "lowercase")]
+enum AudioDescription {
+    Pcm { rate: u32, sample: &'static str },
+    Opus { rate: u32, channels: u32 },
+}
+
+#[derive(Serialize)]
+struct NamedLevel {
+    channel: String,
+    #[serde(flatten)]
+    levels: ChannelLevels,
+}
+
+/// Listen for WebSocket clients on `address`, serving them from `hub` on the current Tokio
+/// runtime until the process exits.
+pub(crate) fn start(address: &str, hub: LiveHub) -> Result<(), Error> {
+    let listener = std::net::TcpListener::bind(address)
+        .map_err(|e| anyhow!("Couldn't listen on {}: {}", address, e))?;
+    listener.set_nonblocking(true)?;
+    let listener = TcpListener::from_std(listener)?;
+    info!("Serving live events on ws://{}", listener.local_addr()?);
+    tokio::spawn(async move {
+        loop {
+            let (stream, peer) = match listener.accept().await {
+                Ok(client) => client,
+                Err(e) => {
+                    error!("Live server stopped: {}", e);
+                    return;
+                }
+            };
+            let hub = hub.clone();
+            tokio::spawn(async move {
+                match serve_client(stream, peer, hub).await {
+                    Ok(()) => debug!("Live client {} disconnected", peer),
+                    Err(e) => debug!("Live client {} dropped: {}", peer, e),
+                }
+            });
+        }
+    });
+    Ok(())
+}
+
+async fn serve_client(stream: TcpStream, peer: SocketAddr, hub: LiveHub) -> Result<(), Error> {
+    let socket = tokio_tungstenite::accept_async(stream).await?;
+    let (mut outgoing, mut incoming) = socket.split();
+    let mut events = hub.subscribe();
+    let format = hub.format();
+    let layout = format.channel_layout();
+    let names: Vec<String> = (0..format.n_channels as usize)
+        .map(|index| channel_name(&layout, index))
+        .collect();
+    let mut subscription = Subscription {
+        events: vec![EventKind::Levels, EventKind::Transcript],
+        channels: (0..names.len()).collect(),
+    };
+    info!("Live client {} connected", peer);
+
+    let audio = match hub.audio() {
+        LiveAudio::Pcm => AudioDescription::Pcm {
+            rate: format.n_sample_per_sec,
+            sample: "i16le",
+        },
+        LiveAudio::Opus(_) => AudioDescription::Opus {
+            rate: OPUS_RATE,
+            channels: format.n_channels.min(2),
+        },
+    };
+    let hello = ServerMessage::Hello {
+        format: format.to_string(),
+        channels: names.clone(),
+        audio,
+    };
+    outgoing.send(json(&hello)?).await?;
+    outgoing.send(subscribed(&subscription, &names)?).await?;
+
+    loop {
+        tokio::select! {
+            message = incoming.next() => match message {
+                Some(Ok(Message::Text(text))) => {
+                    let reply = match update(&mut subscription, &text, &layout) {
+                        Ok(()) => subscribed(&subscription, &names)?,
+                        Err(e) => json(&ServerMessage::Error { message: e.to_string() })?,
+                    };
+                    outgoing.send(reply).await?;
+                }
+                Some(Ok(Message::Close(_))) | None => return Ok(()),
+                // Pings are answered by tungstenite itself.
+                Some(Ok(_)) => {}
+                Some(Err(e)) => return Err(e.into()),
+            },
+            event = events.recv() => match event {
+                Ok(event) => {
+                    if let Some(message) = to_message(&event, &subscription, &names, &format)? {
+                        outgoing.send(message).await?;
+                    }
+                }
+                Err(RecvError::Lagged(missed)) => {
+                    warn!("Live client {} is too slow and missed {} events", peer, missed)
+                }
+                // Every handle to the hub is gone.
+                Err(RecvError::Closed) => {
+                    outgoing.send(Message::Close(None)).await?;
+                    return Ok(());
+                }
+            },
+        }
+    }
+}
+
+/// Apply a `SubscribeRequest`.
+fn update(
+    subscription: &mut Subscription,
+    request: &str,
+    layout: &ChannelLayout,
+) -> Result<(), Error> {
+    let request: SubscribeRequest = serde_json::from_str(request)?;
+    if let Some(channels) = request.channels {
+        subscription.channels = if channels.trim().eq_ignore_ascii_case("all") {
+            (0..layout.n_channels() as usize).collect()
+        } else {
+            let mut channels = parse_channels(&channels, layout)?;
+            channels.sort_unstable();
+            channels
+        };
+    }
+    if let Some(events) = request.events {
+        subscription.events = events;
+    }
+    Ok(())
+}
+
+/// The message carrying `event` to a client with `subscription`, if it wants it.
+fn to_message(
+    event: &LiveEvent,
+    subscription: &Subscription,
+    names: &[String],
+    format: &StreamFormat,
+) -> Result<Option<Message>, Error> {
+    if !subscription.events.contains(&event.kind()) {
+        return Ok(None);
+    }
+    let message = match event {
+        LiveEvent::Pcm(samples) => {
+            let n_channels = format.n_channels as usize;
+            let bytes = samples
+                .chunks_exact(n_channels)
+                .flat_map(|frame| subscription.channels.iter().map(move |&index| frame[index]))
+                .flat_map(i16::to_le_bytes)
+                .collect();
+            Message::Binary(bytes)
+        }
+        LiveEvent::Opus(packet) => Message::Binary(packet.clone()),
+        LiveEvent::Levels(levels) => json(&ServerMessage::Levels {
+            channels: subscription
+                .channels
+                .iter()
+                .map(|&index| NamedLevel {
+                    channel: names[index].clone(),
+                    levels: levels[index],
+                })
+                .collect(),
+        })?,
+        LiveEvent::Transcript(text) => json(&ServerMessage::Transcript { text })?,
+    };
+    Ok(Some(message))
+}
+
+fn subscribed(subscription: &Subscription, names: &[String]) -> Result<Message, Error> {
+    json(&ServerMessage::Subscribed {
+        events: &subscription.events,
+        channels: subscription.channels.iter().map(|&index| names[index].clone()).collect(),
+    })
+}
+
+fn json(message: &ServerMessage<'_>) -> Result<Message, Error> {
+    Ok(Message::Text(serde_json::to_string(message)?))
+}
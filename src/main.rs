@@ -2,55 +2,422 @@
 extern crate anyhow;
 #[macro_use]
 extern crate lazy_static;
-#[macro_use]
 extern crate tokio;
 
-use std::io::Error as IoError;
-use std::time::SystemTime;
+use std::fs::File;
+use std::io::{self, Error as IoError, Read};
+use std::path::Path;
+use std::time::{Duration, SystemTime};
 
 use log::{debug, error, info, Level, log_enabled};
+use serde::Serialize;
 
 use crate::audio_client::IAudioClientWrapper;
 use crate::audio_sink::AudioSink;
 use crate::capture_client::BufferStatus;
 use crate::device::Device;
-use crate::stream_format::{SampleFormat, StreamFormat};
+use crate::flac::frame::CompressionLevel;
+use crate::live::{server, LiveAudio, LiveHub};
+use crate::network::Endpoint;
+use crate::opus::OpusSettings;
+use crate::rtp::RtpPayload;
+use crate::source::network::NetworkSource;
+use crate::source::raw_pcm::RawPcmSource;
+use crate::stream_format::{
+    ALaw, I24, I24In32, MuLaw, Sample, SampleFormat, StreamFormat, U8,
+};
+use crate::wav::metadata::WavMetadata;
+use crate::wav::reader::WavReader;
+use crate::wav::repair;
+use crate::wav::repair::Repair;
 use crate::writer::AudioWriter;
-use crate::writer::hound_writer::HoundWriter;
+use crate::writer::asr_connector::ASRConnector;
+use crate::writer::channel_split_writer::{parse_channels, ChannelSplitWriter};
+use crate::writer::flac_writer::FlacWriter;
+use crate::writer::live_writer::LiveWriter;
+use crate::writer::meter_writer::{MeterSettings, MeterWriter};
+use crate::writer::network_writer::NetworkWriter;
+use crate::writer::opus_writer::OpusWriter;
+use crate::writer::output_path::{DEFAULT_TEMPLATE, OutputPath};
+use crate::writer::raw_pcm_writer::RawPcmWriter;
+use crate::writer::replay_writer::ReplayWriter;
+use crate::writer::rf64_writer::Rf64Writer;
+use crate::writer::rtp_writer::RtpWriter;
+use crate::writer::segmented_writer::{Rollover, SegmentedWriter};
+use crate::writer::tee_writer::TeeWriter;
 
 mod asr;
 mod audio_client;
 mod audio_sink;
 mod buffer;
 mod capture_client;
+mod channel_layout;
 mod com;
+mod commands;
 mod device;
 mod device_enumerator;
+mod dsp;
+mod flac;
+mod live;
+mod network;
+mod opus;
+mod rtp;
+mod source;
 mod stream_format;
 mod utils;
+mod wav;
 mod writer;
 
 const DEFAULT_TIMEOUT_SECS: u64 = 1000;
 
+/// Seconds of audio kept in memory for instant replay. Replay is disabled when unset.
+const REPLAY_SECS_VAR: &str = "AUDIA_REPLAY_SECS";
+
+/// Template for recordings, see `OutputPath`. Recordings are FLAC when it ends in `.flac` and
+/// Ogg Opus when it ends in `.opus` or `.ogg`. `-` streams raw PCM to stdout instead,
+/// `pipe:<path>` to a named pipe, `tcp://host:port` or `udp://host:port` to another machine
+/// running `audia receive` and `rtp://host:port` to RTP receivers.
+const OUTPUT_VAR: &str = "AUDIA_OUTPUT";
+
+/// FLAC compression level, 0 (fastest) to 8 (smallest).
+const FLAC_LEVEL_VAR: &str = "AUDIA_FLAC_LEVEL";
+
+/// Comment stored in WAV recordings' INFO tags.
+const COMMENT_VAR: &str = "AUDIA_COMMENT";
+
+/// Bit depth recordings are saved at, if not the device's own.
+const BIT_DEPTH_VAR: &str = "AUDIA_BIT_DEPTH";
+
+/// Headerless samples to record instead of a device: a file, a named pipe or `-` for stdin.
+/// Needs `AUDIA_INPUT_FORMAT`.
+const INPUT_VAR: &str = "AUDIA_INPUT";
+
+/// Format of the `AUDIA_INPUT` samples, e.g. `8000Hz:1ch:mulaw`, see `StreamFormat`.
+const INPUT_FORMAT_VAR: &str = "AUDIA_INPUT_FORMAT";
+
+fn output_path(device: &str) -> OutputPath {
+    let template = std::env::var(OUTPUT_VAR).unwrap_or_else(|_| DEFAULT_TEMPLATE.to_string());
+    OutputPath::new(&template, device)
+}
+
+fn output_sample_format() -> Option<SampleFormat> {
+    let bits = std::env::var(BIT_DEPTH_VAR).ok()?;
+    match bits.trim() {
+        "8" => Some(SampleFormat::U8),
+        "16" => Some(SampleFormat::I16),
+        "24" => Some(SampleFormat::I24),
+        "32" => Some(SampleFormat::I32),
+        _ => {
+            error!("{} must be one of 8, 16, 24 or 32, got `{}`", BIT_DEPTH_VAR, bits);
+            None
+        }
+    }
+}
+
+fn flac_level() -> CompressionLevel {
+    match std::env::var(FLAC_LEVEL_VAR).ok().map(|level| level.trim().parse::<u8>()) {
+        None => CompressionLevel::default(),
+        Some(Ok(level)) if level <= 8 => CompressionLevel::new(level),
+        Some(_) => {
+            error!("{} must be between 0 and 8", FLAC_LEVEL_VAR);
+            CompressionLevel::default()
+        }
+    }
+}
+
+/// Opus encoder settings, see `OpusSettings`.
+const OPUS_VAR: &str = "AUDIA_OPUS";
+
+fn opus_settings() -> OpusSettings {
+    match std::env::var(OPUS_VAR).ok().map(|settings| settings.parse()) {
+        None => OpusSettings::default(),
+        Some(Ok(settings)) => settings,
+        Some(Err(e)) => {
+            error!("Ignoring {}: {}", OPUS_VAR, e);
+            OpusSettings::default()
+        }
+    }
+}
+
+/// RTP payload, `l16` (the default) or `opus`. Opus uses the `AUDIA_OPUS` settings.
+const RTP_PAYLOAD_VAR: &str = "AUDIA_RTP_PAYLOAD";
+
+/// Where to save the SDP describing an RTP stream. It's logged when unset.
+const RTP_SDP_VAR: &str = "AUDIA_RTP_SDP";
+
+fn rtp_payload() -> RtpPayload {
+    match std::env::var(RTP_PAYLOAD_VAR).ok().map(|payload| payload.parse()) {
+        None => RtpPayload::L16,
+        Some(Ok(RtpPayload::Opus(_))) => RtpPayload::Opus(opus_settings()),
+        Some(Ok(payload)) => payload,
+        Some(Err(e)) => {
+            error!("Ignoring {}: {}", RTP_PAYLOAD_VAR, e);
+            RtpPayload::L16
+        }
+    }
+}
+
+/// Meter every channel's levels, see `MeterSettings`. Set it empty for the defaults.
+const METER_VAR: &str = "AUDIA_METER";
+
+fn meter_settings() -> Option<MeterSettings> {
+    match std::env::var(METER_VAR).ok()?.parse() {
+        Ok(settings) => Some(settings),
+        Err(e) => {
+            error!("Ignoring {}: {}", METER_VAR, e);
+            Some(MeterSettings::default())
+        }
+    }
+}
+
+/// `host:port` to serve live audio, levels and transcripts to WebSocket clients on.
+const LIVE_VAR: &str = "AUDIA_LIVE";
+
+/// How live audio is sent, `pcm` (the default) or `opus`. Opus uses the `AUDIA_OPUS` settings.
+const LIVE_AUDIO_VAR: &str = "AUDIA_LIVE_AUDIO";
+
+/// Send the capture to the speech recognition service when set, publishing its transcripts to
+/// live clients.
+const ASR_VAR: &str = "AUDIA_ASR";
+
+/// How the capture's channels are mixed to mono for speech recognition, as one row of
+/// coefficients, e.g. `1,0` for the left channel only. See `MixMatrix`.
+const ASR_MIX_VAR: &str = "AUDIA_ASR_MIX";
+
+/// Start the live server if one is configured, returning the hub to publish to.
+fn live_hub(stream_format: StreamFormat) -> Option<LiveHub> {
+    let address = std::env::var(LIVE_VAR).ok()?;
+    let audio = match std::env::var(LIVE_AUDIO_VAR).ok().map(|audio| audio.parse()) {
+        None => LiveAudio::Pcm,
+        Some(Ok(LiveAudio::Opus(_))) => LiveAudio::Opus(opus_settings()),
+        Some(Ok(audio)) => audio,
+        Some(Err(e)) => {
+            error!("Ignoring {}: {}", LIVE_AUDIO_VAR, e);
+            LiveAudio::Pcm
+        }
+    };
+    let hub = LiveHub::new(stream_format, audio);
+    match server::start(&address, hub.clone()) {
+        Ok(()) => Some(hub),
+        Err(e) => {
+            error!("Live output is off: {}", e);
+            None
+        }
+    }
+}
+
+/// Record these channels of a WAV recording to separate mono files, e.g. `FL,FR` or `1,3`, or
+/// `all` for every channel.
+const SPLIT_CHANNELS_VAR: &str = "AUDIA_SPLIT_CHANNELS";
+
+/// None to record the channels together, or Some(None) to split out every channel.
+fn split_channels(stream_format: &StreamFormat) -> Option<Option<Vec<usize>>> {
+    let channels = std::env::var(SPLIT_CHANNELS_VAR).ok()?;
+    if channels.trim().eq_ignore_ascii_case("all") {
+        return Some(None);
+    }
+    match parse_channels(&channels, &stream_format.channel_layout()) {
+        Ok(channels) => Some(Some(channels)),
+        Err(e) => {
+            error!("Ignoring {}: {}", SPLIT_CHANNELS_VAR, e);
+            None
+        }
+    }
+}
+
+/// Split WAV recordings into files of this length or size, see `Rollover`.
+const SEGMENT_VAR: &str = "AUDIA_SEGMENT";
+
+fn rollover() -> Option<Rollover> {
+    let rollover = std::env::var(SEGMENT_VAR).ok()?;
+    match rollover.parse() {
+        Ok(rollover) => Some(rollover),
+        Err(e) => {
+            error!("Ignoring {}: {}", SEGMENT_VAR, e);
+            None
+        }
+    }
+}
+
+fn replay_history() -> Option<Duration> {
+    let secs = std::env::var(REPLAY_SECS_VAR).ok()?;
+    match secs.parse() {
+        Ok(secs) => Some(Duration::from_secs(secs)),
+        Err(_) => {
+            error!("{} must be a whole number of seconds, got `{}`", REPLAY_SECS_VAR, secs);
+            None
+        }
+    }
+}
+
+fn create_sink<T>(
+    stream_format: StreamFormat,
+    device: &str,
+) -> Result<Box<dyn AudioWriter<T>>, anyhow::Error>
+    where
+        T: hound::Sample + Sample + Serialize + Copy + Send + 'static,
+{
+    let path = output_path(device);
+    let recorder: Box<dyn AudioWriter<T>> = if path.template() == "-" {
+        Box::new(RawPcmWriter::stdout(stream_format, output_sample_format()))
+    } else if let Some(pipe) = path.template().strip_prefix("pipe:") {
+        Box::new(RawPcmWriter::pipe(stream_format, Path::new(pipe), output_sample_format())?)
+    } else if path.template().starts_with("tcp://") || path.template().starts_with("udp://") {
+        let endpoint: Endpoint = path.template().parse()?;
+        Box::new(NetworkWriter::connect(stream_format, &endpoint)?)
+    } else if let Some(address) = path.template().strip_prefix("rtp://") {
+        let writer = RtpWriter::connect(stream_format, address, rtp_payload())
+            .map_err(|e| anyhow!("Couldn't stream RTP to {}: {}", address, e))?;
+        let sdp = writer
+            .sdp(&format!("Audia {}", device))
+            .map_err(|e| anyhow!("Couldn't describe the RTP stream: {}", e))?;
+        match std::env::var(RTP_SDP_VAR) {
+            Ok(sdp_path) => {
+                std::fs::write(&sdp_path, sdp)
+                    .map_err(|e| anyhow!("Couldn't save the SDP to {}: {}", sdp_path, e))?;
+                info!("Saved the stream's SDP to {}", sdp_path);
+            }
+            Err(_) => info!("Stream SDP:\n{}", sdp),
+        }
+        Box::new(writer)
+    } else if path.template().ends_with(".flac") {
+        Box::new(
+            FlacWriter::create(
+                stream_format,
+                path.render(SystemTime::now()),
+                flac_level(),
+                output_sample_format().map(|format| format.valid_bits() as u32),
+                Some(device),
+            )?,
+        )
+    } else if path.template().ends_with(".opus") || path.template().ends_with(".ogg") {
+        Box::new(
+            OpusWriter::create(
+                stream_format,
+                path.render(SystemTime::now()),
+                opus_settings(),
+                Some(device),
+            )?,
+        )
+    } else if let Some(channels) = split_channels(&stream_format) {
+        let start = SystemTime::now();
+        let comment = std::env::var(COMMENT_VAR).ok();
+        let metadata =
+            WavMetadata::for_recording(&stream_format, Some(device), comment.as_deref(), start);
+        Box::new(
+            ChannelSplitWriter::create(
+                stream_format,
+                &path,
+                channels.as_deref(),
+                output_sample_format(),
+                &metadata,
+            )?,
+        )
+    } else {
+        let start = SystemTime::now();
+        let comment = std::env::var(COMMENT_VAR).ok();
+        let metadata =
+            WavMetadata::for_recording(&stream_format, Some(device), comment.as_deref(), start);
+        match rollover() {
+            Some(rollover) => Box::new(
+                SegmentedWriter::create(
+                    stream_format,
+                    path,
+                    output_sample_format(),
+                    rollover,
+                    &metadata,
+                )?,
+            ),
+            None => Box::new(
+                Rf64Writer::create(
+                    stream_format,
+                    path.render(start),
+                    output_sample_format(),
+                    &metadata,
+                )?,
+            ),
+        }
+    };
+    let mut writers = vec![recorder];
+    let mut replay_handle = None;
+    if let Some(history) = replay_history() {
+        let replay = ReplayWriter::with_history(stream_format, history);
+        info!("Keeping {}s for replay; enter `replay [path]` to save it", history.as_secs());
+        replay_handle = Some(replay.handle());
+        writers.push(Box::new(replay));
+    }
+    let mut meter_handle = None;
+    let hub = live_hub(stream_format);
+    let meter = meter_settings();
+    if meter.is_some() || hub.is_some() {
+        // Live clients are sent the meter's readings, which are only logged with `AUDIA_METER`.
+        let settings = meter.unwrap_or(MeterSettings {
+            log_interval: None,
+            ..MeterSettings::default()
+        });
+        let meter = MeterWriter::with_settings(stream_format, settings, hub.clone());
+        info!("Metering levels; enter `levels` to show the latest reading");
+        meter_handle = Some(meter.handle());
+        writers.push(Box::new(meter));
+    }
+    // Audio piped in on stdin leaves no room for commands.
+    let stdin_is_audio = std::env::var(INPUT_VAR).map_or(false, |input| input == "-");
+    if (replay_handle.is_some() || meter_handle.is_some()) && !stdin_is_audio {
+        commands::listen_on_stdin(replay_handle, meter_handle);
+    }
+    if std::env::var_os(ASR_VAR).is_some() {
+        let mix = match std::env::var(ASR_MIX_VAR) {
+            Ok(mix) => Some(mix.parse().map_err(|e| anyhow!("{}: {}", ASR_MIX_VAR, e))?),
+            Err(_) => None,
+        };
+        writers.push(Box::new(ASRConnector::for_capture(stream_format, mix, hub.clone())?));
+    }
+    if let Some(hub) = hub {
+        writers.push(Box::new(LiveWriter::with_hub(hub)?));
+    }
+    Ok(match writers.len() {
+        1 => writers.remove(0),
+        _ => Box::new(TeeWriter::with_writers(writers)),
+    })
+}
+
 unsafe fn capture_output_stream(
     client: IAudioClientWrapper,
     stream_format: StreamFormat,
-) {
+    device: &str,
+) -> Result<(), anyhow::Error> {
     match stream_format.sample_format {
+        SampleFormat::U8 => {
+            client.record::<U8>().stream_to_sink(create_sink(stream_format, device)?);
+        }
+        SampleFormat::I24 => {
+            client.record::<I24>().stream_to_sink(create_sink(stream_format, device)?);
+        }
+        SampleFormat::I24In32 => {
+            client.record::<I24In32>().stream_to_sink(create_sink(stream_format, device)?);
+        }
         SampleFormat::F32 => {
-            let sink: Box<HoundWriter<f32>> = Box::new(HoundWriter::new(stream_format));
-            client.record::<f32>().stream_to_sink(sink);
+            client.record::<f32>().stream_to_sink(create_sink(stream_format, device)?);
         }
         SampleFormat::I32 => {
-            let sink: Box<HoundWriter<i32>> = Box::new(HoundWriter::new(stream_format));
-            client.record::<i32>().stream_to_sink(sink);
+            client.record::<i32>().stream_to_sink(create_sink(stream_format, device)?);
         }
         SampleFormat::I16 => {
-            let sink: Box<HoundWriter<i16>> = Box::new(HoundWriter::new(stream_format));
-            client.record::<i16>().stream_to_sink(sink);
+            client.record::<i16>().stream_to_sink(create_sink(stream_format, device)?);
+        }
+        SampleFormat::ALaw => {
+            client.record::<ALaw>().stream_to_sink(create_sink(stream_format, device)?);
+        }
+        SampleFormat::MuLaw => {
+            client.record::<MuLaw>().stream_to_sink(create_sink(stream_format, device)?);
+        }
+        // Neither comes from a device: nothing maps to u16 and `StreamFormat::from_raw` rejects
+        // 64-bit float.
+        SampleFormat::U16 | SampleFormat::F64 => {
+            bail!("Can't record {} samples", stream_format.sample_format)
         }
-        SampleFormat::U16 => unimplemented!(),
     };
+    Ok(())
 }
 
 fn create_client(device: Device) -> IAudioClientWrapper {
@@ -59,15 +426,153 @@ fn create_client(device: Device) -> IAudioClientWrapper {
     }
 }
 
+/// `audia repair <file>...`: fix the headers of WAV files left by a crashed recording.
+fn repair_files(paths: &[String]) -> bool {
+    if paths.is_empty() {
+        error!("Usage: audia repair <file>...");
+        return false;
+    }
+    let mut all_repaired = true;
+    for path in paths {
+        // Reading the header back checks the file is usable and says what it holds.
+        let repaired = repair::repair(Path::new(path)).and_then(|repair| {
+            let reader = WavReader::new(std::fs::File::open(path)?)?;
+            Ok((repair, reader.format()))
+        });
+        match repaired {
+            Ok((Repair::Intact { n_frames }, format)) => {
+                info!("{}: intact, {} frames of {}", path, n_frames, format)
+            }
+            Ok((
+                Repair::Repaired {
+                    n_frames,
+                    truncated,
+                    is_rf64,
+                },
+                format,
+            )) => info!(
+                "{}: repaired, {} frames of {}{}{}",
+                path,
+                n_frames,
+                format,
+                if is_rf64 { " as RF64" } else { "" },
+                if truncated > 0 {
+                    format!(", dropped {} bytes of a partial frame", truncated)
+                } else {
+                    String::new()
+                }
+            ),
+            Err(e) => {
+                error!("{}: {}", path, e);
+                all_repaired = false;
+            }
+        }
+    }
+    all_repaired
+}
+
+/// `audia receive <tcp|udp>://host:port`: record a stream sent from another machine, as if it
+/// had been captured here.
+fn receive_stream(endpoint: &str) -> Result<(), anyhow::Error> {
+    let endpoint: Endpoint = endpoint.parse()?;
+    let mut source = NetworkSource::listen(&endpoint)?;
+    let format = source.format();
+    let peer = source.peer().ip().to_string();
+    match format.sample_format {
+        SampleFormat::U8 => source.stream_to_sink::<U8>(create_sink(format, &peer)?),
+        SampleFormat::I24 => source.stream_to_sink::<I24>(create_sink(format, &peer)?),
+        SampleFormat::I24In32 => source.stream_to_sink::<I24In32>(create_sink(format, &peer)?),
+        SampleFormat::F32 => source.stream_to_sink::<f32>(create_sink(format, &peer)?),
+        SampleFormat::I32 => source.stream_to_sink::<i32>(create_sink(format, &peer)?),
+        SampleFormat::I16 => source.stream_to_sink::<i16>(create_sink(format, &peer)?),
+        SampleFormat::ALaw => source.stream_to_sink::<ALaw>(create_sink(format, &peer)?),
+        SampleFormat::MuLaw => source.stream_to_sink::<MuLaw>(create_sink(format, &peer)?),
+        SampleFormat::U16 | SampleFormat::F64 => {
+            bail!("Can't record {} samples", format.sample_format)
+        }
+    }
+}
+
+/// Record the raw samples at `input` as if they had been captured from a device.
+fn record_raw_input(input: &str) -> Result<(), anyhow::Error> {
+    let format: StreamFormat = match std::env::var(INPUT_FORMAT_VAR) {
+        Ok(format) => format.parse()?,
+        Err(_) => bail!("{} needs {}, e.g. `8000Hz:1ch:mulaw`", INPUT_VAR, INPUT_FORMAT_VAR),
+    };
+    let (reader, name): (Box<dyn Read>, String) = if input == "-" {
+        (Box::new(io::stdin()), "stdin".to_string())
+    } else {
+        let path = Path::new(input);
+        let file = File::open(path).map_err(|e| anyhow!("Couldn't open {}: {}", input, e))?;
+        let name = path.file_stem().unwrap_or_default().to_string_lossy().into_owned();
+        (Box::new(io::BufReader::new(file)), name)
+    };
+    info!("Reading {} from {}", format, input);
+    match format.sample_format {
+        SampleFormat::U8 => RawPcmSource::<_, U8>::new(reader, format)
+            .stream_to_sink(create_sink(format, &name)?),
+        SampleFormat::I24 => RawPcmSource::<_, I24>::new(reader, format)
+            .stream_to_sink(create_sink(format, &name)?),
+        SampleFormat::I24In32 => RawPcmSource::<_, I24In32>::new(reader, format)
+            .stream_to_sink(create_sink(format, &name)?),
+        SampleFormat::F32 => RawPcmSource::<_, f32>::new(reader, format)
+            .stream_to_sink(create_sink(format, &name)?),
+        SampleFormat::I32 => RawPcmSource::<_, i32>::new(reader, format)
+            .stream_to_sink(create_sink(format, &name)?),
+        SampleFormat::I16 => RawPcmSource::<_, i16>::new(reader, format)
+            .stream_to_sink(create_sink(format, &name)?),
+        SampleFormat::ALaw => RawPcmSource::<_, ALaw>::new(reader, format)
+            .stream_to_sink(create_sink(format, &name)?),
+        SampleFormat::MuLaw => RawPcmSource::<_, MuLaw>::new(reader, format)
+            .stream_to_sink(create_sink(format, &name)?),
+        SampleFormat::U16 | SampleFormat::F64 => {
+            bail!("Can't record {} samples", format.sample_format)
+        }
+    }
+}
+
 #[tokio::main]
 async fn main() {
     env_logger::init();
+    let args: Vec<String> = std::env::args().collect();
+    if args.get(1).map(String::as_str) == Some("repair") {
+        std::process::exit(if repair_files(&args[2..]) { 0 } else { 1 });
+    }
+    if args.get(1).map(String::as_str) == Some("receive") {
+        let endpoint = match args.get(2) {
+            Some(endpoint) => endpoint,
+            None => {
+                error!("Usage: audia receive <tcp|udp>://host:port");
+                std::process::exit(1);
+            }
+        };
+        if let Err(e) = receive_stream(endpoint) {
+            error!("{}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+    if let Ok(input) = std::env::var(INPUT_VAR) {
+        if let Err(e) = record_raw_input(&input) {
+            error!("{}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
     let device = Device::new();
     info!("Device: {}", device.name);
+    let device_name = device.name.clone();
     let client = create_client(device);
-    let stream_format = client.get_format().unwrap();
+    let stream_format = match client.get_format() {
+        Ok(stream_format) => stream_format,
+        Err(e) => {
+            error!("Can't record from this device: {}", e);
+            return;
+        }
+    };
     debug!("Stream Format; {:?}", stream_format);
-    unsafe {
-        capture_output_stream(client, stream_format);
+    if let Err(e) = unsafe { capture_output_stream(client, stream_format, &device_name) } {
+        error!("{}", e);
+        std::process::exit(1);
     }
 }
@@ -0,0 +1,218 @@
+//! Streaming captured samples between machines.
+//!
+//! Every packet carries the stream's format, so a receiver can join a UDP stream at any point.
+//! A packet is a fixed header, the `fmt ` chunk body describing the samples and the samples
+//! themselves:
+//!
+//! | bytes | field                                           |
+//! |-------|-------------------------------------------------|
+//! | 4     | `AUDN`                                          |
+//! | 1     | protocol version                                |
+//! | 1     | flags, bit 0 marks the end of the stream        |
+//! | 2     | length of the `fmt ` chunk body                 |
+//! | 4     | sequence number, counting packets from zero     |
+//! | 8     | frames sent before this packet                  |
+//! | 4     | length of the samples in bytes                  |
+//!
+//! Integers are little-endian. Over TCP packets follow each other on the stream; over UDP each
+//! datagram holds one packet.
+
+use std::fmt;
+use std::io::{ErrorKind, Read};
+use std::str::FromStr;
+
+use anyhow::Error;
+
+use crate::stream_format::StreamFormat;
+use crate::wav::{fmt_chunk, parse_fmt};
+
+const MAGIC: [u8; 4] = *b"AUDN";
+const VERSION: u8 = 1;
+const END_OF_STREAM: u8 = 1;
+const HEADER_SIZE: usize = 24;
+
+/// Largest `fmt ` chunk body, that of `WAVEFORMATEXTENSIBLE`.
+const MAX_FMT_SIZE: usize = 40;
+
+/// Port used when none is configured.
+pub(crate) const DEFAULT_PORT: u16 = 5750;
+
+/// Largest packet of samples a receiver accepts, well over a capture packet.
+const MAX_SAMPLES_SIZE: u32 = 16 << 20;
+
+/// Samples per UDP datagram, keeping packets within a typical Ethernet MTU.
+pub(crate) const MAX_DATAGRAM_PAYLOAD: usize = 1400;
+
+/// Largest UDP datagram a receiver accepts.
+pub(crate) const MAX_DATAGRAM_SIZE: usize = 65_507;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Transport {
+    Tcp,
+    Udp,
+}
+
+/// Where to stream to or listen on, written `tcp://host:port` or `udp://host:port`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct Endpoint {
+    pub(crate) transport: Transport,
+    /// `host:port`, resolved when connecting.
+    pub(crate) address: String,
+}
+
+impl FromStr for Endpoint {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (transport, address) = if let Some(address) = s.strip_prefix("tcp://") {
+            (Transport::Tcp, address)
+        } else if let Some(address) = s.strip_prefix("udp://") {
+            (Transport::Udp, address)
+        } else {
+            bail!("Expected `tcp://host:port` or `udp://host:port`, got `{}`", s);
+        };
+        if !address.contains(':') {
+            bail!("`{}` has no port", s);
+        }
+        Ok(Endpoint {
+            transport,
+            address: address.to_string(),
+        })
+    }
+}
+
+impl fmt::Display for Endpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.transport {
+            Transport::Tcp => write!(f, "tcp://{}", self.address),
+            Transport::Udp => write!(f, "udp://{}", self.address),
+        }
+    }
+}
+
+/// One packet of interleaved samples.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct Packet {
+    pub(crate) format: StreamFormat,
+    pub(crate) sequence: u32,
+    /// Frames sent before this packet, so a receiver knows how much a lost packet held.
+    pub(crate) first_frame: u64,
+    pub(crate) end_of_stream: bool,
+    pub(crate) samples: Vec<u8>,
+}
+
+/// Encode a packet of `samples` in `format`.
+pub(crate) fn encode_packet(
+    format: &StreamFormat,
+    sequence: u32,
+    first_frame: u64,
+    end_of_stream: bool,
+    samples: &[u8],
+) -> Vec<u8> {
+    let fmt = fmt_chunk(format);
+    let mut packet = Vec::with_capacity(HEADER_SIZE + fmt.len() + samples.len());
+    packet.extend_from_slice(&MAGIC);
+    packet.push(VERSION);
+    packet.push(if end_of_stream { END_OF_STREAM } else { 0 });
+    packet.extend_from_slice(&(fmt.len() as u16).to_le_bytes());
+    packet.extend_from_slice(&sequence.to_le_bytes());
+    packet.extend_from_slice(&first_frame.to_le_bytes());
+    packet.extend_from_slice(&(samples.len() as u32).to_le_bytes());
+    packet.extend_from_slice(&fmt);
+    packet.extend_from_slice(samples);
+    packet
+}
+
+/// Read the next packet, or None if the input ends cleanly between packets.
+pub(crate) fn read_packet<R: Read>(input: &mut R) -> Result<Option<Packet>, Error> {
+    let mut header = [0u8; HEADER_SIZE];
+    match input.read_exact(&mut header) {
+        Ok(()) => {}
+        Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e.into()),
+    }
+    if header[0..4] != MAGIC {
+        bail!("Not an audio stream packet");
+    }
+    if header[4] != VERSION {
+        bail!("Unsupported stream protocol version {}", header[4]);
+    }
+    let fmt_len = u16::from_le_bytes([header[6], header[7]]) as usize;
+    if fmt_len > MAX_FMT_SIZE {
+        bail!("Packet format is {} bytes", fmt_len);
+    }
+    let mut first_frame = [0u8; 8];
+    first_frame.copy_from_slice(&header[12..20]);
+    let samples_len = u32::from_le_bytes([header[20], header[21], header[22], header[23]]);
+    if samples_len > MAX_SAMPLES_SIZE {
+        bail!("Packet holds {} bytes of samples", samples_len);
+    }
+    let mut fmt = vec![0u8; fmt_len];
+    input.read_exact(&mut fmt)?;
+    let mut samples = vec![0u8; samples_len as usize];
+    input.read_exact(&mut samples)?;
+    Ok(Some(Packet {
+        format: parse_fmt(&fmt)?,
+        sequence: u32::from_le_bytes([header[8], header[9], header[10], header[11]]),
+        first_frame: u64::from_le_bytes(first_frame),
+        end_of_stream: header[5] & END_OF_STREAM != 0,
+        samples,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::channel_layout::ChannelLayout;
+    use crate::stream_format::SampleFormat;
+
+    #[test]
+    fn packets_round_trip() {
+        let stereo = StreamFormat::new(SampleFormat::I16, 48000, ChannelLayout::new(2, 0x3));
+        let surround = StreamFormat::new(SampleFormat::F32, 44100, ChannelLayout::new(6, 0x60f));
+        let samples: Vec<u8> = (0..=255).collect();
+        let mut stream = encode_packet(&stereo, 7, 4800, false, &samples);
+        stream.extend(encode_packet(&surround, u32::MAX, 1 << 40, true, &[]));
+
+        let mut input = &stream[..];
+        let first = read_packet(&mut input).unwrap().unwrap();
+        assert_eq!(
+            first,
+            Packet {
+                format: stereo,
+                sequence: 7,
+                first_frame: 4800,
+                end_of_stream: false,
+                samples,
+            }
+        );
+        let last = read_packet(&mut input).unwrap().unwrap();
+        assert_eq!(last.format, surround);
+        assert_eq!(last.sequence, u32::MAX);
+        assert_eq!(last.first_frame, 1 << 40);
+        assert!(last.end_of_stream);
+        assert!(last.samples.is_empty());
+        assert_eq!(read_packet(&mut input).unwrap(), None);
+    }
+
+    #[test]
+    fn rejects_other_data() {
+        let stereo = StreamFormat::new(SampleFormat::I16, 48000, ChannelLayout::new(2, 0x3));
+        let mut packet = encode_packet(&stereo, 0, 0, false, &[0; 4]);
+        packet[0] = b'X';
+        assert!(read_packet(&mut &packet[..]).is_err());
+        packet[0] = b'A';
+        packet[4] = VERSION + 1;
+        assert!(read_packet(&mut &packet[..]).is_err());
+    }
+
+    #[test]
+    fn parses_endpoints() {
+        let endpoint: Endpoint = "udp://10.0.0.2:5750".parse().unwrap();
+        assert_eq!(endpoint.transport, Transport::Udp);
+        assert_eq!(endpoint.address, "10.0.0.2:5750");
+        assert_eq!(endpoint.to_string(), "udp://10.0.0.2:5750");
+        assert!("tcp://10.0.0.2".parse::<Endpoint>().is_err());
+        assert!("http://10.0.0.2:80".parse::<Endpoint>().is_err());
+    }
+}
@@ -0,0 +1,193 @@
+use std::str::FromStr;
+
+use anyhow::Error;
+use audiopus::coder::Encoder;
+use audiopus::{Application, Bitrate, Channels, SampleRate};
+
+use crate::channel_layout::ChannelLayout;
+use crate::dsp::pipeline::FormatConverter;
+use crate::stream_format::{Sample, SampleFormat, StreamFormat};
+
+/// Opus always runs at 48kHz; other capture rates are resampled.
+pub(crate) const OPUS_RATE: u32 = 48_000;
+
+/// Largest packet libopus produces for one frame.
+const MAX_PACKET_BYTES: usize = 4000;
+
+/// Encoder settings.
+///
+/// Written as comma separated `key=value` pairs, e.g. `bitrate=24k,frame=20,application=voip`.
+/// `bitrate` is in bits per second with an optional `k`, `frame` is the frame duration in
+/// milliseconds and `application` is `voip`, `audio` or `lowdelay`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) struct OpusSettings {
+    /// None leaves the choice to the encoder.
+    pub(crate) bitrate: Option<u32>,
+    /// Samples per channel in each frame, at 48kHz.
+    pub(crate) frame_size: usize,
+    pub(crate) application: Application,
+}
+
+impl Default for OpusSettings {
+    fn default() -> Self {
+        OpusSettings {
+            bitrate: None,
+            frame_size: 960,
+            application: Application::Audio,
+        }
+    }
+}
+
+impl FromStr for OpusSettings {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut settings = OpusSettings::default();
+        for setting in s.split(',').map(str::trim).filter(|setting| !setting.is_empty()) {
+            let (key, value) = match setting.find('=') {
+                Some(split) => (&setting[..split], setting[split + 1..].trim()),
+                None => bail!("Opus setting `{}` should be `key=value`", setting),
+            };
+            match key.trim().to_ascii_lowercase().as_str() {
+                "bitrate" => {
+                    let lower = value.to_ascii_lowercase();
+                    let bitrate = match lower.strip_suffix('k') {
+                        Some(kbps) => kbps.parse::<u32>().map(|kbps| kbps * 1000),
+                        None => lower.parse::<u32>(),
+                    }
+                    .map_err(|_| anyhow!("Opus bitrate `{}` isn't a number", value))?;
+                    if !(500..=512_000).contains(&bitrate) {
+                        bail!("Opus bitrate must be between 500 and 512000, got {}", bitrate);
+                    }
+                    settings.bitrate = Some(bitrate);
+                }
+                "frame" => {
+                    settings.frame_size = match value {
+                        "2.5" => 120,
+                        "5" => 240,
+                        "10" => 480,
+                        "20" => 960,
+                        "40" => 1920,
+                        "60" => 2880,
+                        _ => bail!("Opus frames are 2.5, 5, 10, 20, 40 or 60ms, not {}", value),
+                    }
+                }
+                "application" => {
+                    settings.application = match value.to_ascii_lowercase().as_str() {
+                        "voip" => Application::Voip,
+                        "audio" => Application::Audio,
+                        "lowdelay" => Application::LowDelay,
+                        _ => bail!("Unknown Opus application `{}`", value),
+                    }
+                }
+                _ => bail!("Unknown Opus setting `{}`", key),
+            }
+        }
+        Ok(settings)
+    }
+}
+
+/// An encoded frame, with the number of 48kHz samples per channel it decodes to.
+pub(crate) struct OpusPacket {
+    pub(crate) data: Vec<u8>,
+    pub(crate) samples: usize,
+}
+
+/// Turns captured audio into Opus packets.
+///
+/// libopus only takes mono or stereo at a handful of rates, so the capture is resampled to
+/// 48kHz and anything wider than stereo is downmixed first.
+pub(crate) struct OpusEncoder {
+    converter: FormatConverter,
+    encoder: Encoder,
+    settings: OpusSettings,
+    n_channels: usize,
+    /// Converted samples not yet making up a whole frame.
+    pending: Vec<f32>,
+    /// Whether any audio has been encoded, so `flush` knows if there's a stream to finish.
+    started: bool,
+}
+
+impl OpusEncoder {
+    pub(crate) fn new(source: StreamFormat, settings: OpusSettings) -> Result<Self, Error> {
+        let (layout, channels) = if source.n_channels == 1 {
+            (ChannelLayout::MONO, Channels::Mono)
+        } else {
+            (ChannelLayout::STEREO, Channels::Stereo)
+        };
+        let target = StreamFormat::new(SampleFormat::F32, OPUS_RATE, layout);
+        let mut encoder = Encoder::new(SampleRate::Hz48000, channels, settings.application)?;
+        if let Some(bitrate) = settings.bitrate {
+            encoder.set_bitrate(Bitrate::BitsPerSecond(bitrate as i32))?;
+        }
+        Ok(OpusEncoder {
+            converter: FormatConverter::new(source, target),
+            encoder,
+            settings,
+            n_channels: layout.n_channels() as usize,
+            pending: Vec::new(),
+            started: false,
+        })
+    }
+
+    pub(crate) fn n_channels(&self) -> usize {
+        self.n_channels
+    }
+
+    /// Samples per channel the decoder should drop from the start of the stream.
+    pub(crate) fn pre_skip(&self) -> Result<u32, Error> {
+        Ok(self.encoder.lookahead()?)
+    }
+
+    /// Encode interleaved capture samples, returning every frame that is complete.
+    pub(crate) fn encode<S: Sample>(&mut self, input: &[S]) -> Result<Vec<OpusPacket>, Error> {
+        let converted: Vec<f32> = self.converter.convert(input);
+        self.started |= !converted.is_empty();
+        self.pending.extend(converted);
+        self.encode_pending()
+    }
+
+    /// Encode everything still buffered, padding the last frame with silence.
+    ///
+    /// The encoder's output lags its input by the pre-skip, so that much silence is encoded
+    /// after the audio to push the last real samples out. It counts towards the last packet's
+    /// `samples`, making the final granule position pre-skip plus the frames encoded.
+    pub(crate) fn flush(&mut self) -> Result<Vec<OpusPacket>, Error> {
+        let rest: Vec<f32> = self.converter.flush();
+        self.started |= !rest.is_empty();
+        if !self.started {
+            return Ok(vec![]);
+        }
+        self.pending.extend(rest);
+        let delay = self.pre_skip()? as usize * self.n_channels;
+        self.pending.resize(self.pending.len() + delay, 0.0);
+        let frame_len = self.settings.frame_size * self.n_channels;
+        let remainder = self.pending.len() % frame_len;
+        if remainder == 0 {
+            return self.encode_pending();
+        }
+        let real_samples = remainder / self.n_channels;
+        self.pending.resize(self.pending.len() + frame_len - remainder, 0.0);
+        let mut packets = self.encode_pending()?;
+        if let Some(last) = packets.last_mut() {
+            last.samples = real_samples;
+        }
+        Ok(packets)
+    }
+
+    fn encode_pending(&mut self) -> Result<Vec<OpusPacket>, Error> {
+        let frame_len = self.settings.frame_size * self.n_channels;
+        let n_frames = self.pending.len() / frame_len;
+        let mut packets = Vec::with_capacity(n_frames);
+        let mut output = [0u8; MAX_PACKET_BYTES];
+        for frame in self.pending.chunks_exact(frame_len) {
+            let len = self.encoder.encode_float(frame, &mut output)?;
+            packets.push(OpusPacket {
+                data: output[..len].to_vec(),
+                samples: self.settings.frame_size,
+            });
+        }
+        self.pending.drain(..n_frames * frame_len);
+        Ok(packets)
+    }
+}
@@ -0,0 +1,270 @@
+//! RTP (RFC 3550) packets, RTCP reports and SDP descriptions for streaming to media tools.
+
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::net::SocketAddr;
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::Error;
+
+use crate::opus::{OpusSettings, OPUS_RATE};
+use crate::stream_format::StreamFormat;
+
+const VERSION: u8 = 2;
+
+/// First payload type free for dynamic use, see RFC 3551.
+pub(crate) const DYNAMIC_PAYLOAD_TYPE: u8 = 96;
+
+/// Largest RTP payload, keeping packets within a typical Ethernet MTU.
+pub(crate) const MAX_PAYLOAD: usize = 1400;
+
+const SENDER_REPORT: u8 = 200;
+const SOURCE_DESCRIPTION: u8 = 202;
+const GOODBYE: u8 = 203;
+const CNAME: u8 = 1;
+
+/// Seconds from the NTP epoch, 1900, to the Unix epoch.
+const NTP_UNIX_OFFSET: u64 = 2_208_988_800;
+
+/// How samples are carried.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum RtpPayload {
+    /// 16-bit big-endian PCM at the capture's rate and channel count (RFC 3551).
+    L16,
+    /// Opus at 48kHz (RFC 7587).
+    Opus(OpusSettings),
+}
+
+impl FromStr for RtpPayload {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "l16" => Ok(RtpPayload::L16),
+            "opus" => Ok(RtpPayload::Opus(OpusSettings::default())),
+            _ => Err(anyhow!("Unknown RTP payload `{}`, expected `l16` or `opus`", s)),
+        }
+    }
+}
+
+impl RtpPayload {
+    /// Payload type for streams of `format`. Only L16 at 44.1kHz has a static type.
+    pub(crate) fn payload_type(&self, format: &StreamFormat) -> u8 {
+        match (self, format.n_sample_per_sec, format.n_channels) {
+            (RtpPayload::L16, 44_100, 2) => 10,
+            (RtpPayload::L16, 44_100, 1) => 11,
+            _ => DYNAMIC_PAYLOAD_TYPE,
+        }
+    }
+
+    /// Ticks per second of the RTP timestamp.
+    pub(crate) fn clock_rate(&self, format: &StreamFormat) -> u32 {
+        match self {
+            RtpPayload::L16 => format.n_sample_per_sec,
+            RtpPayload::Opus(_) => OPUS_RATE,
+        }
+    }
+}
+
+/// A random identifier, as RFC 3550 asks of SSRCs and initial sequence numbers.
+pub(crate) fn random_u32() -> u32 {
+    // Each `RandomState` is seeded afresh by the OS.
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u128(
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|since_epoch| since_epoch.as_nanos())
+            .unwrap_or(0),
+    );
+    hasher.finish() as u32
+}
+
+/// The 12 byte fixed header of an RTP packet.
+pub(crate) fn rtp_header(
+    payload_type: u8,
+    marker: bool,
+    sequence: u16,
+    timestamp: u32,
+    ssrc: u32,
+) -> [u8; 12] {
+    let mut header = [0u8; 12];
+    header[0] = VERSION << 6;
+    header[1] = (marker as u8) << 7 | payload_type & 0x7f;
+    header[2..4].copy_from_slice(&sequence.to_be_bytes());
+    header[4..8].copy_from_slice(&timestamp.to_be_bytes());
+    header[8..12].copy_from_slice(&ssrc.to_be_bytes());
+    header
+}
+
+/// `time` as a 64-bit NTP timestamp: seconds since 1900 and a 32-bit fraction.
+pub(crate) fn ntp_timestamp(time: SystemTime) -> u64 {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let fraction = ((since_epoch.subsec_nanos() as u64) << 32) / 1_000_000_000;
+    (since_epoch.as_secs() + NTP_UNIX_OFFSET) << 32 | fraction
+}
+
+/// What a sender report says about the stream so far.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct SenderStats {
+    pub(crate) ssrc: u32,
+    /// RTP timestamp matching `ntp_time`.
+    pub(crate) rtp_time: u32,
+    pub(crate) ntp_time: u64,
+    pub(crate) packet_count: u32,
+    /// Payload bytes sent, not counting headers.
+    pub(crate) octet_count: u32,
+}
+
+/// A compound RTCP packet: a sender report and the source's canonical name, followed by a
+/// goodbye when `leaving`.
+pub(crate) fn rtcp_report(stats: &SenderStats, cname: &str, leaving: bool) -> Vec<u8> {
+    let mut report = Vec::with_capacity(80);
+    rtcp_header(&mut report, 0, SENDER_REPORT, 6);
+    report.extend_from_slice(&stats.ssrc.to_be_bytes());
+    report.extend_from_slice(&stats.ntp_time.to_be_bytes());
+    report.extend_from_slice(&stats.rtp_time.to_be_bytes());
+    report.extend_from_slice(&stats.packet_count.to_be_bytes());
+    report.extend_from_slice(&stats.octet_count.to_be_bytes());
+
+    let cname = &cname.as_bytes()[..cname.len().min(255)];
+    // SSRC, the item and a terminating zero, padded to 32 bits.
+    let sdes_len = (4 + 2 + cname.len() + 1 + 3) / 4 * 4;
+    let start = report.len();
+    rtcp_header(&mut report, 1, SOURCE_DESCRIPTION, sdes_len / 4);
+    report.extend_from_slice(&stats.ssrc.to_be_bytes());
+    report.push(CNAME);
+    report.push(cname.len() as u8);
+    report.extend_from_slice(cname);
+    report.resize(start + 4 + sdes_len, 0);
+
+    if leaving {
+        rtcp_header(&mut report, 1, GOODBYE, 1);
+        report.extend_from_slice(&stats.ssrc.to_be_bytes());
+    }
+    report
+}
+
+/// `count` is the report count or source count; `words` is the length after the header.
+fn rtcp_header(output: &mut Vec<u8>, count: u8, packet_type: u8, words: usize) {
+    output.push(VERSION << 6 | count);
+    output.push(packet_type);
+    output.extend_from_slice(&(words as u16).to_be_bytes());
+}
+
+/// Session description for a stream from `source` to `destination`, for receivers such as
+/// `ffplay -protocol_whitelist file,udp,rtp stream.sdp`.
+pub(crate) fn sdp(
+    payload: &RtpPayload,
+    format: &StreamFormat,
+    packet_frames: usize,
+    source: SocketAddr,
+    destination: SocketAddr,
+    session_name: &str,
+) -> String {
+    let address = |address: SocketAddr| match address {
+        SocketAddr::V4(v4) => format!("IN IP4 {}", v4.ip()),
+        SocketAddr::V6(v6) => format!("IN IP6 {}", v6.ip()),
+    };
+    let payload_type = payload.payload_type(format);
+    let clock_rate = payload.clock_rate(format);
+    let session_id = ntp_timestamp(SystemTime::now()) >> 32;
+    let mut sdp = String::new();
+    sdp.push_str("v=0\r\n");
+    sdp.push_str(&format!("o=- {} 1 {}\r\n", session_id, address(source)));
+    sdp.push_str(&format!("s={}\r\n", session_name));
+    sdp.push_str(&format!("c={}\r\n", address(destination)));
+    sdp.push_str("t=0 0\r\n");
+    sdp.push_str(&format!("m=audio {} RTP/AVP {}\r\n", destination.port(), payload_type));
+    match payload {
+        RtpPayload::L16 if format.n_channels == 1 => {
+            sdp.push_str(&format!("a=rtpmap:{} L16/{}\r\n", payload_type, clock_rate))
+        }
+        RtpPayload::L16 => sdp.push_str(&format!(
+            "a=rtpmap:{} L16/{}/{}\r\n",
+            payload_type, clock_rate, format.n_channels
+        )),
+        RtpPayload::Opus(_) => {
+            // Opus is always declared with two channels; `stereo` says what's really sent.
+            let stereo = (format.n_channels > 1) as u8;
+            sdp.push_str(&format!("a=rtpmap:{} opus/{}/2\r\n", payload_type, OPUS_RATE));
+            sdp.push_str(&format!(
+                "a=fmtp:{} stereo={}; sprop-stereo={}\r\n",
+                payload_type, stereo, stereo
+            ));
+        }
+    }
+    let ptime = packet_frames as f64 * 1000.0 / clock_rate as f64;
+    sdp.push_str(&format!("a=ptime:{}\r\n", ptime));
+    sdp.push_str("a=sendonly\r\n");
+    sdp
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::channel_layout::ChannelLayout;
+    use crate::stream_format::SampleFormat;
+
+    #[test]
+    fn writes_rtp_headers() {
+        let header = rtp_header(96, true, 0xfffe, 0x0102_0304, 0xdead_beef);
+        assert_eq!(header, [0x80, 0xe0, 0xff, 0xfe, 1, 2, 3, 4, 0xde, 0xad, 0xbe, 0xef]);
+        assert_eq!(rtp_header(10, false, 1, 0, 0)[..2], [0x80, 10]);
+    }
+
+    #[test]
+    fn converts_to_ntp_time() {
+        let time = UNIX_EPOCH + std::time::Duration::from_millis(1500);
+        assert_eq!(ntp_timestamp(time), ((NTP_UNIX_OFFSET + 1) << 32) | (1 << 31));
+    }
+
+    #[test]
+    fn writes_compound_rtcp_reports() {
+        let stats = SenderStats {
+            ssrc: 7,
+            rtp_time: 1000,
+            ntp_time: 1 << 32,
+            packet_count: 3,
+            octet_count: 2880,
+        };
+        // A 28 byte sender report, then the source description: SSRC, CNAME type and length,
+        // five bytes of name and a terminating zero, padded to 12 bytes.
+        let report = rtcp_report(&stats, "audia", false);
+        assert_eq!(report.len(), 28 + 16);
+        assert_eq!(report[..4], [0x80, SENDER_REPORT, 0, 6]);
+        assert_eq!(report[20..28], [0, 0, 0, 3, 0, 0, 0x0b, 0x40]);
+        assert_eq!(report[28..32], [0x81, SOURCE_DESCRIPTION, 0, 3]);
+        assert_eq!(report[36..43], [CNAME, 5, b'a', b'u', b'd', b'i', b'a']);
+        assert!(report[43..].iter().all(|&byte| byte == 0));
+
+        let report = rtcp_report(&stats, "audia", true);
+        assert_eq!(report.len(), 28 + 16 + 8);
+        assert_eq!(report[44..], [0x81, GOODBYE, 0, 1, 0, 0, 0, 7]);
+    }
+
+    #[test]
+    fn describes_opus_streams() {
+        let format = StreamFormat::new(SampleFormat::F32, 44100, ChannelLayout::new(2, 0x3));
+        let source = "192.168.1.2:40000".parse().unwrap();
+        let destination = "239.1.2.3:5004".parse().unwrap();
+        let payload = RtpPayload::Opus(OpusSettings::default());
+        let sdp = sdp(&payload, &format, 960, source, destination, "Audia");
+        let lines: Vec<&str> = sdp.split_terminator("\r\n").collect();
+        assert_eq!(lines[0], "v=0");
+        assert!(lines[1].starts_with("o=- ") && lines[1].ends_with(" 1 IN IP4 192.168.1.2"));
+        assert_eq!(
+            lines[2..],
+            [
+                "s=Audia",
+                "c=IN IP4 239.1.2.3",
+                "t=0 0",
+                "m=audio 5004 RTP/AVP 96",
+                "a=rtpmap:96 opus/48000/2",
+                "a=fmtp:96 stereo=1; sprop-stereo=1",
+                "a=ptime:20",
+                "a=sendonly",
+            ]
+        );
+    }
+}
@@ -0,0 +1,12 @@
+use crate::stream_format::Sample;
+
+pub(crate) mod network;
+pub(crate) mod raw_pcm;
+
+/// Samples from their little-endian bytes. A trailing partial sample is ignored.
+pub(crate) fn decode_samples<T: Sample>(bytes: &[u8]) -> Vec<T> {
+    bytes
+        .chunks_exact(T::FORMAT.sample_size())
+        .map(|sample| unsafe { std::ptr::read_unaligned(sample.as_ptr() as *const T) })
+        .collect()
+}
@@ -0,0 +1,323 @@
+use std::io::ErrorKind;
+use std::net::{SocketAddr, TcpListener, TcpStream, UdpSocket};
+use std::time::Duration;
+
+use anyhow::Error;
+use log::{debug, info, warn};
+use serde::Serialize;
+
+use crate::buffer::{ExtensibleBuffer, BUFFER_LENGTH};
+use crate::network::{read_packet, Endpoint, Packet, Transport, MAX_DATAGRAM_SIZE};
+use crate::source::decode_samples;
+use crate::stream_format;
+use crate::stream_format::StreamFormat;
+use crate::writer::{feed, AudioWriter};
+
+/// A UDP sender that stays silent this long is taken to have gone.
+const UDP_IDLE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Longest gap from lost packets that is filled with silence. Longer gaps mean the sender
+/// restarted rather than lost a few packets, so they're skipped.
+const MAX_GAP_SECS: u64 = 10;
+
+enum Input {
+    Tcp(TcpStream),
+    Udp(UdpSocket),
+}
+
+/// Receives a stream sent by `NetworkWriter`, so one machine can record another's capture.
+///
+/// Packets lost over UDP are replaced with silence, keeping the recording in time with the
+/// sender.
+pub(crate) struct NetworkSource {
+    input: Input,
+    peer: SocketAddr,
+    format: StreamFormat,
+    /// The first packet, read to learn the format.
+    first: Option<Packet>,
+    lost_packets: u64,
+}
+
+impl NetworkSource {
+    /// Listen on `endpoint` and wait for a sender. Over TCP only the first connection is taken.
+    pub(crate) fn listen(endpoint: &Endpoint) -> Result<Self, Error> {
+        info!("Waiting for a stream on {}", endpoint);
+        let (input, peer) = match endpoint.transport {
+            Transport::Tcp => {
+                let listener = TcpListener::bind(&endpoint.address)
+                    .map_err(|e| anyhow!("Couldn't listen on {}: {}", endpoint, e))?;
+                let (stream, peer) = listener.accept()?;
+                (Input::Tcp(stream), peer)
+            }
+            Transport::Udp => {
+                let socket = UdpSocket::bind(&endpoint.address)
+                    .map_err(|e| anyhow!("Couldn't listen on {}: {}", endpoint, e))?;
+                // Windows fails peeks into a buffer smaller than the datagram.
+                let (_, peer) = socket.peek_from(&mut vec![0u8; MAX_DATAGRAM_SIZE])?;
+                (Input::Udp(socket), peer)
+            }
+        };
+        NetworkSource::start(input, peer)
+    }
+
+    /// Take the stream from `peer` on `input`, reading its first packet to learn the format.
+    fn start(mut input: Input, peer: SocketAddr) -> Result<Self, Error> {
+        let first = match next_packet(&mut input, peer)? {
+            Some(packet) => packet,
+            None => bail!("{} closed the stream before sending anything", peer),
+        };
+        if let Input::Udp(ref socket) = input {
+            socket.set_read_timeout(Some(UDP_IDLE_TIMEOUT))?;
+        }
+        info!("Receiving {} from {}", first.format, peer);
+        Ok(NetworkSource {
+            input,
+            peer,
+            format: first.format,
+            first: Some(first),
+            lost_packets: 0,
+        })
+    }
+
+    pub(crate) fn format(&self) -> StreamFormat {
+        self.format
+    }
+
+    pub(crate) fn peer(&self) -> SocketAddr {
+        self.peer
+    }
+
+    /// Feed the stream to `sink` until the sender ends it, closing the sink at the end.
+    pub(crate) fn stream_to_sink<T>(
+        &mut self,
+        mut sink: Box<dyn AudioWriter<T>>,
+    ) -> Result<(), Error>
+        where
+            T: hound::Sample + stream_format::Sample + Serialize + Copy,
+    {
+        if T::FORMAT != self.format.sample_format {
+            bail!("Can't receive {} samples as {}", self.format.sample_format, T::FORMAT);
+        }
+        let n_channels = self.format.n_channels as usize;
+        let block_align = self.format.n_block_align as usize;
+        let max_gap = self.format.n_sample_per_sec as u64 * MAX_GAP_SECS;
+        let mut buffer: Option<ExtensibleBuffer<T>> = None;
+        let mut next_sequence = None;
+        let mut next_frame = 0;
+        loop {
+            let packet = match self.first.take() {
+                Some(packet) => packet,
+                None => match next_packet(&mut self.input, self.peer)? {
+                    Some(packet) => packet,
+                    None => break,
+                },
+            };
+            if packet.format != self.format {
+                bail!(
+                    "{} changed the stream from {} to {}",
+                    self.peer,
+                    self.format,
+                    packet.format
+                );
+            }
+            let expected = next_sequence.unwrap_or(packet.sequence);
+            let lost = packet.sequence.wrapping_sub(expected);
+            if lost > u32::MAX / 2 {
+                debug!("Dropping packet {} from {}, which came late", packet.sequence, self.peer);
+                continue;
+            }
+            if lost > 0 {
+                self.lost_packets += lost as u64;
+                let gap = packet.first_frame.saturating_sub(next_frame);
+                if gap <= max_gap {
+                    warn!("Lost {} packets from {}, filling {} frames", lost, self.peer, gap);
+                    let chunk_frames = gap.min((BUFFER_LENGTH / n_channels) as u64);
+                    let silence = <T as stream_format::Sample>::from(&0i16);
+                    let silence = vec![silence; chunk_frames as usize * n_channels];
+                    let mut remaining = gap;
+                    while remaining > 0 {
+                        let frames = remaining.min(chunk_frames);
+                        let samples = &silence[..frames as usize * n_channels];
+                        feed(&mut buffer, samples, n_channels, sink.as_mut())?;
+                        remaining -= frames;
+                    }
+                } else {
+                    warn!("Lost {} packets from {}, skipping {} frames", lost, self.peer, gap);
+                }
+            }
+            let n_frames = packet.samples.len() / block_align;
+            next_sequence = Some(packet.sequence.wrapping_add(1));
+            next_frame = packet.first_frame + n_frames as u64;
+            if n_frames > 0 {
+                let samples: Vec<T> = decode_samples(&packet.samples[..n_frames * block_align]);
+                feed(&mut buffer, &samples, n_channels, sink.as_mut())?;
+            }
+            if packet.end_of_stream {
+                break;
+            }
+        }
+        if self.lost_packets > 0 {
+            warn!("{} packets from {} were lost", self.lost_packets, self.peer);
+        }
+        info!("{} ended the stream", self.peer);
+        sink.close()
+    }
+}
+
+/// The next packet from `peer`, or None once the sender has gone.
+fn next_packet(input: &mut Input, peer: SocketAddr) -> Result<Option<Packet>, Error> {
+    match input {
+        Input::Tcp(stream) => match read_packet(stream) {
+            Err(e) if is_disconnect(&e) => {
+                warn!("Lost the connection to {}: {}", peer, e);
+                Ok(None)
+            }
+            packet => packet,
+        },
+        Input::Udp(socket) => {
+            let mut datagram = vec![0u8; MAX_DATAGRAM_SIZE];
+            loop {
+                let (len, from) = match socket.recv_from(&mut datagram) {
+                    Ok(received) => received,
+                    // Timeouts are `WouldBlock` on Unix and `TimedOut` on Windows.
+                    Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                        warn!(
+                            "Nothing from {} for {}s, ending the stream",
+                            peer,
+                            UDP_IDLE_TIMEOUT.as_secs()
+                        );
+                        return Ok(None);
+                    }
+                    Err(e) => return Err(e.into()),
+                };
+                if from != peer {
+                    debug!("Ignoring a datagram from {}", from);
+                    continue;
+                }
+                match read_packet(&mut &datagram[..len]) {
+                    Ok(Some(packet)) => return Ok(Some(packet)),
+                    Ok(None) => debug!("Ignoring a truncated datagram from {}", from),
+                    Err(e) => warn!("Ignoring a bad datagram from {}: {}", from, e),
+                }
+            }
+        }
+    }
+}
+
+fn is_disconnect(error: &Error) -> bool {
+    match error.downcast_ref::<std::io::Error>() {
+        Some(e) => matches!(e.kind(), ErrorKind::ConnectionReset | ErrorKind::ConnectionAborted),
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::channel_layout::ChannelLayout;
+    use crate::network::encode_packet;
+    use crate::network::Transport;
+    use crate::stream_format::SampleFormat;
+    use crate::writer::collecting_writer::CollectingWriter;
+    use crate::writer::network_writer::NetworkWriter;
+    use crate::writer::sample_bytes;
+
+    fn stereo() -> StreamFormat {
+        StreamFormat::new(SampleFormat::I16, 48000, ChannelLayout::new(2, 0x3))
+    }
+
+    /// Frames `first_frame..first_frame + n_frames` of a stereo ramp.
+    fn ramp(first_frame: u64, n_frames: usize) -> Vec<i16> {
+        (0..n_frames as u64)
+            .flat_map(|frame| {
+                let value = ((first_frame + frame) % 1000) as i16 + 1;
+                vec![value, -value]
+            })
+            .collect()
+    }
+
+    /// Receive everything `source` is sent.
+    fn receive(source: &mut NetworkSource) -> Vec<i16> {
+        let sink = CollectingWriter::<i16>::new(source.format()).unwrap();
+        let samples = sink.samples.clone();
+        source.stream_to_sink(Box::new(sink)).unwrap();
+        let samples = samples.lock().unwrap().clone();
+        samples
+    }
+
+    /// Send `packets` of `(sequence, first_frame, n_frames)` over UDP, then receive them.
+    fn receive_udp(packets: &[(u32, u64, usize)]) -> (NetworkSource, Vec<i16>) {
+        let format = stereo();
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        sender.connect(receiver.local_addr().unwrap()).unwrap();
+        for (index, &(sequence, first_frame, n_frames)) in packets.iter().enumerate() {
+            let samples = ramp(first_frame, n_frames);
+            let end_of_stream = index == packets.len() - 1;
+            let samples = sample_bytes(&samples);
+            let packet = encode_packet(&format, sequence, first_frame, end_of_stream, samples);
+            sender.send(&packet).unwrap();
+        }
+        let peer = sender.local_addr().unwrap();
+        let mut source = NetworkSource::start(Input::Udp(receiver), peer).unwrap();
+        let samples = receive(&mut source);
+        (source, samples)
+    }
+
+    #[test]
+    fn receives_a_tcp_stream() {
+        let format = stereo();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let endpoint = Endpoint {
+            transport: Transport::Tcp,
+            address: listener.local_addr().unwrap().to_string(),
+        };
+        let sender = std::thread::spawn(move || {
+            let mut writer = NetworkWriter::<i16>::connect(format, &endpoint).unwrap();
+            let mut buffer = ExtensibleBuffer::new(ramp(0, 480), SampleFormat::I16);
+            writer.write(&buffer, 480).unwrap();
+            for packet in 1..10 {
+                let samples = ramp(packet * 480, 480);
+                buffer.extend(&samples, samples.len());
+                writer.write(&buffer, 480).unwrap();
+            }
+            writer.close().unwrap();
+        });
+        let (stream, peer) = listener.accept().unwrap();
+        let mut source = NetworkSource::start(Input::Tcp(stream), peer).unwrap();
+        assert_eq!(source.format(), format);
+        let samples = receive(&mut source);
+        sender.join().unwrap();
+        assert_eq!(samples, ramp(0, 4800));
+        assert_eq!(source.lost_packets, 0);
+    }
+
+    #[test]
+    fn fills_lost_udp_packets_and_drops_late_ones() {
+        let (source, samples) = receive_udp(&[
+            (0, 0, 100),
+            (1, 100, 100),
+            // Packet 2, frames 200..300, is lost and arrives after packet 3.
+            (3, 300, 100),
+            (2, 200, 100),
+            (4, 400, 100),
+        ]);
+        assert_eq!(source.lost_packets, 1);
+        assert_eq!(samples.len(), 500 * 2);
+        assert_eq!(&samples[..400], &ramp(0, 200)[..]);
+        assert!(samples[400..600].iter().all(|&sample| sample == 0));
+        assert_eq!(&samples[600..], &ramp(300, 200)[..]);
+    }
+
+    #[test]
+    fn fills_gaps_longer_than_the_buffer_with_silence() {
+        // Four seconds of stereo fill the buffer; lose twice that and a little more.
+        let gap = BUFFER_LENGTH + 4800;
+        let (source, samples) = receive_udp(&[(0, 0, 100), (7, 100 + gap as u64, 100)]);
+        assert_eq!(source.lost_packets, 6);
+        assert_eq!(samples.len(), (200 + gap) * 2);
+        assert_eq!(&samples[..200], &ramp(0, 100)[..]);
+        assert!(samples[200..200 + gap * 2].iter().all(|&sample| sample == 0));
+        assert_eq!(&samples[200 + gap * 2..], &ramp(100 + gap as u64, 100)[..]);
+    }
+}
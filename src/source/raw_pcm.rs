@@ -0,0 +1,97 @@
+use std::io::{ErrorKind, Read};
+use std::marker::PhantomData;
+
+use anyhow::Error;
+use serde::Serialize;
+
+use crate::buffer::ExtensibleBuffer;
+use crate::source::decode_samples;
+use crate::stream_format;
+use crate::stream_format::StreamFormat;
+use crate::writer::AudioWriter;
+
+/// Frames read from the input per packet handed to the sink.
+const PACKET_FRAMES: usize = 1024;
+
+/// Headerless interleaved samples read from a file, pipe or socket.
+///
+/// The input has no header, so its format has to be given up front, e.g. `8000Hz:1ch:mulaw`
+/// for a telephony recording. Samples are little-endian, as WASAPI delivers them.
+pub(crate) struct RawPcmSource<R, T>
+    where
+        R: Read,
+        T: hound::Sample + stream_format::Sample + Serialize + Copy,
+{
+    reader: R,
+    format: StreamFormat,
+    sample: PhantomData<T>,
+}
+
+impl<R, T> RawPcmSource<R, T>
+    where
+        R: Read,
+        T: hound::Sample + stream_format::Sample + Serialize + Copy,
+{
+    pub(crate) fn new(reader: R, format: StreamFormat) -> Self {
+        assert_eq!(
+            T::FORMAT,
+            format.sample_format,
+            "Sample type doesn't match the source format"
+        );
+        RawPcmSource {
+            reader,
+            format,
+            sample: PhantomData,
+        }
+    }
+
+    pub(crate) fn format(&self) -> StreamFormat {
+        self.format
+    }
+
+    /// Feed the whole input to `sink`, closing it at the end of the input.
+    ///
+    /// A trailing partial frame is dropped.
+    pub(crate) fn stream_to_sink(
+        &mut self,
+        mut sink: Box<dyn AudioWriter<T>>,
+    ) -> Result<(), Error> {
+        let block_align = self.format.n_block_align as usize;
+        let n_channels = self.format.n_channels as usize;
+        let mut bytes = vec![0u8; PACKET_FRAMES * block_align];
+        let mut buffer: Option<ExtensibleBuffer<T>> = None;
+        loop {
+            let n_bytes = self.read_packet(&mut bytes)?;
+            let n_frames = n_bytes / block_align;
+            if n_frames == 0 {
+                break;
+            }
+            let samples: Vec<T> = decode_samples(&bytes[..n_frames * block_align]);
+            match buffer {
+                Some(ref mut buffer) => buffer.extend(&samples, n_frames * n_channels),
+                None => {
+                    buffer = Some(ExtensibleBuffer::new(samples, self.format.sample_format))
+                }
+            }
+            sink.write(buffer.as_ref().unwrap(), n_frames)?;
+            if n_bytes < bytes.len() {
+                break;
+            }
+        }
+        sink.close()
+    }
+
+    /// Fill `bytes` unless the input ends first, returning how many bytes were read.
+    fn read_packet(&mut self, bytes: &mut [u8]) -> Result<usize, Error> {
+        let mut filled = 0;
+        while filled < bytes.len() {
+            match self.reader.read(&mut bytes[filled..]) {
+                Ok(0) => break,
+                Ok(n) => filled += n,
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Ok(filled)
+    }
+}
@@ -1,28 +1,113 @@
 use core::mem;
+use std::fmt;
+use std::io;
+use std::str::FromStr;
 
+use anyhow::Error;
+use serde::{Deserialize, Serialize, Serializer};
 use winapi::shared::guiddef::GUID;
 use winapi::shared::ksmedia;
 use winapi::shared::mmreg;
 use winapi::shared::mmreg::*;
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+use crate::channel_layout::ChannelLayout;
+use crate::dsp::g711;
+
+/// Size of the `WAVEFORMATEXTENSIBLE` fields that follow the `WAVEFORMATEX` header.
+const EXTENSIBLE_CB_SIZE: usize =
+    mem::size_of::<WAVEFORMATEXTENSIBLE>() - mem::size_of::<WAVEFORMATEX>();
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum SampleFormat {
+    U8,
     I16,
+    /// Packed 24-bit, three bytes per sample.
+    I24,
+    /// 24-bit left-justified in a 32-bit container.
+    I24In32,
     I32,
     U16,
     F32,
+    F64,
+    /// G.711 A-law, one companded byte per sample.
+    ALaw,
+    /// G.711 μ-law, one companded byte per sample.
+    MuLaw,
 }
 
 impl SampleFormat {
+    /// The sample format of a wave format with encoding `tag` in `bits` bit containers.
+    pub(crate) fn from_wave(
+        tag: FormatTag,
+        bits: u16,
+        valid_bits: u16,
+    ) -> Result<SampleFormat, FormatError> {
+        match (tag, bits) {
+            (FormatTag::PCM, 8) => Ok(SampleFormat::U8),
+            (FormatTag::PCM, 16) => Ok(SampleFormat::I16),
+            (FormatTag::PCM, 24) => Ok(SampleFormat::I24),
+            (FormatTag::PCM, 32) if valid_bits == 24 => Ok(SampleFormat::I24In32),
+            (FormatTag::PCM, 32) => Ok(SampleFormat::I32),
+            (FormatTag::IeeFloat, 32) => Ok(SampleFormat::F32),
+            (FormatTag::IeeFloat, 64) => Ok(SampleFormat::F64),
+            (FormatTag::Alaw, 8) => Ok(SampleFormat::ALaw),
+            (FormatTag::Mulaw, 8) => Ok(SampleFormat::MuLaw),
+            (FormatTag::Unknown(tag), _) => Err(FormatError::UnknownTag(tag)),
+            (tag, _) if tag.is_compressed() => Err(FormatError::Compressed(tag)),
+            (tag, bits) => Err(FormatError::UnsupportedBits {
+                tag,
+                bits,
+                valid_bits,
+            }),
+        }
+    }
+
     #[inline]
     pub fn sample_size(&self) -> usize {
         match *self {
+            SampleFormat::U8 => mem::size_of::<U8>(),
             SampleFormat::I16 => mem::size_of::<i16>(),
+            SampleFormat::I24 => mem::size_of::<I24>(),
+            SampleFormat::I24In32 => mem::size_of::<I24In32>(),
             SampleFormat::I32 => mem::size_of::<i32>(),
             SampleFormat::U16 => mem::size_of::<u16>(),
             SampleFormat::F32 => mem::size_of::<f32>(),
+            SampleFormat::F64 => mem::size_of::<f64>(),
+            SampleFormat::ALaw => mem::size_of::<ALaw>(),
+            SampleFormat::MuLaw => mem::size_of::<MuLaw>(),
+        }
+    }
+
+    #[inline]
+    pub fn is_float(&self) -> bool {
+        matches!(*self, SampleFormat::F32 | SampleFormat::F64)
+    }
+
+    /// Bits of each sample that carry signal.
+    #[inline]
+    pub fn valid_bits(&self) -> usize {
+        match *self {
+            SampleFormat::I24In32 => 24,
+            _ => self.sample_size() * 8,
+        }
+    }
+
+    /// Resolution of the format as linear PCM. The same as `valid_bits`, except for companded
+    /// formats which cover a wider range than their stored size.
+    #[inline]
+    pub fn linear_bits(&self) -> usize {
+        match *self {
+            SampleFormat::ALaw => 13,
+            SampleFormat::MuLaw => 14,
+            _ => self.valid_bits(),
         }
     }
+
+    #[inline]
+    pub fn is_companded(&self) -> bool {
+        matches!(*self, SampleFormat::ALaw | SampleFormat::MuLaw)
+    }
 }
 
 /// Trait for containers that contain PCM data.
@@ -32,6 +117,8 @@ pub unsafe trait Sample: Copy + Clone + Sized {
 
     /// Turns the sample into its equivalent as a floating-point.
     fn to_f32(&self) -> f32;
+    /// Turns the sample into a double precision float, without losing any precision.
+    fn to_f64(&self) -> f64;
     /// Converts this sample into a standard i16 sample.
     fn to_i16(&self) -> i16;
     /// Converts into i32 sample.
@@ -49,7 +136,12 @@ unsafe impl Sample for i32 {
     const FORMAT: SampleFormat = SampleFormat::I32;
     #[inline]
     fn to_f32(&self) -> f32 {
-        self.to_i32().to_f32()
+        *self as f32 / -(i32::MIN as f32)
+    }
+
+    #[inline]
+    fn to_f64(&self) -> f64 {
+        *self as f64 / -(i32::MIN as f64)
     }
 
     #[inline]
@@ -57,13 +149,10 @@ unsafe impl Sample for i32 {
         *self
     }
 
+    /// Keeps the top 16 bits. Use `dsp::conversion` to dither instead of truncating.
     #[inline]
     fn to_i16(&self) -> i16 {
-        if *self >= 32768 {
-            (*self - 32768) as i16
-        } else {
-            (*self as i16) - 32767 - 1
-        }
+        (*self >> 16) as i16
     }
 
     #[inline]
@@ -88,6 +177,11 @@ unsafe impl Sample for u16 {
         self.to_i16().to_f32()
     }
 
+    #[inline]
+    fn to_f64(&self) -> f64 {
+        self.to_i16().to_f64()
+    }
+
     #[inline]
     fn to_i32(&self) -> i32 {
         self.to_i16().to_i32()
@@ -128,9 +222,18 @@ unsafe impl Sample for i16 {
         }
     }
 
+    #[inline]
+    fn to_f64(&self) -> f64 {
+        if *self < 0 {
+            *self as f64 / -(i16::MIN as f64)
+        } else {
+            *self as f64 / i16::MAX as f64
+        }
+    }
+
     #[inline]
     fn to_i32(&self) -> i32 {
-        *self as i32
+        (*self as i32) << 16
     }
 
     #[inline]
@@ -164,27 +267,37 @@ unsafe impl Sample for f32 {
         *self
     }
 
+    #[inline]
+    fn to_f64(&self) -> f64 {
+        *self as f64
+    }
+
+    /// Values outside [-1, 1] saturate.
     #[inline]
     fn to_i32(&self) -> i32 {
-        if *self >= 0.0 {
-            (*self * i32::MAX as f32) as i32
+        let sample = self.max(-1.0).min(1.0) as f64;
+        if sample >= 0.0 {
+            (sample * i32::MAX as f64) as i32
         } else {
-            (-*self * i32::MIN as f32) as i32
+            (-sample * i32::MIN as f64) as i32
         }
     }
 
+    /// Values outside [-1, 1] saturate.
     #[inline]
     fn to_i16(&self) -> i16 {
-        if *self >= 0.0 {
-            (*self * i16::MAX as f32) as i16
+        let sample = self.max(-1.0).min(1.0);
+        if sample >= 0.0 {
+            (sample * i16::MAX as f32) as i16
         } else {
-            (-*self * i16::MIN as f32) as i16
+            (-sample * i16::MIN as f32) as i16
         }
     }
 
+    /// Values outside [-1, 1] saturate.
     #[inline]
     fn to_u16(&self) -> u16 {
-        (((*self + 1.0) * 0.5) * u16::MAX as f32).round() as u16
+        (((self.max(-1.0).min(1.0) + 1.0) * 0.5) * u16::MAX as f32).round() as u16
     }
 
     #[inline]
@@ -196,7 +309,416 @@ unsafe impl Sample for f32 {
     }
 }
 
-#[derive(Clone, Copy, Debug)]
+unsafe impl Sample for f64 {
+    const FORMAT: SampleFormat = SampleFormat::F64;
+
+    #[inline]
+    fn to_f32(&self) -> f32 {
+        *self as f32
+    }
+
+    #[inline]
+    fn to_f64(&self) -> f64 {
+        *self
+    }
+
+    /// Values outside [-1, 1] saturate.
+    #[inline]
+    fn to_i32(&self) -> i32 {
+        let sample = self.max(-1.0).min(1.0);
+        if sample >= 0.0 {
+            (sample * i32::MAX as f64) as i32
+        } else {
+            (-sample * i32::MIN as f64) as i32
+        }
+    }
+
+    /// Values outside [-1, 1] saturate.
+    #[inline]
+    fn to_i16(&self) -> i16 {
+        (*self as f32).to_i16()
+    }
+
+    /// Values outside [-1, 1] saturate.
+    #[inline]
+    fn to_u16(&self) -> u16 {
+        (*self as f32).to_u16()
+    }
+
+    #[inline]
+    fn from<S>(sample: &S) -> Self
+        where
+            S: Sample,
+    {
+        sample.to_f64()
+    }
+}
+
+/// Unsigned 8-bit sample, offset by 128 as stored in WAV files.
+#[repr(transparent)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(transparent)]
+pub struct U8(pub u8);
+
+/// Packed little-endian 24-bit sample.
+#[repr(transparent)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct I24(pub [u8; 3]);
+
+/// 24-bit sample left-justified in a 32-bit container, with the low byte unused.
+#[repr(transparent)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct I24In32(pub i32);
+
+impl I24 {
+    /// Build from a value in the 24-bit range.
+    #[inline]
+    pub fn from_value(value: i32) -> Self {
+        let bytes = value.to_le_bytes();
+        I24([bytes[0], bytes[1], bytes[2]])
+    }
+
+    /// Sign extended value in the 24-bit range.
+    #[inline]
+    pub fn value(&self) -> i32 {
+        i32::from_le_bytes([0, self.0[0], self.0[1], self.0[2]]) >> 8
+    }
+}
+
+impl I24In32 {
+    /// Build from a value in the 24-bit range.
+    #[inline]
+    pub fn from_value(value: i32) -> Self {
+        I24In32(value << 8)
+    }
+
+    /// Sign extended value in the 24-bit range.
+    #[inline]
+    pub fn value(&self) -> i32 {
+        self.0 >> 8
+    }
+}
+
+impl Serialize for I24 {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_i32(self.value())
+    }
+}
+
+impl Serialize for I24In32 {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_i32(self.value())
+    }
+}
+
+unsafe impl Sample for U8 {
+    const FORMAT: SampleFormat = SampleFormat::U8;
+
+    #[inline]
+    fn to_f32(&self) -> f32 {
+        (self.0 as f32 - 128.0) / 128.0
+    }
+
+    #[inline]
+    fn to_f64(&self) -> f64 {
+        (self.0 as f64 - 128.0) / 128.0
+    }
+
+    #[inline]
+    fn to_i32(&self) -> i32 {
+        (self.0 as i32 - 128) << 24
+    }
+
+    #[inline]
+    fn to_i16(&self) -> i16 {
+        (self.0 as i16 - 128) << 8
+    }
+
+    #[inline]
+    fn to_u16(&self) -> u16 {
+        (self.0 as u16) << 8
+    }
+
+    #[inline]
+    fn from<S>(sample: &S) -> Self
+        where
+            S: Sample,
+    {
+        U8((sample.to_u16() >> 8) as u8)
+    }
+}
+
+unsafe impl Sample for I24 {
+    const FORMAT: SampleFormat = SampleFormat::I24;
+
+    #[inline]
+    fn to_f32(&self) -> f32 {
+        self.value() as f32 / (1 << 23) as f32
+    }
+
+    #[inline]
+    fn to_f64(&self) -> f64 {
+        self.value() as f64 / (1 << 23) as f64
+    }
+
+    #[inline]
+    fn to_i32(&self) -> i32 {
+        self.value() << 8
+    }
+
+    #[inline]
+    fn to_i16(&self) -> i16 {
+        (self.value() >> 8) as i16
+    }
+
+    #[inline]
+    fn to_u16(&self) -> u16 {
+        self.to_i16().to_u16()
+    }
+
+    #[inline]
+    fn from<S>(sample: &S) -> Self
+        where
+            S: Sample,
+    {
+        I24::from_value(sample.to_i32() >> 8)
+    }
+}
+
+unsafe impl Sample for I24In32 {
+    const FORMAT: SampleFormat = SampleFormat::I24In32;
+
+    #[inline]
+    fn to_f32(&self) -> f32 {
+        self.value() as f32 / (1 << 23) as f32
+    }
+
+    #[inline]
+    fn to_f64(&self) -> f64 {
+        self.value() as f64 / (1 << 23) as f64
+    }
+
+    #[inline]
+    fn to_i32(&self) -> i32 {
+        self.0 & !0xff
+    }
+
+    #[inline]
+    fn to_i16(&self) -> i16 {
+        (self.0 >> 16) as i16
+    }
+
+    #[inline]
+    fn to_u16(&self) -> u16 {
+        self.to_i16().to_u16()
+    }
+
+    #[inline]
+    fn from<S>(sample: &S) -> Self
+        where
+            S: Sample,
+    {
+        I24In32(sample.to_i32() & !0xff)
+    }
+}
+
+/// G.711 A-law sample. Converting to and from other formats encodes and decodes it.
+#[repr(transparent)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ALaw(pub u8);
+
+/// G.711 μ-law sample. Converting to and from other formats encodes and decodes it.
+#[repr(transparent)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MuLaw(pub u8);
+
+impl Serialize for ALaw {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_i16(self.to_i16())
+    }
+}
+
+impl Serialize for MuLaw {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_i16(self.to_i16())
+    }
+}
+
+unsafe impl Sample for ALaw {
+    const FORMAT: SampleFormat = SampleFormat::ALaw;
+
+    #[inline]
+    fn to_f32(&self) -> f32 {
+        self.to_i16().to_f32()
+    }
+
+    #[inline]
+    fn to_f64(&self) -> f64 {
+        self.to_i16().to_f64()
+    }
+
+    #[inline]
+    fn to_i32(&self) -> i32 {
+        self.to_i16().to_i32()
+    }
+
+    #[inline]
+    fn to_i16(&self) -> i16 {
+        g711::alaw_decode(self.0)
+    }
+
+    #[inline]
+    fn to_u16(&self) -> u16 {
+        self.to_i16().to_u16()
+    }
+
+    #[inline]
+    fn from<S>(sample: &S) -> Self
+        where
+            S: Sample,
+    {
+        ALaw(g711::alaw_encode(sample.to_i16()))
+    }
+}
+
+unsafe impl Sample for MuLaw {
+    const FORMAT: SampleFormat = SampleFormat::MuLaw;
+
+    #[inline]
+    fn to_f32(&self) -> f32 {
+        self.to_i16().to_f32()
+    }
+
+    #[inline]
+    fn to_f64(&self) -> f64 {
+        self.to_i16().to_f64()
+    }
+
+    #[inline]
+    fn to_i32(&self) -> i32 {
+        self.to_i16().to_i32()
+    }
+
+    #[inline]
+    fn to_i16(&self) -> i16 {
+        g711::mulaw_decode(self.0)
+    }
+
+    #[inline]
+    fn to_u16(&self) -> u16 {
+        self.to_i16().to_u16()
+    }
+
+    #[inline]
+    fn from<S>(sample: &S) -> Self
+        where
+            S: Sample,
+    {
+        MuLaw(g711::mulaw_encode(sample.to_i16()))
+    }
+}
+
+// hound only knows about signed samples, so these delegate to the signed type of the same width
+// and let it handle the WAV encoding.
+impl hound::Sample for U8 {
+    fn write<W: io::Write>(self, writer: &mut W, bits: u16) -> hound::Result<()> {
+        ((self.0 as i16 - 128) as i8).write(writer, bits)
+    }
+
+    fn read<R: io::Read>(
+        reader: &mut R,
+        format: hound::SampleFormat,
+        bytes: u16,
+        bits: u16,
+    ) -> hound::Result<Self> {
+        i8::read(reader, format, bytes, bits).map(|x| U8((x as i16 + 128) as u8))
+    }
+
+    fn as_i16(self) -> i16 {
+        self.0 as i16 - 128
+    }
+}
+
+impl hound::Sample for I24 {
+    fn write<W: io::Write>(self, writer: &mut W, bits: u16) -> hound::Result<()> {
+        self.value().write(writer, bits)
+    }
+
+    fn read<R: io::Read>(
+        reader: &mut R,
+        format: hound::SampleFormat,
+        bytes: u16,
+        bits: u16,
+    ) -> hound::Result<Self> {
+        i32::read(reader, format, bytes, bits).map(I24::from_value)
+    }
+
+    /// The top 16 of the 24 bits.
+    fn as_i16(self) -> i16 {
+        (self.value() >> 8) as i16
+    }
+}
+
+impl hound::Sample for I24In32 {
+    fn write<W: io::Write>(self, writer: &mut W, bits: u16) -> hound::Result<()> {
+        self.value().write(writer, bits)
+    }
+
+    fn read<R: io::Read>(
+        reader: &mut R,
+        format: hound::SampleFormat,
+        bytes: u16,
+        bits: u16,
+    ) -> hound::Result<Self> {
+        i32::read(reader, format, bytes, bits).map(I24In32::from_value)
+    }
+
+    /// The top 16 of the 24 bits.
+    fn as_i16(self) -> i16 {
+        (self.value() >> 8) as i16
+    }
+}
+
+// hound can't write G.711, so companded samples are stored decoded as 16-bit PCM.
+impl hound::Sample for ALaw {
+    fn write<W: io::Write>(self, writer: &mut W, bits: u16) -> hound::Result<()> {
+        self.to_i16().write(writer, bits)
+    }
+
+    fn read<R: io::Read>(
+        reader: &mut R,
+        format: hound::SampleFormat,
+        bytes: u16,
+        bits: u16,
+    ) -> hound::Result<Self> {
+        i16::read(reader, format, bytes, bits).map(|x| <ALaw as Sample>::from(&x))
+    }
+
+    fn as_i16(self) -> i16 {
+        self.to_i16()
+    }
+}
+
+impl hound::Sample for MuLaw {
+    fn write<W: io::Write>(self, writer: &mut W, bits: u16) -> hound::Result<()> {
+        self.to_i16().write(writer, bits)
+    }
+
+    fn read<R: io::Read>(
+        reader: &mut R,
+        format: hound::SampleFormat,
+        bytes: u16,
+        bits: u16,
+    ) -> hound::Result<Self> {
+        i16::read(reader, format, bytes, bits).map(|x| <MuLaw as Sample>::from(&x))
+    }
+
+    fn as_i16(self) -> i16 {
+        self.to_i16()
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub(crate) enum FormatTag {
     PCM,
     IeeFloat,
@@ -208,10 +730,13 @@ pub(crate) enum FormatTag {
     MPEG,
     DolbySpdif,
     WmaSpdif,
+    /// Any other `wFormatTag`, kept so the format can still be reported.
+    Unknown(u16),
 }
 
 impl FormatTag {
-    fn value(&self) -> u16 {
+    /// The `wFormatTag` value.
+    pub(crate) fn value(&self) -> u16 {
         match *self {
             FormatTag::PCM => WAVE_FORMAT_PCM,
             FormatTag::IeeFloat => WAVE_FORMAT_IEEE_FLOAT,
@@ -223,73 +748,559 @@ impl FormatTag {
             FormatTag::MPEG => WAVE_FORMAT_MPEG,
             FormatTag::DolbySpdif => WAVE_FORMAT_DOLBY_AC3_SPDIF,
             FormatTag::WmaSpdif => WAVE_FORMAT_WMASPDIF,
+            FormatTag::Unknown(tag) => tag,
         }
     }
+
+    /// Encoded or passthrough formats, which can't be captured as samples.
+    pub(crate) fn is_compressed(&self) -> bool {
+        matches!(
+            *self,
+            FormatTag::DRM
+                | FormatTag::ADPCM
+                | FormatTag::MPEG
+                | FormatTag::DolbySpdif
+                | FormatTag::WmaSpdif
+        )
+    }
+}
+
+impl fmt::Display for FormatTag {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match *self {
+            FormatTag::PCM => "PCM",
+            FormatTag::IeeFloat => "IEEE float",
+            FormatTag::DRM => "DRM",
+            FormatTag::Extensible => "extensible",
+            FormatTag::Alaw => "A-law",
+            FormatTag::Mulaw => "μ-law",
+            FormatTag::ADPCM => "ADPCM",
+            FormatTag::MPEG => "MPEG",
+            FormatTag::DolbySpdif => "Dolby AC-3 S/PDIF",
+            FormatTag::WmaSpdif => "WMA Pro S/PDIF",
+            FormatTag::Unknown(tag) => return write!(f, "unknown format tag {:#06x}", tag),
+        };
+        f.write_str(name)
+    }
 }
 
 impl From<u16> for FormatTag {
     fn from(input: u16) -> FormatTag {
-        if input == FormatTag::PCM.value() {
-            FormatTag::PCM
-        } else if input == FormatTag::IeeFloat.value() {
-            FormatTag::IeeFloat
-        } else if input == FormatTag::DRM.value() {
-            FormatTag::DRM
-        } else if input == FormatTag::Extensible.value() {
-            FormatTag::Extensible
-        } else if input == FormatTag::Alaw.value() {
-            FormatTag::Alaw
-        } else if input == FormatTag::Mulaw.value() {
-            FormatTag::Mulaw
-        } else if input == FormatTag::ADPCM.value() {
-            FormatTag::ADPCM
-        } else if input == FormatTag::MPEG.value() {
-            FormatTag::MPEG
-        } else if input == FormatTag::DolbySpdif.value() {
-            FormatTag::DolbySpdif
-        } else if input == FormatTag::WmaSpdif.value() {
-            FormatTag::WmaSpdif
-        } else {
-            panic!("")
+        [
+            FormatTag::PCM,
+            FormatTag::IeeFloat,
+            FormatTag::DRM,
+            FormatTag::Extensible,
+            FormatTag::Alaw,
+            FormatTag::Mulaw,
+            FormatTag::ADPCM,
+            FormatTag::MPEG,
+            FormatTag::DolbySpdif,
+            FormatTag::WmaSpdif,
+        ]
+        .iter()
+        .find(|tag| tag.value() == input)
+        .copied()
+        .unwrap_or(FormatTag::Unknown(input))
+    }
+}
+
+/// Why a device format can't be captured.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) enum FormatError {
+    /// `wFormatTag`, or the tag embedded in an extensible `SubFormat`, isn't one we know.
+    UnknownTag(u16),
+    /// An extensible `SubFormat` GUID that doesn't correspond to any format tag.
+    UnknownSubFormat(String),
+    /// Tagged as extensible, but too short to hold the extension.
+    MissingExtension { cb_size: u16 },
+    /// Encoded or passthrough audio, such as ADPCM or S/PDIF.
+    Compressed(FormatTag),
+    /// A known encoding at a sample size we can't read.
+    UnsupportedBits {
+        tag: FormatTag,
+        bits: u16,
+        valid_bits: u16,
+    },
+}
+
+impl fmt::Display for FormatError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FormatError::UnknownTag(tag) => write!(f, "Unknown format tag {:#06x}", tag),
+            FormatError::UnknownSubFormat(guid) => write!(f, "Unknown SubFormat {}", guid),
+            FormatError::MissingExtension { cb_size } => write!(
+                f,
+                "Extensible format has {} extension bytes, needs {}",
+                cb_size, EXTENSIBLE_CB_SIZE
+            ),
+            FormatError::Compressed(tag) => write!(f, "Compressed {} audio isn't supported", tag),
+            FormatError::UnsupportedBits {
+                tag,
+                bits,
+                valid_bits,
+            } => write!(
+                f,
+                "{} with {} bit samples ({} valid) isn't supported",
+                tag, bits, valid_bits
+            ),
         }
     }
 }
 
-#[derive(Clone, Copy, Debug)]
+impl std::error::Error for FormatError {}
+
+/// Format of a stream, mirroring the `WAVEFORMATEX`/`WAVEFORMATEXTENSIBLE` it was read from.
+///
+/// Also has a compact text form, `<rate>Hz:<channels>ch:<sample format>[:<channel mask>]`,
+/// e.g. `48000Hz:2ch:f32`. The channel mask is only written for extensible formats, and valid
+/// bits follow the sample format when they aren't its usual number, e.g. `i24in32/20`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub(crate) struct StreamFormat {
-    format_tag: FormatTag,
+    pub(crate) format_tag: FormatTag,
+    /// Encoding of the samples. Matches `format_tag` unless the format is extensible, in which
+    /// case it comes from the `SubFormat` GUID.
+    pub(crate) sub_format: FormatTag,
     pub(crate) n_channels: u32,
     pub(crate) n_sample_per_sec: u32,
     pub(crate) n_avg_bytes_per_sec: u32,
     pub(crate) n_block_align: u32,
+    /// Container size of each sample.
     pub(crate) w_bits_per_sample: u32,
-    cb_size: u32,
+    /// Bits of each container that carry signal, e.g. 24 for 24-bit audio in 32-bit containers.
+    pub(crate) valid_bits_per_sample: u32,
+    /// Speaker positions as `SPEAKER_*` flags. Zero when the device doesn't specify a layout.
+    pub(crate) channel_mask: u32,
+    pub(crate) cb_size: u32,
     pub(crate) sample_format: SampleFormat,
 }
 
-impl From<WAVEFORMATEX> for StreamFormat {
-    fn from(format: WAVEFORMATEX) -> Self {
-        unsafe {
-            fn cmp_guid(a: &GUID, b: &GUID) -> bool {
-                a.Data1 == b.Data1 && a.Data2 == b.Data2 && a.Data3 == b.Data3 && a.Data4 == b.Data4
+/// Map a `WAVEFORMATEXTENSIBLE` `SubFormat` onto the equivalent format tag.
+///
+/// `SubFormat` GUIDs for wave formats are all the PCM GUID with the tag in `Data1`.
+fn sub_format_tag(sub_format: &GUID) -> Result<FormatTag, FormatError> {
+    let template = &ksmedia::KSDATAFORMAT_SUBTYPE_PCM;
+    let is_wave_format = sub_format.Data1 <= u16::MAX as u32
+        && sub_format.Data2 == template.Data2
+        && sub_format.Data3 == template.Data3
+        && sub_format.Data4 == template.Data4;
+    if !is_wave_format {
+        return Err(FormatError::UnknownSubFormat(format_guid(sub_format)));
+    }
+    match FormatTag::from(sub_format.Data1 as u16) {
+        FormatTag::Unknown(tag) => Err(FormatError::UnknownTag(tag)),
+        tag => Ok(tag),
+    }
+}
+
+fn format_guid(guid: &GUID) -> String {
+    let d = guid.Data4;
+    format!(
+        "{{{:08x}-{:04x}-{:04x}-{:02x}{:02x}-{:02x}{:02x}{:02x}{:02x}{:02x}{:02x}}}",
+        guid.Data1, guid.Data2, guid.Data3, d[0], d[1], d[2], d[3], d[4], d[5], d[6], d[7]
+    )
+}
+
+impl StreamFormat {
+    /// Describe a format that didn't come from a device, such as the target of a conversion.
+    ///
+    /// Uses `WAVEFORMATEXTENSIBLE` whenever a plain `WAVEFORMATEX` would be ambiguous.
+    pub(crate) fn new(
+        sample_format: SampleFormat,
+        n_sample_per_sec: u32,
+        channel_layout: ChannelLayout,
+    ) -> Self {
+        let n_channels = channel_layout.n_channels();
+        let sample_size = sample_format.sample_size();
+        // Float is unambiguous at any width, integer PCM isn't beyond 16 bits.
+        let extensible = n_channels > 2
+            || (!sample_format.is_float()
+                && (sample_size > 2 || sample_format.valid_bits() != sample_size * 8));
+        let channel_mask = if extensible {
+            Some(channel_layout.mask())
+        } else {
+            None
+        };
+        StreamFormat::build(sample_format, n_sample_per_sec, n_channels, channel_mask)
+    }
+
+    /// A `WAVEFORMATEXTENSIBLE` format when there's a `channel_mask`, otherwise a plain
+    /// `WAVEFORMATEX`.
+    fn build(
+        sample_format: SampleFormat,
+        n_sample_per_sec: u32,
+        n_channels: u32,
+        channel_mask: Option<u32>,
+    ) -> Self {
+        let sample_size = sample_format.sample_size() as u32;
+        let sub_format = match sample_format {
+            SampleFormat::ALaw => FormatTag::Alaw,
+            SampleFormat::MuLaw => FormatTag::Mulaw,
+            _ if sample_format.is_float() => FormatTag::IeeFloat,
+            _ => FormatTag::PCM,
+        };
+        let (format_tag, cb_size) = match channel_mask {
+            Some(_) => (FormatTag::Extensible, EXTENSIBLE_CB_SIZE as u32),
+            None => (sub_format, 0),
+        };
+        StreamFormat {
+            format_tag,
+            sub_format,
+            n_channels,
+            n_sample_per_sec,
+            n_avg_bytes_per_sec: n_sample_per_sec * sample_size * n_channels,
+            n_block_align: sample_size * n_channels,
+            w_bits_per_sample: sample_size * 8,
+            valid_bits_per_sample: sample_format.valid_bits() as u32,
+            channel_mask: channel_mask.unwrap_or(0),
+            cb_size,
+            sample_format,
+        }
+    }
+
+    /// Speaker position of each channel.
+    pub(crate) fn channel_layout(&self) -> ChannelLayout {
+        ChannelLayout::new(self.n_channels, self.channel_mask)
+    }
+
+    /// Read a format returned by WASAPI, including the `WAVEFORMATEXTENSIBLE` fields when
+    /// `wFormatTag` says they are present.
+    ///
+    /// # Safety
+    /// `format` must point to a valid `WAVEFORMATEX`, followed by the rest of a
+    /// `WAVEFORMATEXTENSIBLE` if it is tagged as extensible.
+    pub(crate) unsafe fn from_raw(format: *const WAVEFORMATEX) -> Result<Self, FormatError> {
+        let base = *format;
+        let format_tag: FormatTag = base.wFormatTag.into();
+        let (sub_format, valid_bits_per_sample, channel_mask) = match format_tag {
+            FormatTag::Extensible if base.cbSize as usize >= EXTENSIBLE_CB_SIZE => {
+                let extensible =
+                    std::ptr::read_unaligned(format as *const WAVEFORMATEXTENSIBLE);
+                let sub_format = extensible.SubFormat;
+                let valid_bits = match extensible.Samples {
+                    0 => base.wBitsPerSample,
+                    valid_bits => valid_bits,
+                };
+                (sub_format_tag(&sub_format)?, valid_bits, extensible.dwChannelMask)
             }
-            let format_tag: FormatTag = format.wFormatTag.into();
-            let sample_format = match (format.wBitsPerSample, format_tag) {
-                (16, FormatTag::PCM) => SampleFormat::I16,
-                (32, FormatTag::IeeFloat) => SampleFormat::F32,
-                (32, _) => SampleFormat::F32,
-                _ => panic!("Couldn't ascertain format"),
-            };
-            StreamFormat {
-                format_tag,
-                n_channels: format.nChannels.into(),
-                n_sample_per_sec: format.nSamplesPerSec.into(),
-                n_avg_bytes_per_sec: format.nAvgBytesPerSec.into(),
-                n_block_align: format.nBlockAlign.into(),
-                w_bits_per_sample: format.wBitsPerSample.into(),
-                cb_size: format.cbSize.into(),
-                sample_format,
+            FormatTag::Extensible => {
+                return Err(FormatError::MissingExtension {
+                    cb_size: base.cbSize,
+                })
             }
+            tag => (tag, base.wBitsPerSample, 0),
+        };
+        let sample_format =
+            SampleFormat::from_wave(sub_format, base.wBitsPerSample, valid_bits_per_sample)?;
+        // 64-bit float is read from files and streams, but capture can't record it.
+        if sample_format == SampleFormat::F64 {
+            return Err(FormatError::UnsupportedBits {
+                tag: sub_format,
+                bits: base.wBitsPerSample,
+                valid_bits: valid_bits_per_sample,
+            });
+        }
+        Ok(StreamFormat {
+            format_tag,
+            sub_format,
+            n_channels: base.nChannels.into(),
+            n_sample_per_sec: base.nSamplesPerSec,
+            n_avg_bytes_per_sec: base.nAvgBytesPerSec,
+            n_block_align: base.nBlockAlign.into(),
+            w_bits_per_sample: base.wBitsPerSample.into(),
+            valid_bits_per_sample: valid_bits_per_sample.into(),
+            channel_mask,
+            cb_size: base.cbSize.into(),
+            sample_format,
+        })
+    }
+}
+
+impl fmt::Display for SampleFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match *self {
+            SampleFormat::U8 => "u8",
+            SampleFormat::I16 => "i16",
+            SampleFormat::I24 => "i24",
+            SampleFormat::I24In32 => "i24in32",
+            SampleFormat::I32 => "i32",
+            SampleFormat::U16 => "u16",
+            SampleFormat::F32 => "f32",
+            SampleFormat::F64 => "f64",
+            SampleFormat::ALaw => "alaw",
+            SampleFormat::MuLaw => "mulaw",
+        };
+        f.write_str(name)
+    }
+}
+
+impl FromStr for SampleFormat {
+    type Err = Error;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        match input.to_ascii_lowercase().as_str() {
+            "u8" => Ok(SampleFormat::U8),
+            "i16" => Ok(SampleFormat::I16),
+            "i24" => Ok(SampleFormat::I24),
+            "i24in32" => Ok(SampleFormat::I24In32),
+            "i32" => Ok(SampleFormat::I32),
+            "u16" => Ok(SampleFormat::U16),
+            "f32" => Ok(SampleFormat::F32),
+            "f64" => Ok(SampleFormat::F64),
+            "alaw" => Ok(SampleFormat::ALaw),
+            "mulaw" => Ok(SampleFormat::MuLaw),
+            _ => Err(anyhow!("Unknown sample format `{}`", input)),
         }
     }
 }
+
+impl fmt::Display for StreamFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}Hz:{}ch:{}",
+            self.n_sample_per_sec, self.n_channels, self.sample_format
+        )?;
+        if self.valid_bits_per_sample != self.sample_format.valid_bits() as u32 {
+            write!(f, "/{}", self.valid_bits_per_sample)?;
+        }
+        if self.format_tag == FormatTag::Extensible {
+            write!(f, ":{:#x}", self.channel_mask)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for StreamFormat {
+    type Err = Error;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        let parts: Vec<&str> = input.trim().split(':').collect();
+        if parts.len() < 3 || parts.len() > 4 {
+            bail!("Expected a format like `48000Hz:2ch:f32`, got `{}`", input);
+        }
+        let number = |part: &str, suffix: &str| -> Result<u32, Error> {
+            let lower = part.to_ascii_lowercase();
+            lower
+                .strip_suffix(&suffix.to_ascii_lowercase())
+                .ok_or_else(|| anyhow!("`{}` should end in `{}`", part, suffix))?
+                .parse()
+                .map_err(|e| anyhow!("`{}` - {}", part, e))
+        };
+        let n_sample_per_sec = number(parts[0], "Hz")?;
+        let n_channels = number(parts[1], "ch")?;
+        if n_sample_per_sec == 0 || n_channels == 0 {
+            bail!("Sample rate and channels must be non-zero in `{}`", input);
+        }
+        let (sample_format, valid_bits): (SampleFormat, _) = match parts[2].find('/') {
+            Some(split) => {
+                let bits = &parts[2][split + 1..];
+                let valid_bits: u32 = bits
+                    .parse()
+                    .map_err(|_| anyhow!("Valid bits `{}` aren't a number", bits))?;
+                (parts[2][..split].parse()?, Some(valid_bits))
+            }
+            None => (parts[2].parse()?, None),
+        };
+        let channel_mask = match parts.get(3) {
+            Some(mask) => {
+                let hex = mask.trim_start_matches("0x").trim_start_matches("0X");
+                Some(
+                    u32::from_str_radix(hex, 16)
+                        .map_err(|e| anyhow!("Channel mask `{}` - {}", mask, e))?,
+                )
+            }
+            None => None,
+        };
+        let mut format =
+            StreamFormat::build(sample_format, n_sample_per_sec, n_channels, channel_mask);
+        if let Some(valid_bits) = valid_bits {
+            if valid_bits == 0 || valid_bits > format.w_bits_per_sample {
+                bail!("{} samples can't have {} valid bits", sample_format, valid_bits);
+            }
+            format.valid_bits_per_sample = valid_bits;
+        }
+        Ok(format)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hound::Sample as _;
+
+    fn round_trip(text: &str) -> StreamFormat {
+        let format: StreamFormat = text.parse().unwrap();
+        assert_eq!(format.to_string(), text);
+        assert_eq!(format.to_string().parse::<StreamFormat>().unwrap(), format);
+        format
+    }
+
+    #[test]
+    fn plain_formats_round_trip() {
+        let format = round_trip("48000Hz:2ch:f32");
+        assert_eq!(format, StreamFormat::new(SampleFormat::F32, 48000, ChannelLayout::STEREO));
+        assert_eq!(format.format_tag, FormatTag::IeeFloat);
+        assert_eq!(round_trip("44100Hz:1ch:i16").format_tag, FormatTag::PCM);
+        assert_eq!("48000hz:2CH:F32".parse::<StreamFormat>().unwrap(), format);
+    }
+
+    #[test]
+    fn extensible_formats_round_trip() {
+        let surround = StreamFormat::new(SampleFormat::I16, 48000, ChannelLayout::new(6, 0x3f));
+        assert_eq!(surround.to_string(), "48000Hz:6ch:i16:0x3f");
+        assert_eq!(round_trip("48000Hz:6ch:i16:0x3f"), surround);
+
+        let unassigned = round_trip("48000Hz:2ch:i32:0x0");
+        assert_eq!(unassigned.format_tag, FormatTag::Extensible);
+        assert_eq!(unassigned.channel_mask, 0);
+        assert_eq!(unassigned.cb_size, EXTENSIBLE_CB_SIZE as u32);
+    }
+
+    #[test]
+    fn keeps_unusual_valid_bits() {
+        let format = round_trip("96000Hz:2ch:i24in32/20:0x3");
+        assert_eq!(format.w_bits_per_sample, 32);
+        assert_eq!(format.valid_bits_per_sample, 20);
+        let json = serde_json::to_string(&format).unwrap();
+        assert_eq!(serde_json::from_str::<StreamFormat>(&json).unwrap(), format);
+        assert_eq!(round_trip("96000Hz:2ch:i24in32:0x3").valid_bits_per_sample, 24);
+    }
+
+    #[test]
+    fn companded_formats_round_trip() {
+        let format = round_trip("8000Hz:1ch:mulaw");
+        assert_eq!(format, StreamFormat::new(SampleFormat::MuLaw, 8000, ChannelLayout::MONO));
+        assert_eq!(format.format_tag, FormatTag::Mulaw);
+        assert_eq!(format.n_block_align, 1);
+        assert_eq!(round_trip("8000Hz:1ch:alaw").format_tag, FormatTag::Alaw);
+    }
+
+    #[test]
+    fn rejects_malformed_formats() {
+        let inputs = [
+            "",
+            "48000Hz:2ch",
+            "48000Hz:2ch:f32:0x3:extra",
+            "48000:2ch:f32",
+            "48000Hz:2:f32",
+            "0Hz:2ch:f32",
+            "48000Hz:0ch:f32",
+            "48000Hz:2ch:f16",
+            "48000Hz:2ch:f32:0xzz",
+            "48000Hz:2ch:i24in32/x",
+            "48000Hz:2ch:i24in32/0",
+            "48000Hz:2ch:i16/24",
+        ];
+        for input in inputs.iter() {
+            assert!(input.parse::<StreamFormat>().is_err(), "`{}` parsed", input);
+        }
+    }
+
+    #[test]
+    fn narrows_24_bit_samples_to_their_top_16_bits() {
+        let cases = [(0x7f_ffff, i16::MAX), (-0x80_0000, i16::MIN), (0x12_3456, 0x1234)];
+        for (value, expected) in cases {
+            assert_eq!(I24::from_value(value).as_i16(), expected);
+            assert_eq!(I24In32::from_value(value).as_i16(), expected);
+        }
+    }
+
+    fn wave_format(tag: u16, bits: u16, cb_size: u16) -> WAVEFORMATEX {
+        WAVEFORMATEX {
+            wFormatTag: tag,
+            nChannels: 2,
+            nSamplesPerSec: 48000,
+            nAvgBytesPerSec: 48000 * bits as u32 / 4,
+            nBlockAlign: bits / 4,
+            wBitsPerSample: bits,
+            cbSize: cb_size,
+        }
+    }
+
+    fn read_extensible(
+        bits: u16,
+        valid_bits: u16,
+        sub_format: GUID,
+    ) -> Result<StreamFormat, FormatError> {
+        let format = WAVEFORMATEXTENSIBLE {
+            Format: wave_format(WAVE_FORMAT_EXTENSIBLE, bits, EXTENSIBLE_CB_SIZE as u16),
+            Samples: valid_bits,
+            dwChannelMask: 0x3,
+            SubFormat: sub_format,
+        };
+        unsafe { StreamFormat::from_raw(&format as *const _ as *const WAVEFORMATEX) }
+    }
+
+    /// The SubFormat GUID for a wave format tag.
+    fn sub_format(tag: u16) -> GUID {
+        GUID {
+            Data1: tag as u32,
+            ..ksmedia::KSDATAFORMAT_SUBTYPE_PCM
+        }
+    }
+
+    #[test]
+    fn reads_the_encoding_from_the_sub_format() {
+        let pcm = read_extensible(16, 16, ksmedia::KSDATAFORMAT_SUBTYPE_PCM).unwrap();
+        assert_eq!(pcm.format_tag, FormatTag::Extensible);
+        assert_eq!(pcm.sub_format, FormatTag::PCM);
+        assert_eq!(pcm.sample_format, SampleFormat::I16);
+        assert_eq!(pcm.channel_layout(), ChannelLayout::STEREO);
+
+        let float = read_extensible(32, 32, ksmedia::KSDATAFORMAT_SUBTYPE_IEEE_FLOAT).unwrap();
+        assert_eq!(float.sub_format, FormatTag::IeeFloat);
+        assert_eq!(float.sample_format, SampleFormat::F32);
+        assert_eq!(float.to_string(), "48000Hz:2ch:f32:0x3");
+    }
+
+    #[test]
+    fn keeps_32_bit_integers_apart_from_24_bit() {
+        let pcm = ksmedia::KSDATAFORMAT_SUBTYPE_PCM;
+        assert_eq!(read_extensible(32, 32, pcm).unwrap().sample_format, SampleFormat::I32);
+        assert_eq!(read_extensible(32, 24, pcm).unwrap().sample_format, SampleFormat::I24In32);
+    }
+
+    #[test]
+    fn takes_valid_bits_from_the_container_when_unset() {
+        let pcm = ksmedia::KSDATAFORMAT_SUBTYPE_PCM;
+        let format = read_extensible(24, 0, pcm).unwrap();
+        assert_eq!(format.valid_bits_per_sample, 24);
+        assert_eq!(format.sample_format, SampleFormat::I24);
+        assert_eq!(read_extensible(32, 0, pcm).unwrap().sample_format, SampleFormat::I32);
+    }
+
+    #[test]
+    fn reads_plain_formats() {
+        let format = wave_format(WAVE_FORMAT_PCM, 16, 0);
+        let format = unsafe { StreamFormat::from_raw(&format) }.unwrap();
+        assert_eq!(format, StreamFormat::new(SampleFormat::I16, 48000, ChannelLayout::STEREO));
+    }
+
+    #[test]
+    fn rejects_unusable_formats() {
+        let short = wave_format(WAVE_FORMAT_EXTENSIBLE, 16, 0);
+        assert_eq!(
+            unsafe { StreamFormat::from_raw(&short) },
+            Err(FormatError::MissingExtension { cb_size: 0 })
+        );
+        assert_eq!(
+            read_extensible(16, 16, sub_format(0x1234)),
+            Err(FormatError::UnknownTag(0x1234))
+        );
+        assert_eq!(
+            read_extensible(8, 8, sub_format(WAVE_FORMAT_MPEG)),
+            Err(FormatError::Compressed(FormatTag::MPEG))
+        );
+        let not_wave = GUID {
+            Data2: 0xabcd,
+            ..ksmedia::KSDATAFORMAT_SUBTYPE_PCM
+        };
+        assert_eq!(
+            read_extensible(16, 16, not_wave),
+            Err(FormatError::UnknownSubFormat(
+                "{00000001-abcd-0010-8000-00aa00389b71}".to_string()
+            ))
+        );
+    }
+}
@@ -0,0 +1,248 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::Error;
+
+use crate::stream_format::StreamFormat;
+use crate::writer::output_path::utc;
+
+pub(crate) const BEXT: [u8; 4] = *b"bext";
+pub(crate) const LIST: [u8; 4] = *b"LIST";
+pub(crate) const INFO: [u8; 4] = *b"INFO";
+
+/// Software that made the file.
+pub(crate) const ISFT: [u8; 4] = *b"ISFT";
+/// Creation date.
+pub(crate) const ICRD: [u8; 4] = *b"ICRD";
+/// Free-form comment.
+pub(crate) const ICMT: [u8; 4] = *b"ICMT";
+/// Source form; we store the capture device's name.
+pub(crate) const ISRF: [u8; 4] = *b"ISRF";
+
+const SOFTWARE: &str = concat!("Audia ", env!("CARGO_PKG_VERSION"));
+
+/// Size of a version 1 `bext` chunk before the coding history.
+const BEXT_FIXED_SIZE: usize = 602;
+
+/// Broadcast WAV (EBU Tech 3285) description of a recording.
+///
+/// Origination times are UTC, so `time_reference` counts from UTC midnight.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct Bext {
+    pub(crate) description: String,
+    pub(crate) originator: String,
+    pub(crate) originator_reference: String,
+    /// `yyyy-mm-dd`.
+    pub(crate) origination_date: String,
+    /// `hh:mm:ss`.
+    pub(crate) origination_time: String,
+    /// Samples since midnight at the first sample.
+    pub(crate) time_reference: u64,
+    /// EBU R98 lines describing how the audio was made.
+    pub(crate) coding_history: String,
+}
+
+impl Bext {
+    /// Describe a recording in `format` whose first sample was captured at `start`.
+    pub(crate) fn new(format: &StreamFormat, start: SystemTime, description: &str) -> Self {
+        let (year, month, day, hour, minute, second) = utc(start);
+        let since_epoch = start.duration_since(UNIX_EPOCH).unwrap_or_default();
+        let secs_of_day = since_epoch.as_secs() % 86_400;
+        let time_reference = secs_of_day * format.n_sample_per_sec as u64
+            + since_epoch.subsec_nanos() as u64 * format.n_sample_per_sec as u64 / 1_000_000_000;
+        let mut bext = Bext {
+            description: description.to_string(),
+            originator: SOFTWARE.to_string(),
+            originator_reference: String::new(),
+            origination_date: format!("{:04}-{:02}-{:02}", year, month, day),
+            origination_time: format!("{:02}:{:02}:{:02}", hour, minute, second),
+            time_reference,
+            coding_history: String::new(),
+        };
+        bext.set_coding_history(format);
+        bext
+    }
+
+    /// Describe the samples as stored in `format`.
+    pub(crate) fn set_coding_history(&mut self, format: &StreamFormat) {
+        let mode = match format.n_channels {
+            1 => ",M=mono",
+            2 => ",M=stereo",
+            _ => "",
+        };
+        self.coding_history = format!(
+            "A=PCM,F={},W={}{},T={}\r\n",
+            format.n_sample_per_sec, format.valid_bits_per_sample, mode, SOFTWARE
+        );
+    }
+
+    /// Body of the `bext` chunk.
+    pub(crate) fn to_bytes(&self) -> Vec<u8> {
+        let mut bext = Vec::with_capacity(BEXT_FIXED_SIZE + self.coding_history.len());
+        push_fixed(&mut bext, &self.description, 256);
+        push_fixed(&mut bext, &self.originator, 32);
+        push_fixed(&mut bext, &self.originator_reference, 32);
+        push_fixed(&mut bext, &self.origination_date, 10);
+        push_fixed(&mut bext, &self.origination_time, 8);
+        bext.extend_from_slice(&(self.time_reference as u32).to_le_bytes());
+        bext.extend_from_slice(&((self.time_reference >> 32) as u32).to_le_bytes());
+        bext.extend_from_slice(&1u16.to_le_bytes());
+        // UMID, then the loudness fields and padding that version 1 reserves.
+        bext.resize(BEXT_FIXED_SIZE, 0);
+        bext.extend_from_slice(self.coding_history.as_bytes());
+        // Keep the size even inside the chunk, as some readers don't skip the pad byte.
+        bext.resize(bext.len() + bext.len() % 2, 0);
+        bext
+    }
+
+    pub(crate) fn parse(bext: &[u8]) -> Result<Self, Error> {
+        if bext.len() < BEXT_FIXED_SIZE {
+            bail!("bext chunk is only {} bytes", bext.len());
+        }
+        let u32_at = |offset: usize| {
+            u32::from_le_bytes([bext[offset], bext[offset + 1], bext[offset + 2], bext[offset + 3]])
+        };
+        Ok(Bext {
+            description: read_fixed(&bext[0..256]),
+            originator: read_fixed(&bext[256..288]),
+            originator_reference: read_fixed(&bext[288..320]),
+            origination_date: read_fixed(&bext[320..330]),
+            origination_time: read_fixed(&bext[330..338]),
+            time_reference: u32_at(338) as u64 | (u32_at(342) as u64) << 32,
+            coding_history: read_fixed(&bext[BEXT_FIXED_SIZE..]),
+        })
+    }
+}
+
+/// `LIST` chunk of `INFO` tags, in the order they were added.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub(crate) struct InfoTags {
+    tags: Vec<([u8; 4], String)>,
+}
+
+impl InfoTags {
+    /// Tags for a recording from `device` started at `start`.
+    pub(crate) fn for_recording(device: Option<&str>, start: SystemTime) -> Self {
+        let (year, month, day, ..) = utc(start);
+        let mut tags = InfoTags::default();
+        tags.set(ISFT, SOFTWARE);
+        tags.set(ICRD, &format!("{:04}-{:02}-{:02}", year, month, day));
+        if let Some(device) = device {
+            tags.set(ISRF, device);
+        }
+        tags
+    }
+
+    /// Set tag `id`, replacing any earlier value.
+    pub(crate) fn set(&mut self, id: [u8; 4], value: &str) {
+        match self.tags.iter_mut().find(|(tag, _)| *tag == id) {
+            Some((_, existing)) => *existing = value.to_string(),
+            None => self.tags.push((id, value.to_string())),
+        }
+    }
+
+    pub(crate) fn get(&self, id: [u8; 4]) -> Option<&str> {
+        self.tags
+            .iter()
+            .find(|(tag, _)| *tag == id)
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.tags.is_empty()
+    }
+
+    /// Body of the `LIST` chunk.
+    pub(crate) fn to_bytes(&self) -> Vec<u8> {
+        let mut list = INFO.to_vec();
+        for (id, value) in &self.tags {
+            // Values are zero-terminated and padded to an even length.
+            let len = value.len() + 1;
+            list.extend_from_slice(id);
+            list.extend_from_slice(&(len as u32).to_le_bytes());
+            list.extend_from_slice(value.as_bytes());
+            list.resize(list.len() + 1 + len % 2, 0);
+        }
+        list
+    }
+
+    /// Read the tags from a `LIST` chunk body, or None for lists other than `INFO`.
+    pub(crate) fn parse(list: &[u8]) -> Option<Self> {
+        if list.len() < 4 || list[0..4] != INFO {
+            return None;
+        }
+        let mut tags = InfoTags::default();
+        let mut offset = 4;
+        while offset + 8 <= list.len() {
+            let id = [list[offset], list[offset + 1], list[offset + 2], list[offset + 3]];
+            let len = u32::from_le_bytes([
+                list[offset + 4],
+                list[offset + 5],
+                list[offset + 6],
+                list[offset + 7],
+            ]) as usize;
+            let end = (offset + 8 + len).min(list.len());
+            tags.set(id, &read_fixed(&list[offset + 8..end]));
+            offset = end + len % 2;
+        }
+        Some(tags)
+    }
+}
+
+/// Everything the WAV writer can embed besides the samples.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub(crate) struct WavMetadata {
+    pub(crate) bext: Option<Bext>,
+    pub(crate) info: InfoTags,
+}
+
+impl WavMetadata {
+    /// Provenance for a recording in `format` from `device`, started at `start`.
+    pub(crate) fn for_recording(
+        format: &StreamFormat,
+        device: Option<&str>,
+        comment: Option<&str>,
+        start: SystemTime,
+    ) -> Self {
+        let mut info = InfoTags::for_recording(device, start);
+        if let Some(comment) = comment {
+            info.set(ICMT, comment);
+        }
+        let description = match device {
+            Some(device) => format!("Recorded from {}", device),
+            None => String::new(),
+        };
+        WavMetadata {
+            bext: Some(Bext::new(format, start, &description)),
+            info,
+        }
+    }
+
+    /// The chunks to write, in order.
+    pub(crate) fn chunks(&self) -> Vec<([u8; 4], Vec<u8>)> {
+        let mut chunks = vec![];
+        if let Some(bext) = &self.bext {
+            chunks.push((BEXT, bext.to_bytes()));
+        }
+        if !self.info.is_empty() {
+            chunks.push((LIST, self.info.to_bytes()));
+        }
+        chunks
+    }
+}
+
+/// Write `value` into a field of `len` bytes, truncated or padded with zeros.
+fn push_fixed(output: &mut Vec<u8>, value: &str, len: usize) {
+    let mut end = value.len().min(len);
+    // Don't cut a UTF-8 character in half.
+    while !value.is_char_boundary(end) {
+        end -= 1;
+    }
+    output.extend_from_slice(&value.as_bytes()[..end]);
+    output.resize(output.len() + len - end, 0);
+}
+
+/// A zero-padded text field.
+fn read_fixed(field: &[u8]) -> String {
+    let end = field.iter().position(|byte| *byte == 0).unwrap_or(field.len());
+    String::from_utf8_lossy(&field[..end]).into_owned()
+}
@@ -0,0 +1,150 @@
+//! WAV files we write ourselves rather than through hound, so they can grow past 4GB.
+//!
+//! Files are written as RF64 (EBU Tech 3306): a plain RIFF WAV whose first chunk is a `JUNK`
+//! chunk big enough to hold a `ds64` chunk. Recordings that stay under 4GB are finished as
+//! ordinary WAV files; bigger ones have `RIFF` replaced by `RF64`, the `JUNK` chunk turned
+//! into a `ds64` chunk with the real sizes, and the 32-bit sizes set to `0xFFFFFFFF`.
+
+use anyhow::Error;
+
+use crate::stream_format::{FormatTag, SampleFormat, StreamFormat};
+
+pub(crate) mod metadata;
+pub(crate) mod reader;
+pub(crate) mod repair;
+pub(crate) mod writer;
+
+pub(crate) const RIFF: [u8; 4] = *b"RIFF";
+pub(crate) const RF64: [u8; 4] = *b"RF64";
+pub(crate) const WAVE: [u8; 4] = *b"WAVE";
+pub(crate) const JUNK: [u8; 4] = *b"JUNK";
+pub(crate) const DS64: [u8; 4] = *b"ds64";
+pub(crate) const FMT: [u8; 4] = *b"fmt ";
+pub(crate) const DATA: [u8; 4] = *b"data";
+
+/// 32-bit size of a chunk whose real size is in the `ds64` chunk.
+pub(crate) const SIZE_IN_DS64: u32 = u32::MAX;
+
+/// Largest RIFF or data chunk size a plain WAV file can hold.
+pub(crate) const MAX_WAV_SIZE: u64 = u32::MAX as u64;
+
+/// `ds64` body without a chunk size table: RIFF size, data size and sample count.
+pub(crate) const DS64_SIZE: usize = 28;
+
+/// Bytes of the `WAVEFORMATEXTENSIBLE` `SubFormat` GUID after the tag in `Data1`, shared by
+/// every wave format.
+const SUB_FORMAT_SUFFIX: [u8; 12] = [
+    0x00, 0x00, 0x10, 0x00, 0x80, 0x00, 0x00, 0xaa, 0x00, 0x38, 0x9b, 0x71,
+];
+
+/// Body of the `fmt ` chunk describing `format`.
+pub(crate) fn fmt_chunk(format: &StreamFormat) -> Vec<u8> {
+    let mut fmt = Vec::with_capacity(40);
+    fmt.extend_from_slice(&format.format_tag.value().to_le_bytes());
+    fmt.extend_from_slice(&(format.n_channels as u16).to_le_bytes());
+    fmt.extend_from_slice(&format.n_sample_per_sec.to_le_bytes());
+    fmt.extend_from_slice(&format.n_avg_bytes_per_sec.to_le_bytes());
+    fmt.extend_from_slice(&(format.n_block_align as u16).to_le_bytes());
+    fmt.extend_from_slice(&(format.w_bits_per_sample as u16).to_le_bytes());
+    match format.format_tag {
+        FormatTag::Extensible => {
+            fmt.extend_from_slice(&(format.cb_size as u16).to_le_bytes());
+            fmt.extend_from_slice(&(format.valid_bits_per_sample as u16).to_le_bytes());
+            fmt.extend_from_slice(&format.channel_mask.to_le_bytes());
+            fmt.extend_from_slice(&(format.sub_format.value() as u32).to_le_bytes());
+            fmt.extend_from_slice(&SUB_FORMAT_SUFFIX);
+        }
+        // Only plain integer PCM may leave out `cbSize`.
+        FormatTag::PCM => {}
+        _ => fmt.extend_from_slice(&0u16.to_le_bytes()),
+    }
+    fmt
+}
+
+/// Read the format from the body of a `fmt ` chunk.
+pub(crate) fn parse_fmt(fmt: &[u8]) -> Result<StreamFormat, Error> {
+    if fmt.len() < 16 {
+        bail!("fmt chunk is only {} bytes", fmt.len());
+    }
+    let u16_at = |offset: usize| u16::from_le_bytes([fmt[offset], fmt[offset + 1]]);
+    let u32_at = |offset: usize| {
+        u32::from_le_bytes([fmt[offset], fmt[offset + 1], fmt[offset + 2], fmt[offset + 3]])
+    };
+    let format_tag = FormatTag::from(u16_at(0));
+    let n_channels = u16_at(2);
+    let block_align = u16_at(12);
+    let bits = u16_at(14);
+    let cb_size = if fmt.len() >= 18 { u16_at(16) } else { 0 };
+    let (sub_format, valid_bits, channel_mask) = match format_tag {
+        FormatTag::Extensible if fmt.len() >= 40 && cb_size >= 22 => {
+            if fmt[28..40] != SUB_FORMAT_SUFFIX || u32_at(24) > u16::MAX as u32 {
+                let guid: String = fmt[24..40].iter().map(|b| format!("{:02x}", b)).collect();
+                bail!("Unknown SubFormat {}", guid);
+            }
+            let valid_bits = match u16_at(18) {
+                0 => bits,
+                valid_bits => valid_bits,
+            };
+            (FormatTag::from(u32_at(24) as u16), valid_bits, u32_at(20))
+        }
+        FormatTag::Extensible => bail!("Extensible fmt chunk is too short"),
+        tag => (tag, bits, 0),
+    };
+    let sample_format = SampleFormat::from_wave(sub_format, bits, valid_bits)?;
+    if n_channels == 0 {
+        bail!("fmt chunk has no channels");
+    }
+    // Readers step through the samples a frame at a time, so frames must be exactly this big.
+    let frame_size = n_channels as usize * sample_format.sample_size();
+    if block_align as usize != frame_size {
+        bail!("fmt chunk says frames are {} bytes, but they're {}", block_align, frame_size);
+    }
+    Ok(StreamFormat {
+        format_tag,
+        sub_format,
+        n_channels: n_channels as u32,
+        n_sample_per_sec: u32_at(4),
+        n_avg_bytes_per_sec: u32_at(8),
+        n_block_align: block_align as u32,
+        w_bits_per_sample: bits as u32,
+        valid_bits_per_sample: valid_bits as u32,
+        channel_mask,
+        cb_size: cb_size as u32,
+        sample_format,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::channel_layout::ChannelLayout;
+
+    #[test]
+    fn fmt_chunks_round_trip() {
+        for &(sample_format, n_channels, mask) in &[
+            (SampleFormat::I16, 2, 0x3),
+            (SampleFormat::I24, 2, 0x3),
+            (SampleFormat::I24In32, 6, 0x60f),
+            (SampleFormat::F64, 1, 0x4),
+            (SampleFormat::MuLaw, 1, 0x4),
+        ] {
+            let layout = ChannelLayout::new(n_channels, mask);
+            let format = StreamFormat::new(sample_format, 48000, layout);
+            assert_eq!(parse_fmt(&fmt_chunk(&format)).unwrap(), format);
+        }
+    }
+
+    #[test]
+    fn rejects_frames_that_cant_be_read() {
+        let format = StreamFormat::new(SampleFormat::I16, 48000, ChannelLayout::new(2, 0x3));
+        let mut fmt = fmt_chunk(&format);
+        fmt[2..4].copy_from_slice(&0u16.to_le_bytes());
+        assert!(parse_fmt(&fmt).is_err());
+
+        let mut fmt = fmt_chunk(&format);
+        fmt[12..14].copy_from_slice(&0u16.to_le_bytes());
+        assert!(parse_fmt(&fmt).is_err());
+        fmt[12..14].copy_from_slice(&3u16.to_le_bytes());
+        assert!(parse_fmt(&fmt).is_err());
+    }
+}
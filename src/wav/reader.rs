@@ -0,0 +1,174 @@
+use std::io::{Read, Seek, SeekFrom};
+
+use anyhow::Error;
+
+use crate::stream_format::{Sample, StreamFormat};
+use crate::wav::metadata::{Bext, InfoTags, WavMetadata, BEXT, LIST};
+use crate::wav::{parse_fmt, DATA, DS64, FMT, RF64, RIFF, SIZE_IN_DS64, WAVE};
+
+/// A chunk found in the file, other than the data chunk.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct Chunk {
+    pub(crate) id: [u8; 4],
+    /// Offset of the chunk body.
+    pub(crate) offset: u64,
+    pub(crate) len: u64,
+}
+
+/// Reads samples from a WAV or RF64 file.
+pub(crate) struct WavReader<R>
+    where
+        R: Read + Seek,
+{
+    input: R,
+    format: StreamFormat,
+    is_rf64: bool,
+    chunks: Vec<Chunk>,
+    data_offset: u64,
+    data_len: u64,
+    /// Bytes of samples read so far.
+    position: u64,
+}
+
+impl<R> WavReader<R>
+    where
+        R: Read + Seek,
+{
+    /// Read the header and find the samples.
+    pub(crate) fn new(mut input: R) -> Result<Self, Error> {
+        let mut header = [0u8; 12];
+        input.read_exact(&mut header)?;
+        let is_rf64 = match [header[0], header[1], header[2], header[3]] {
+            RIFF => false,
+            RF64 => true,
+            _ => bail!("Not a WAV file"),
+        };
+        if header[8..12] != WAVE {
+            bail!("Not a WAV file");
+        }
+        let file_len = input.seek(SeekFrom::End(0))?;
+        let mut offset = 12;
+        let mut ds64_data_len = None;
+        let mut format = None;
+        let mut data = None;
+        let mut chunks = vec![];
+        while offset + 8 <= file_len {
+            input.seek(SeekFrom::Start(offset))?;
+            let mut chunk_header = [0u8; 8];
+            input.read_exact(&mut chunk_header)?;
+            let id = [chunk_header[0], chunk_header[1], chunk_header[2], chunk_header[3]];
+            let size = u32::from_le_bytes([
+                chunk_header[4],
+                chunk_header[5],
+                chunk_header[6],
+                chunk_header[7],
+            ]);
+            let body = offset + 8;
+            let len = match id {
+                DS64 if is_rf64 => {
+                    let mut ds64 = [0u8; 16];
+                    input.read_exact(&mut ds64)?;
+                    let mut data_len = [0u8; 8];
+                    data_len.copy_from_slice(&ds64[8..16]);
+                    ds64_data_len = Some(u64::from_le_bytes(data_len));
+                    size as u64
+                }
+                DATA if is_rf64 && size == SIZE_IN_DS64 => match ds64_data_len {
+                    Some(len) => len,
+                    None => bail!("RF64 file has no ds64 chunk before its data"),
+                },
+                _ => size as u64,
+            };
+            match id {
+                FMT => {
+                    let mut fmt = vec![0u8; len as usize];
+                    input.read_exact(&mut fmt)?;
+                    format = Some(parse_fmt(&fmt)?);
+                }
+                DATA => data = Some((body, len)),
+                _ => chunks.push(Chunk { id, offset: body, len }),
+            }
+            // Chunks are padded to an even length.
+            offset = body + len + len % 2;
+        }
+        let format = match format {
+            Some(format) => format,
+            None => bail!("WAV file has no fmt chunk"),
+        };
+        let (data_offset, data_len) = match data {
+            Some(data) => data,
+            None => bail!("WAV file has no data chunk"),
+        };
+        input.seek(SeekFrom::Start(data_offset))?;
+        Ok(WavReader {
+            input,
+            format,
+            is_rf64,
+            chunks,
+            data_offset,
+            data_len: data_len.min(file_len - data_offset),
+            position: 0,
+        })
+    }
+
+    pub(crate) fn format(&self) -> StreamFormat {
+        self.format
+    }
+
+    pub(crate) fn is_rf64(&self) -> bool {
+        self.is_rf64
+    }
+
+    /// Every chunk other than `fmt ` and `data`, in file order.
+    pub(crate) fn chunks(&self) -> &[Chunk] {
+        &self.chunks
+    }
+
+    /// The `bext` chunk and `INFO` tags, if the file has them.
+    pub(crate) fn metadata(&mut self) -> Result<WavMetadata, Error> {
+        let mut metadata = WavMetadata::default();
+        for chunk in self.chunks.clone() {
+            match chunk.id {
+                BEXT => metadata.bext = Some(Bext::parse(&self.read_chunk(&chunk)?)?),
+                LIST => {
+                    if let Some(info) = InfoTags::parse(&self.read_chunk(&chunk)?) {
+                        metadata.info = info;
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok(metadata)
+    }
+
+    /// Read the body of `chunk`, leaving the sample position where it was.
+    pub(crate) fn read_chunk(&mut self, chunk: &Chunk) -> Result<Vec<u8>, Error> {
+        let mut body = vec![0u8; chunk.len as usize];
+        self.input.seek(SeekFrom::Start(chunk.offset))?;
+        self.input.read_exact(&mut body)?;
+        self.input.seek(SeekFrom::Start(self.data_offset + self.position))?;
+        Ok(body)
+    }
+
+    pub(crate) fn n_frames(&self) -> u64 {
+        self.data_len / self.format.n_block_align as u64
+    }
+
+    /// Read up to `max_frames` interleaved frames, returning none at the end of the data.
+    pub(crate) fn read<T: Sample>(&mut self, max_frames: usize) -> Result<Vec<T>, Error> {
+        if T::FORMAT != self.format.sample_format {
+            bail!("Can't read {} samples as {}", self.format.sample_format, T::FORMAT);
+        }
+        let block_align = self.format.n_block_align as u64;
+        let frames_left = (self.data_len - self.position) / block_align;
+        let n_bytes = (max_frames as u64).min(frames_left) * block_align;
+        let mut bytes = vec![0u8; n_bytes as usize];
+        self.input.read_exact(&mut bytes)?;
+        self.position += n_bytes;
+        let sample_size = self.format.sample_format.sample_size();
+        Ok(bytes
+            .chunks_exact(sample_size)
+            .map(|sample| unsafe { std::ptr::read_unaligned(sample.as_ptr() as *const T) })
+            .collect())
+    }
+}
@@ -0,0 +1,205 @@
+use std::fs;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use anyhow::Error;
+
+use crate::wav::writer::write_sizes;
+use crate::wav::{
+    DATA, DS64, DS64_SIZE, FMT, JUNK, MAX_WAV_SIZE, RF64, RIFF, SIZE_IN_DS64, WAVE,
+};
+
+/// What `repair` did to a file.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) enum Repair {
+    /// The header already matched the samples.
+    Intact { n_frames: u64 },
+    /// The sizes were recomputed from the file length.
+    Repaired {
+        n_frames: u64,
+        /// Bytes of a partly written frame cut from the end.
+        truncated: u64,
+        is_rf64: bool,
+    },
+}
+
+/// Recover a WAV file whose header wasn't finalized, e.g. because the recording process died.
+///
+/// Anything after the data chunk header is taken to be samples, so the sizes are recomputed
+/// from the file length and a trailing partial frame is dropped. Files whose sizes are
+/// already consistent are left alone.
+pub(crate) fn repair(path: &Path) -> Result<Repair, Error> {
+    let mut file = fs::OpenOptions::new().read(true).write(true).open(path)?;
+    let file_len = file.seek(SeekFrom::End(0))?;
+    file.seek(SeekFrom::Start(0))?;
+    let mut header = [0u8; 12];
+    file.read_exact(&mut header)
+        .map_err(|_| anyhow!("{} is too short to be a WAV file", path.display()))?;
+    let is_rf64 = match [header[0], header[1], header[2], header[3]] {
+        RIFF => false,
+        RF64 => true,
+        _ => bail!("{} isn't a WAV file", path.display()),
+    };
+    if header[8..12] != WAVE {
+        bail!("{} isn't a WAV file", path.display());
+    }
+
+    let mut offset = 12;
+    let mut has_ds64_room = false;
+    let mut ds64_data_len = None;
+    let mut block_align = None;
+    let (data_offset, declared_len) = loop {
+        if offset + 8 > file_len {
+            bail!("{} has no data chunk", path.display());
+        }
+        let (id, size) = read_chunk_header(&mut file, offset)?;
+        let body = offset + 8;
+        match id {
+            JUNK | DS64 if offset == 12 && size as usize >= DS64_SIZE => {
+                has_ds64_room = true;
+                if id == DS64 && is_rf64 {
+                    let mut sizes = [0u8; 16];
+                    file.read_exact(&mut sizes)?;
+                    let mut data_len = [0u8; 8];
+                    data_len.copy_from_slice(&sizes[8..16]);
+                    ds64_data_len = Some(u64::from_le_bytes(data_len));
+                }
+            }
+            FMT if size >= 14 => {
+                let mut fmt = [0u8; 14];
+                file.read_exact(&mut fmt)?;
+                block_align = Some(u16::from_le_bytes([fmt[12], fmt[13]]) as u64);
+            }
+            DATA => match (size, ds64_data_len) {
+                (SIZE_IN_DS64, Some(len)) if is_rf64 => break (body, len),
+                _ => break (body, size as u64),
+            },
+            _ => {}
+        }
+        offset = body + size as u64 + size as u64 % 2;
+    };
+    let block_align = match block_align {
+        Some(block_align) if block_align > 0 => block_align,
+        _ => bail!("{} has no usable fmt chunk before its data", path.display()),
+    };
+
+    let available = file_len - data_offset;
+    if declared_len > 0
+        && declared_len % block_align == 0
+        && ends_with_chunks(&mut file, data_offset + declared_len + declared_len % 2, file_len)?
+    {
+        return Ok(Repair::Intact {
+            n_frames: declared_len / block_align,
+        });
+    }
+    let n_frames = available / block_align;
+    let data_len = n_frames * block_align;
+    file.set_len(data_offset + data_len)?;
+    if data_len % 2 == 1 {
+        file.seek(SeekFrom::End(0))?;
+        file.write_all(&[0])?;
+    }
+    write_sizes(&mut file, data_offset, data_len, block_align, has_ds64_room, MAX_WAV_SIZE)?;
+    file.flush()?;
+    Ok(Repair::Repaired {
+        n_frames,
+        truncated: available - data_len,
+        is_rf64: data_offset + data_len + data_len % 2 - 8 > MAX_WAV_SIZE,
+    })
+}
+
+/// Whether the file from `offset` on is nothing but whole chunks, as after a finished data
+/// chunk. Samples from an unfinished one won't line up like that.
+fn ends_with_chunks(file: &mut fs::File, mut offset: u64, file_len: u64) -> Result<bool, Error> {
+    while offset + 8 <= file_len {
+        let (id, size) = read_chunk_header(file, offset)?;
+        if !id.iter().all(|byte| byte.is_ascii_graphic() || *byte == b' ') {
+            return Ok(false);
+        }
+        offset += 8 + size as u64 + size as u64 % 2;
+    }
+    Ok(offset == file_len)
+}
+
+fn read_chunk_header(file: &mut fs::File, offset: u64) -> Result<([u8; 4], u32), Error> {
+    let mut header = [0u8; 8];
+    file.seek(SeekFrom::Start(offset))?;
+    file.read_exact(&mut header)?;
+    let id = [header[0], header[1], header[2], header[3]];
+    let size = u32::from_le_bytes([header[4], header[5], header[6], header[7]]);
+    Ok((id, size))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::channel_layout::ChannelLayout;
+    use crate::stream_format::{SampleFormat, StreamFormat};
+    use crate::wav::reader::WavReader;
+    use crate::wav::writer::WavWriter;
+    use crate::writer::sample_bytes;
+
+    use super::*;
+
+    fn samples() -> Vec<i16> {
+        (0..202).map(|sample| sample * 101 - 10_000).collect()
+    }
+
+    /// Record `samples()` as stereo to a fresh file, finishing it only if `finish` is set.
+    fn record(name: &str, finish: bool) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!("audia-{}-{}.wav", name, std::process::id()));
+        let format = StreamFormat::new(SampleFormat::I16, 48000, ChannelLayout::STEREO);
+        let mut writer = WavWriter::new(fs::File::create(&path).unwrap(), format).unwrap();
+        writer.write(sample_bytes(&samples())).unwrap();
+        if finish {
+            writer.finish().unwrap();
+        }
+        path
+    }
+
+    fn read_back(path: &Path) -> (u64, Vec<i16>) {
+        let mut reader = WavReader::new(fs::File::open(path).unwrap()).unwrap();
+        let n_frames = reader.n_frames();
+        (n_frames, reader.read::<i16>(n_frames as usize).unwrap())
+    }
+
+    #[test]
+    fn leaves_finished_files_alone() {
+        let path = record("intact", true);
+        let before = fs::read(&path).unwrap();
+        assert_eq!(repair(&path).unwrap(), Repair::Intact { n_frames: 101 });
+        assert_eq!(fs::read(&path).unwrap(), before);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn recovers_a_recording_that_was_never_finished() {
+        let path = record("unfinished", false);
+        // The process died part way through writing another frame.
+        fs::OpenOptions::new().append(true).open(&path).unwrap().write_all(&[1, 2, 3]).unwrap();
+        let repaired = Repair::Repaired {
+            n_frames: 101,
+            truncated: 3,
+            is_rf64: false,
+        };
+        assert_eq!(repair(&path).unwrap(), repaired);
+        assert_eq!(read_back(&path), (101, samples()));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn shrinks_the_header_of_a_truncated_file() {
+        let path = record("truncated", true);
+        let file = fs::OpenOptions::new().write(true).open(&path).unwrap();
+        // Cut the file half way through the 61st frame.
+        file.set_len(file.metadata().unwrap().len() - 40 * 4 - 2).unwrap();
+        let repaired = Repair::Repaired {
+            n_frames: 60,
+            truncated: 2,
+            is_rf64: false,
+        };
+        assert_eq!(repair(&path).unwrap(), repaired);
+        assert_eq!(read_back(&path), (60, samples()[..120].to_vec()));
+        assert_eq!(repair(&path).unwrap(), Repair::Intact { n_frames: 60 });
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,237 @@
+use std::io::{Seek, SeekFrom, Write};
+
+use anyhow::Error;
+
+use crate::stream_format::StreamFormat;
+use crate::wav::{
+    fmt_chunk, DATA, DS64, DS64_SIZE, FMT, JUNK, MAX_WAV_SIZE, RF64, RIFF, SIZE_IN_DS64, WAVE,
+};
+
+/// Writes a WAV file of any size, switching to RF64 when it outgrows 32-bit sizes.
+///
+/// Samples are passed in as little-endian bytes in `format`.
+pub(crate) struct WavWriter<W>
+    where
+        W: Write + Seek,
+{
+    output: W,
+    format: StreamFormat,
+    /// Offset of the first sample.
+    data_offset: u64,
+    data_len: u64,
+    /// Largest size written in 32 bits before the file becomes RF64.
+    max_size: u64,
+}
+
+impl<W> WavWriter<W>
+    where
+        W: Write + Seek,
+{
+    /// Write the header. The sizes in it are filled in by `finish`.
+    pub(crate) fn new(output: W, format: StreamFormat) -> Result<Self, Error> {
+        WavWriter::with_chunks(output, format, &[])
+    }
+
+    /// Write the header followed by `chunks`, such as `bext` or `LIST`, before the samples.
+    pub(crate) fn with_chunks(
+        mut output: W,
+        format: StreamFormat,
+        chunks: &[([u8; 4], Vec<u8>)],
+    ) -> Result<Self, Error> {
+        let fmt = fmt_chunk(&format);
+        let mut header = Vec::with_capacity(64);
+        header.extend_from_slice(&RIFF);
+        header.extend_from_slice(&0u32.to_le_bytes());
+        header.extend_from_slice(&WAVE);
+        // Reserves room for a ds64 chunk; readers skip it while the file is plain WAV.
+        header.extend_from_slice(&JUNK);
+        header.extend_from_slice(&(DS64_SIZE as u32).to_le_bytes());
+        header.extend_from_slice(&[0; DS64_SIZE]);
+        header.extend_from_slice(&FMT);
+        header.extend_from_slice(&(fmt.len() as u32).to_le_bytes());
+        header.extend_from_slice(&fmt);
+        for (id, body) in chunks {
+            header.extend_from_slice(id);
+            header.extend_from_slice(&(body.len() as u32).to_le_bytes());
+            header.extend_from_slice(body);
+            header.resize(header.len() + body.len() % 2, 0);
+        }
+        header.extend_from_slice(&DATA);
+        header.extend_from_slice(&0u32.to_le_bytes());
+        output.write_all(&header)?;
+        Ok(WavWriter {
+            output,
+            format,
+            data_offset: header.len() as u64,
+            data_len: 0,
+            max_size: MAX_WAV_SIZE,
+        })
+    }
+
+    pub(crate) fn format(&self) -> StreamFormat {
+        self.format
+    }
+
+    /// Bytes of samples written so far.
+    pub(crate) fn data_len(&self) -> u64 {
+        self.data_len
+    }
+
+    pub(crate) fn write(&mut self, samples: &[u8]) -> Result<(), Error> {
+        self.output.write_all(samples)?;
+        self.data_len += samples.len() as u64;
+        Ok(())
+    }
+
+    /// Pad the data chunk, fill in the header sizes and hand back the output.
+    pub(crate) fn finish(mut self) -> Result<W, Error> {
+        if self.data_len % 2 == 1 {
+            self.output.write_all(&[0])?;
+        }
+        self.update_header()?;
+        self.output.flush()?;
+        Ok(self.output)
+    }
+
+    /// Write out buffered samples and patch the header to cover them, so the file is playable
+    /// if the process dies before `finish`.
+    pub(crate) fn flush(&mut self) -> Result<(), Error> {
+        self.update_header()?;
+        self.output.flush()?;
+        Ok(())
+    }
+
+    /// Rewrite the header sizes for the samples written so far.
+    fn update_header(&mut self) -> Result<(), Error> {
+        let end = self.output.seek(SeekFrom::Current(0))?;
+        write_sizes(
+            &mut self.output,
+            self.data_offset,
+            self.data_len,
+            self.format.n_block_align as u64,
+            true,
+            self.max_size,
+        )?;
+        self.output.seek(SeekFrom::Start(end))?;
+        Ok(())
+    }
+}
+
+/// Set the RIFF and data chunk sizes of a file whose last chunk is `data_len` bytes of samples
+/// starting at `data_offset`.
+///
+/// Data too big for 32-bit sizes, or over `max_size`, needs room for a `ds64` chunk straight
+/// after the RIFF header, which the `JUNK` chunk `WavWriter` writes there provides.
+pub(crate) fn write_sizes<W>(
+    output: &mut W,
+    data_offset: u64,
+    data_len: u64,
+    block_align: u64,
+    has_ds64_room: bool,
+    max_size: u64,
+) -> Result<(), Error>
+    where
+        W: Write + Seek,
+{
+    let padded_len = data_len + data_len % 2;
+    let riff_size = data_offset + padded_len - 8;
+    let max_size = max_size.min(MAX_WAV_SIZE);
+    if riff_size <= max_size && data_len <= max_size {
+        output.seek(SeekFrom::Start(0))?;
+        output.write_all(&RIFF)?;
+        output.write_all(&(riff_size as u32).to_le_bytes())?;
+        if has_ds64_room {
+            // Undo an earlier upgrade, if the file has since been cut short.
+            output.seek(SeekFrom::Start(12))?;
+            output.write_all(&JUNK)?;
+        }
+        output.seek(SeekFrom::Start(data_offset - 4))?;
+        output.write_all(&(data_len as u32).to_le_bytes())?;
+        return Ok(());
+    }
+    if !has_ds64_room {
+        bail!("{} bytes of samples need RF64, but there's no room for a ds64 chunk", data_len);
+    }
+    let mut ds64 = Vec::with_capacity(DS64_SIZE + 8);
+    ds64.extend_from_slice(&DS64);
+    ds64.extend_from_slice(&(DS64_SIZE as u32).to_le_bytes());
+    ds64.extend_from_slice(&riff_size.to_le_bytes());
+    ds64.extend_from_slice(&data_len.to_le_bytes());
+    ds64.extend_from_slice(&(data_len / block_align).to_le_bytes());
+    // No table of other oversized chunks.
+    ds64.extend_from_slice(&0u32.to_le_bytes());
+    output.seek(SeekFrom::Start(0))?;
+    output.write_all(&RF64)?;
+    output.write_all(&SIZE_IN_DS64.to_le_bytes())?;
+    output.seek(SeekFrom::Start(12))?;
+    output.write_all(&ds64)?;
+    output.seek(SeekFrom::Start(data_offset - 4))?;
+    output.write_all(&SIZE_IN_DS64.to_le_bytes())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use super::*;
+    use crate::channel_layout::ChannelLayout;
+    use crate::stream_format::SampleFormat;
+    use crate::wav::metadata::WavMetadata;
+    use crate::wav::reader::WavReader;
+    use crate::writer::sample_bytes;
+
+    fn stereo() -> StreamFormat {
+        StreamFormat::new(SampleFormat::I16, 48000, ChannelLayout::new(2, 0x3))
+    }
+
+    fn samples() -> Vec<i16> {
+        (0..202).map(|sample| sample * 101 - 10_000).collect()
+    }
+
+    #[test]
+    fn reads_back_wav_files() {
+        let start = UNIX_EPOCH + Duration::from_secs(1_618_317_000);
+        let metadata = WavMetadata::for_recording(&stereo(), Some("Mic"), Some("Take 2"), start);
+        let mut writer =
+            WavWriter::with_chunks(Cursor::new(vec![]), stereo(), &metadata.chunks()).unwrap();
+        writer.write(sample_bytes(&samples())).unwrap();
+        let file = writer.finish().unwrap().into_inner();
+        assert_eq!(file[..4], RIFF);
+        let riff_size = u32::from_le_bytes([file[4], file[5], file[6], file[7]]);
+        assert_eq!(riff_size as usize, file.len() - 8);
+
+        let mut reader = WavReader::new(Cursor::new(file)).unwrap();
+        assert_eq!(reader.format(), stereo());
+        assert!(!reader.is_rf64());
+        assert_eq!(reader.n_frames(), 101);
+        assert_eq!(reader.metadata().unwrap(), metadata);
+        let mut read = reader.read::<i16>(60).unwrap();
+        assert_eq!(read.len(), 120);
+        read.extend(reader.read::<i16>(60).unwrap());
+        assert_eq!(read, samples());
+        assert!(reader.read::<i16>(60).unwrap().is_empty());
+        assert!(reader.read::<f32>(60).is_err());
+    }
+
+    #[test]
+    fn upgrades_to_rf64_past_the_size_limit() {
+        let mut writer = WavWriter::new(Cursor::new(vec![]), stereo()).unwrap();
+        writer.max_size = 256;
+        writer.write(sample_bytes(&samples()[..40])).unwrap();
+        writer.flush().unwrap();
+        assert_eq!(writer.output.get_ref()[..4], RIFF);
+        writer.write(sample_bytes(&samples()[40..])).unwrap();
+        let file = writer.finish().unwrap().into_inner();
+        assert_eq!(file[..4], RF64);
+        assert_eq!(file[4..8], SIZE_IN_DS64.to_le_bytes());
+        assert_eq!(file[12..16], DS64);
+
+        let mut reader = WavReader::new(Cursor::new(file)).unwrap();
+        assert!(reader.is_rf64());
+        assert_eq!(reader.format(), stereo());
+        assert_eq!(reader.n_frames(), 101);
+        assert_eq!(reader.read::<i16>(1000).unwrap(), samples());
+    }
+}
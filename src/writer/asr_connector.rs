@@ -1,38 +1,102 @@
 use anyhow::Error;
 use futures::executor::block_on;
-use log::debug;
+use log::{debug, warn};
 use serde::Serialize;
 
 use crate::asr::python_net_request::{send_to_python, TorchPacket};
-use crate::buffer::ExtensibleBuffer;
+use crate::buffer::{ExtensibleBuffer, SlidingWindow};
+use crate::channel_layout::ChannelLayout;
+use crate::dsp::channel_mix::MixMatrix;
+use crate::dsp::pipeline::FormatConverter;
+use crate::live::LiveHub;
 use crate::stream_format;
-use crate::stream_format::StreamFormat;
+use crate::stream_format::{SampleFormat, StreamFormat};
+use crate::writer::converting_writer::ConvertingWriter;
 use crate::writer::AudioWriter;
 
+/// Sample rate the model is trained at.
+const MODEL_RATE: u32 = 16_000;
+const WINDOW_SECS: usize = 2;
+const HOP_SECS: usize = 1;
+
 pub(crate) struct ASRConnector {
     format: StreamFormat,
+    /// Windows are sent in the processing format rather than the device format.
+    packet_format: StreamFormat,
+    window: SlidingWindow,
+    /// Where transcripts are published for live clients, if anywhere.
+    live: Option<LiveHub>,
+}
+
+impl ASRConnector {
+    /// Feed the model from a capture in `source`, converted to the 16kHz mono it expects.
+    ///
+    /// Channels are folded to mono with `mix` if given, otherwise with the standard downmix.
+    pub(crate) fn for_capture<T>(
+        source: StreamFormat,
+        mix: Option<MixMatrix>,
+        live: Option<LiveHub>,
+    ) -> Result<ConvertingWriter<T, f32, ASRConnector>, Error>
+        where
+            T: hound::Sample + stream_format::Sample + Serialize + Copy,
+    {
+        let target = StreamFormat::new(SampleFormat::F32, MODEL_RATE, ChannelLayout::MONO);
+        let converter = match mix {
+            Some(mix) => FormatConverter::with_mix(source, target, mix)?,
+            None => FormatConverter::new(source, target),
+        };
+        let connector = ASRConnector::with_live(target, live);
+        Ok(ConvertingWriter::with_converter(converter, connector))
+    }
+
+    pub(crate) fn with_live(format: StreamFormat, live: Option<LiveHub>) -> Self {
+        let rate = format.n_sample_per_sec as usize;
+        let packet_format =
+            StreamFormat::new(SampleFormat::F64, format.n_sample_per_sec, format.channel_layout());
+        ASRConnector {
+            format,
+            packet_format,
+            window: SlidingWindow::new(
+                rate * WINDOW_SECS,
+                rate * HOP_SECS,
+                format.n_channels as usize,
+                None,
+            ),
+            live,
+        }
+    }
 }
 
 impl<T> AudioWriter<T> for ASRConnector
     where
         T: hound::Sample + stream_format::Sample + Serialize + Copy,
 {
-    fn new(format: StreamFormat) -> Self {
-        ASRConnector { format }
+    fn new(format: StreamFormat) -> Result<Self, Error> {
+        Ok(ASRConnector::with_live(format, None))
     }
 
     fn write(&mut self, data: &ExtensibleBuffer<T>, frames_available: usize) -> Result<(), Error> {
-        match block_on(send_to_python(TorchPacket {
-            data_packet: data.data.clone(),
-            data_size: data.len,
-            channels: self.format.n_channels as usize,
-        })) {
-            Ok(TextPrediction) => {
-                debug!("Prediction: {:?}", TextPrediction);
-                Ok(())
-            },
-            Err(e) => panic!("Failed to return prediction")
+        let n_channels = self.format.n_channels as usize;
+        self.window.push(data.latest(frames_available * n_channels));
+        for frame in self.window.frames() {
+            let data_size = frame.samples.len();
+            let packet = TorchPacket {
+                data_packet: frame.samples,
+                data_size,
+                channels: n_channels,
+            };
+            match block_on(send_to_python(packet, &self.packet_format)) {
+                Ok(prediction) => {
+                    debug!("Prediction at frame {}: {:?}", frame.start, prediction);
+                    if let Some(live) = &self.live {
+                        live.publish_transcript(&prediction.text);
+                    }
+                }
+                // A missed window only costs its transcript, so keep capturing.
+                Err(e) => warn!("No prediction for frame {}: {}", frame.start, e),
+            }
         }
+        Ok(())
     }
 
     fn close(&mut self) -> Result<(), Error> {
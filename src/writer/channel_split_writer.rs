@@ -0,0 +1,217 @@
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use anyhow::Error;
+use serde::Serialize;
+
+use crate::buffer::ExtensibleBuffer;
+use crate::channel_layout::ChannelLayout;
+use crate::stream_format;
+use crate::stream_format::{SampleFormat, StreamFormat};
+use crate::wav::metadata::WavMetadata;
+use crate::writer::output_path::OutputPath;
+use crate::writer::rf64_writer::Rf64Writer;
+use crate::writer::AudioWriter;
+
+struct SplitChannel<T>
+    where
+        T: hound::Sample + stream_format::Sample + Serialize + Copy,
+{
+    /// Position of the channel in the capture's interleaved frames.
+    index: usize,
+    writer: Box<dyn AudioWriter<T>>,
+}
+
+/// De-interleaves the capture, handing each channel, or a chosen subset, to its own mono
+/// writer.
+pub(crate) struct ChannelSplitWriter<T>
+    where
+        T: hound::Sample + stream_format::Sample + Serialize + Copy,
+{
+    format: StreamFormat,
+    channels: Vec<SplitChannel<T>>,
+}
+
+impl<T> ChannelSplitWriter<T>
+    where
+        T: hound::Sample + stream_format::Sample + Serialize + Copy + 'static,
+{
+    /// Feed channel `index` of the capture to each writer, which must expect
+    /// `channel_format(format, index)`.
+    pub(crate) fn with_writers(
+        format: StreamFormat,
+        writers: Vec<(usize, Box<dyn AudioWriter<T>>)>,
+    ) -> Self {
+        let channels = writers
+            .into_iter()
+            .map(|(index, writer)| {
+                assert!(index < format.n_channels as usize, "No channel {}", index);
+                SplitChannel { index, writer }
+            })
+            .collect();
+        ChannelSplitWriter { format, channels }
+    }
+
+    /// Write `channels`, or every channel, to mono WAV files named after their speakers, e.g.
+    /// `audia-Mic-20210413T120000Z-FL.wav`.
+    pub(crate) fn create(
+        format: StreamFormat,
+        path: &OutputPath,
+        channels: Option<&[usize]>,
+        output: Option<SampleFormat>,
+        metadata: &WavMetadata,
+    ) -> Result<Self, Error> {
+        let layout = format.channel_layout();
+        let all: Vec<usize> = (0..format.n_channels as usize).collect();
+        let path = path.render(SystemTime::now());
+        let mut writers: Vec<(usize, Box<dyn AudioWriter<T>>)> = vec![];
+        for &index in channels.unwrap_or(&all) {
+            if index >= format.n_channels as usize {
+                bail!("No channel {} in a {} channel capture", index + 1, format.n_channels);
+            }
+            let name = channel_name(&layout, index);
+            let mut metadata = metadata.clone();
+            if let Some(bext) = metadata.bext.as_mut() {
+                bext.description = format!("{} ({})", bext.description, name).trim().to_string();
+            }
+            let writer = Rf64Writer::create(
+                channel_format(format, index),
+                with_suffix(&path, &name),
+                output,
+                &metadata,
+            )?;
+            writers.push((index, Box::new(writer)));
+        }
+        Ok(ChannelSplitWriter::with_writers(format, writers))
+    }
+}
+
+impl<T> AudioWriter<T> for ChannelSplitWriter<T>
+    where
+        T: hound::Sample + stream_format::Sample + Serialize + Copy + 'static,
+{
+    fn new(format: StreamFormat) -> Result<Self, Error> {
+        let metadata = WavMetadata::for_recording(&format, None, None, SystemTime::now());
+        ChannelSplitWriter::create(format, &OutputPath::default(), None, None, &metadata)
+    }
+
+    /// Every channel sees every packet; the first error is returned once all have been written.
+    fn write(&mut self, data: &ExtensibleBuffer<T>, frames_available: usize) -> Result<(), Error> {
+        let n_channels = self.format.n_channels as usize;
+        let packet = data.latest(frames_available * n_channels);
+        let frames = packet.len() / n_channels;
+        if frames == 0 {
+            return Ok(());
+        }
+        self.channels
+            .iter_mut()
+            .map(|channel| {
+                // Mono writers only look at the packet they're given, so it needn't be kept.
+                let samples: Vec<T> =
+                    packet.iter().skip(channel.index).step_by(n_channels).copied().collect();
+                channel.writer.write(&ExtensibleBuffer::new(samples, T::FORMAT), frames)
+            })
+            .fold(Ok(()), |result, next| result.and(next))
+    }
+
+    fn close(&mut self) -> Result<(), Error> {
+        self.channels
+            .iter_mut()
+            .map(|channel| channel.writer.close())
+            .fold(Ok(()), |result, next| result.and(next))
+    }
+}
+
+/// Mono format of channel `index`, keeping its speaker position for extensible formats.
+pub(crate) fn channel_format(format: StreamFormat, index: usize) -> StreamFormat {
+    let mask = match format.channel_layout().speakers()[index] {
+        Some(speaker) => speaker.mask(),
+        None => 0,
+    };
+    StreamFormat::new(
+        format.sample_format,
+        format.n_sample_per_sec,
+        ChannelLayout::new(1, mask),
+    )
+}
+
+/// The speaker's short name, or `CH<n>` counting from one for channels with no position.
+pub(crate) fn channel_name(layout: &ChannelLayout, index: usize) -> String {
+    match layout.speakers()[index] {
+        Some(speaker) => speaker.short_name().to_string(),
+        None => format!("CH{}", index + 1),
+    }
+}
+
+/// Parse a comma separated list of channels, each a speaker name such as `FL` or a channel
+/// number counting from one.
+pub(crate) fn parse_channels(list: &str, layout: &ChannelLayout) -> Result<Vec<usize>, Error> {
+    let mut channels = vec![];
+    for channel in list.split(',').map(str::trim).filter(|channel| !channel.is_empty()) {
+        let index = match channel.parse::<usize>() {
+            Ok(number) if (1..=layout.n_channels() as usize).contains(&number) => number - 1,
+            Ok(number) => {
+                bail!("No channel {} in a {} channel capture", number, layout.n_channels())
+            }
+            Err(_) => (0..layout.n_channels() as usize)
+                .find(|&index| channel_name(layout, index).eq_ignore_ascii_case(channel))
+                .ok_or_else(|| anyhow!("The capture has no `{}` channel", channel))?,
+        };
+        if !channels.contains(&index) {
+            channels.push(index);
+        }
+    }
+    if channels.is_empty() {
+        bail!("No channels chosen");
+    }
+    Ok(channels)
+}
+
+/// `path` with `-<suffix>` added to the file name, before the extension.
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let stem = path.file_stem().map(|stem| stem.to_string_lossy()).unwrap_or_default();
+    let name = match path.extension() {
+        Some(extension) => format!("{}-{}.{}", stem, suffix, extension.to_string_lossy()),
+        None => format!("{}-{}", stem, suffix),
+    };
+    path.with_file_name(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::writer::collecting_writer::CollectingWriter;
+
+    #[test]
+    fn feeds_each_writer_its_channel() {
+        // 5.1: FL FR FC LFE BL BR.
+        let format = StreamFormat::new(SampleFormat::I16, 48000, ChannelLayout::new(6, 0x3f));
+        let front_right = CollectingWriter::<i16>::new(channel_format(format, 1)).unwrap();
+        let back_right = CollectingWriter::<i16>::new(channel_format(format, 5)).unwrap();
+        let outputs = [front_right.samples.clone(), back_right.samples.clone()];
+        let mut writer = ChannelSplitWriter::with_writers(
+            format,
+            vec![(1, Box::new(front_right) as Box<_>), (5, Box::new(back_right) as Box<_>)],
+        );
+        let frame = |n: i16| (0..6).map(move |channel| n * 10 + channel);
+        let mut buffer = ExtensibleBuffer::new((0..3).flat_map(frame).collect(), SampleFormat::I16);
+        writer.write(&buffer, 3).unwrap();
+        let packet: Vec<i16> = (3..5).flat_map(frame).collect();
+        buffer.extend(&packet, packet.len());
+        writer.write(&buffer, 2).unwrap();
+        writer.close().unwrap();
+
+        assert_eq!(*outputs[0].lock().unwrap(), [1, 11, 21, 31, 41]);
+        assert_eq!(*outputs[1].lock().unwrap(), [5, 15, 25, 35, 45]);
+    }
+
+    #[test]
+    fn parses_channel_lists() {
+        let layout = ChannelLayout::new(6, 0x3f);
+        assert_eq!(parse_channels("fl, BR,2,FL", &layout).unwrap(), [0, 5, 1]);
+        assert!(parse_channels("7", &layout).is_err());
+        assert!(parse_channels("SL", &layout).is_err());
+        assert!(parse_channels(" , ", &layout).is_err());
+        assert_eq!(channel_name(&ChannelLayout::new(3, 0x3), 2), "CH3");
+    }
+}
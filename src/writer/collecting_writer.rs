@@ -0,0 +1,37 @@
+use std::sync::{Arc, Mutex};
+
+use anyhow::Error;
+use serde::Serialize;
+
+use crate::buffer::ExtensibleBuffer;
+use crate::stream_format;
+use crate::stream_format::StreamFormat;
+use crate::writer::AudioWriter;
+
+/// Keeps every sample it's given, for tests to inspect once it's been handed off.
+pub(crate) struct CollectingWriter<T> {
+    n_channels: usize,
+    pub(crate) samples: Arc<Mutex<Vec<T>>>,
+}
+
+impl<T> AudioWriter<T> for CollectingWriter<T>
+    where
+        T: hound::Sample + stream_format::Sample + Serialize + Copy,
+{
+    fn new(format: StreamFormat) -> Result<Self, Error> {
+        Ok(CollectingWriter {
+            n_channels: format.n_channels as usize,
+            samples: Arc::default(),
+        })
+    }
+
+    fn write(&mut self, data: &ExtensibleBuffer<T>, frames_available: usize) -> Result<(), Error> {
+        let samples = data.latest(frames_available * self.n_channels);
+        self.samples.lock().unwrap().extend_from_slice(samples);
+        Ok(())
+    }
+
+    fn close(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+}
@@ -0,0 +1,119 @@
+use std::marker::PhantomData;
+
+use anyhow::Error;
+use log::warn;
+use serde::Serialize;
+
+use crate::buffer::ExtensibleBuffer;
+use crate::dsp::pipeline::FormatConverter;
+use crate::stream_format;
+use crate::stream_format::StreamFormat;
+use crate::writer::{feed, AudioWriter};
+
+/// Converts the capture to the format a writer asks for before handing it on.
+pub(crate) struct ConvertingWriter<S, T, W>
+    where
+        S: hound::Sample + stream_format::Sample + Serialize + Copy,
+        T: hound::Sample + stream_format::Sample + Serialize + Copy,
+        W: AudioWriter<T>,
+{
+    converter: FormatConverter,
+    buffer: Option<ExtensibleBuffer<T>>,
+    inner: W,
+    phantom_data: PhantomData<S>,
+}
+
+impl<S, T, W> ConvertingWriter<S, T, W>
+    where
+        S: hound::Sample + stream_format::Sample + Serialize + Copy,
+        T: hound::Sample + stream_format::Sample + Serialize + Copy,
+        W: AudioWriter<T>,
+{
+    /// Create `W` for `target` and feed it the capture converted from `source`.
+    pub(crate) fn with_target(source: StreamFormat, target: StreamFormat) -> Result<Self, Error> {
+        Ok(ConvertingWriter::wrap(source, target, W::new(target)?))
+    }
+
+    /// Feed an existing writer, which must expect `target`.
+    pub(crate) fn wrap(source: StreamFormat, target: StreamFormat, inner: W) -> Self {
+        ConvertingWriter::with_converter(FormatConverter::new(source, target), inner)
+    }
+
+    /// Feed an existing writer through `converter`, whose target the writer must expect.
+    pub(crate) fn with_converter(converter: FormatConverter, inner: W) -> Self {
+        assert_eq!(T::FORMAT, converter.target().sample_format);
+        ConvertingWriter {
+            converter,
+            buffer: None,
+            inner,
+            phantom_data: PhantomData,
+        }
+    }
+
+    /// Upsampling can make a packet longer than the rolling buffer, so it's fed in chunks.
+    fn forward(&mut self, converted: Vec<T>) -> Result<(), Error> {
+        let n_channels = self.converter.target().n_channels as usize;
+        feed(&mut self.buffer, &converted, n_channels, &mut self.inner)
+    }
+}
+
+impl<S, T, W> AudioWriter<S> for ConvertingWriter<S, T, W>
+    where
+        S: hound::Sample + stream_format::Sample + Serialize + Copy,
+        T: hound::Sample + stream_format::Sample + Serialize + Copy,
+        W: AudioWriter<T>,
+{
+    /// Without a target, only the sample type changes.
+    fn new(format: StreamFormat) -> Result<Self, Error> {
+        let target = StreamFormat::new(T::FORMAT, format.n_sample_per_sec, format.channel_layout());
+        ConvertingWriter::with_target(format, target)
+    }
+
+    fn write(&mut self, data: &ExtensibleBuffer<S>, frames_available: usize) -> Result<(), Error> {
+        let n_channels = self.converter.source().n_channels as usize;
+        let converted = self.converter.convert(data.latest(frames_available * n_channels));
+        self.forward(converted)
+    }
+
+    fn close(&mut self) -> Result<(), Error> {
+        let remaining = self.converter.flush();
+        self.forward(remaining)?;
+        if self.converter.clipped_samples() > 0 {
+            warn!(
+                "{} samples clipped converting to {:?}",
+                self.converter.clipped_samples(),
+                self.converter.target().sample_format
+            );
+        }
+        self.inner.close()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::buffer::BUFFER_LENGTH;
+    use crate::channel_layout::ChannelLayout;
+    use crate::stream_format::SampleFormat;
+    use crate::writer::collecting_writer::CollectingWriter;
+
+    #[test]
+    fn upsamples_packets_longer_than_the_buffer() {
+        let source = StreamFormat::new(SampleFormat::I16, 8000, ChannelLayout::MONO);
+        let target = StreamFormat::new(SampleFormat::F32, 48000, ChannelLayout::STEREO);
+        let inner = CollectingWriter::<f32>::new(target).unwrap();
+        let output = inner.samples.clone();
+        let mut writer = ConvertingWriter::<i16, f32, _>::wrap(source, target, inner);
+        let n_frames = 40_000;
+        let packet = ExtensibleBuffer::new(vec![8192i16; n_frames], SampleFormat::I16);
+        writer.write(&packet, n_frames).unwrap();
+        writer.close().unwrap();
+
+        let output = output.lock().unwrap();
+        assert!(n_frames * 6 * 2 > BUFFER_LENGTH);
+        assert_eq!(output.len(), n_frames * 6 * 2);
+        // Away from the edges, a constant input stays constant.
+        let middle = &output[output.len() / 2..output.len() / 2 + 100];
+        assert!(middle.iter().all(|sample| (sample - 0.25).abs() < 1e-3), "{:?}", middle);
+    }
+}
@@ -0,0 +1,174 @@
+use std::{fs, io};
+use std::marker::PhantomData;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use anyhow::Error;
+use log::{info, warn};
+use serde::Serialize;
+
+use crate::buffer::ExtensibleBuffer;
+use crate::dsp::conversion::SampleConverter;
+use crate::flac::frame::CompressionLevel;
+use crate::flac::FlacEncoder;
+use crate::stream_format;
+use crate::stream_format::{I24, Sample, SampleFormat, StreamFormat, U8};
+use crate::writer::output_path::{iso_timestamp, OutputPath};
+use crate::writer::AudioWriter;
+
+/// Lossless FLAC recording.
+///
+/// Integer and companded captures are stored exactly. FLAC has no float samples, so float
+/// captures are dithered to 24 bits unless another depth is asked for.
+pub(crate) struct FlacWriter<T>
+    where
+        T: hound::Sample + stream_format::Sample + Serialize + Copy,
+{
+    format: StreamFormat,
+    bits_per_sample: u32,
+    converter: SampleConverter,
+    path: PathBuf,
+    encoder: Option<FlacEncoder<io::BufWriter<fs::File>>>,
+    phantom_data: PhantomData<T>,
+}
+
+impl<T> FlacWriter<T>
+    where
+        T: hound::Sample + stream_format::Sample + Serialize + Copy,
+{
+    /// Create a FLAC file at `path`, tagged with the capture device, start time and format.
+    ///
+    /// `bits_per_sample` may be 8, 16, 24 or 32; by default it's the smallest that holds the
+    /// capture exactly.
+    pub(crate) fn create(
+        format: StreamFormat,
+        path: PathBuf,
+        level: CompressionLevel,
+        bits_per_sample: Option<u32>,
+        device: Option<&str>,
+    ) -> Result<Self, Error> {
+        let bits_per_sample = match bits_per_sample {
+            Some(bits @ 8) | Some(bits @ 16) | Some(bits @ 24) | Some(bits @ 32) => bits,
+            Some(bits) => bail!("Can't write {} bit FLAC, use 8, 16, 24 or 32", bits),
+            None => match T::FORMAT {
+                SampleFormat::U8 => 8,
+                SampleFormat::I16 | SampleFormat::U16 => 16,
+                SampleFormat::ALaw | SampleFormat::MuLaw => 16,
+                SampleFormat::I32 => 32,
+                _ => 24,
+            },
+        };
+        if let Some(parent) = path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+            fs::create_dir_all(parent)?;
+        }
+
+        let mut tags = vec![
+            ("DATE".to_string(), iso_timestamp(SystemTime::now())),
+            ("SOURCE_FORMAT".to_string(), format.to_string()),
+        ];
+        if let Some(device) = device {
+            tags.push(("DEVICE".to_string(), device.to_string()));
+        }
+        let file = io::BufWriter::new(fs::File::create(&path)?);
+        let encoder = FlacEncoder::new(
+            file,
+            format.n_sample_per_sec,
+            format.n_channels as usize,
+            bits_per_sample,
+            level,
+            &tags,
+        )?;
+        info!("Recording to {} at FLAC level {}", path.display(), level.level);
+        Ok(FlacWriter {
+            format,
+            bits_per_sample,
+            converter: SampleConverter::for_export(format.n_channels as usize),
+            path,
+            encoder: Some(encoder),
+            phantom_data: PhantomData,
+        })
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Right-justified integers at the output bit depth.
+    fn to_integers(&mut self, samples: &[T]) -> Vec<i64> {
+        let bits = self.bits_per_sample;
+        match bits {
+            8 => justify(&self.converter.convert::<T, U8>(samples), bits),
+            16 => justify(&self.converter.convert::<T, i16>(samples), bits),
+            24 => justify(&self.converter.convert::<T, I24>(samples), bits),
+            _ => justify(&self.converter.convert::<T, i32>(samples), bits),
+        }
+    }
+}
+
+fn justify<S: Sample>(samples: &[S], bits: u32) -> Vec<i64> {
+    samples
+        .iter()
+        .map(|sample| (sample.to_i32() >> (32 - bits)) as i64)
+        .collect()
+}
+
+impl<T> AudioWriter<T> for FlacWriter<T>
+    where
+        T: hound::Sample + stream_format::Sample + Serialize + Copy,
+{
+    fn new(format: StreamFormat) -> Result<Self, Error> {
+        let path = OutputPath::default().render(SystemTime::now()).with_extension("flac");
+        FlacWriter::create(format, path, CompressionLevel::default(), None, None)
+    }
+
+    fn write(&mut self, data: &ExtensibleBuffer<T>, frames_available: usize) -> Result<(), Error> {
+        let samples = data.latest(frames_available * self.format.n_channels as usize);
+        let integers = self.to_integers(samples);
+        match self.encoder {
+            Some(ref mut encoder) => encoder.write(&integers),
+            None => Err(anyhow!("Writer not initialised")),
+        }
+    }
+
+    fn close(&mut self) -> Result<(), Error> {
+        if let Some(encoder) = self.encoder.take() {
+            encoder.finish()?;
+        }
+        if self.converter.clipped_samples() > 0 {
+            warn!(
+                "{} samples were clipped converting to {} bits",
+                self.converter.clipped_samples(),
+                self.bits_per_sample
+            );
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::channel_layout::ChannelLayout;
+
+    #[test]
+    fn records_files_decoders_can_read() {
+        let format = StreamFormat::new(SampleFormat::I16, 48000, ChannelLayout::new(2, 0x3));
+        let path = std::env::temp_dir().join(format!("audia-flac-{}.flac", std::process::id()));
+        let level = CompressionLevel::default();
+        let mut writer =
+            FlacWriter::<i16>::create(format, path.clone(), level, None, Some("Mic")).unwrap();
+        let samples: Vec<i16> = (0..9000).map(|n| ((n * 37) % 2000 - 1000) as i16).collect();
+        let mut buffer = ExtensibleBuffer::new(samples[..4000].to_vec(), SampleFormat::I16);
+        writer.write(&buffer, 2000).unwrap();
+        buffer.extend(&samples[4000..], 5000);
+        writer.write(&buffer, 2500).unwrap();
+        writer.close().unwrap();
+
+        let mut reader = claxon::FlacReader::open(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(reader.streaminfo().bits_per_sample, 16);
+        assert_eq!(reader.get_tag("DEVICE").collect::<Vec<_>>(), ["Mic"]);
+        let decoded: Vec<i16> = reader.samples().map(|sample| sample.unwrap() as i16).collect();
+        assert_eq!(decoded, samples);
+    }
+}
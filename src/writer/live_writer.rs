@@ -0,0 +1,88 @@
+use std::marker::PhantomData;
+
+use anyhow::Error;
+use serde::Serialize;
+
+use crate::buffer::ExtensibleBuffer;
+use crate::dsp::conversion::SampleConverter;
+use crate::live::{LiveAudio, LiveEvent, LiveHub};
+use crate::opus::OpusEncoder;
+use crate::stream_format;
+use crate::stream_format::StreamFormat;
+use crate::writer::AudioWriter;
+
+enum Encoding {
+    Pcm(SampleConverter),
+    Opus(OpusEncoder),
+}
+
+/// Publishes the capture to a `LiveHub` for WebSocket clients. Levels come from a
+/// `MeterWriter` publishing to the same hub.
+///
+/// Nothing is encoded while no client is connected.
+pub(crate) struct LiveWriter<T>
+    where
+        T: hound::Sample + stream_format::Sample + Serialize + Copy,
+{
+    format: StreamFormat,
+    hub: LiveHub,
+    encoding: Encoding,
+    phantom_data: PhantomData<T>,
+}
+
+impl<T> LiveWriter<T>
+    where
+        T: hound::Sample + stream_format::Sample + Serialize + Copy,
+{
+    pub(crate) fn with_hub(hub: LiveHub) -> Result<Self, Error> {
+        let format = hub.format();
+        let n_channels = format.n_channels as usize;
+        let encoding = match hub.audio() {
+            LiveAudio::Pcm => Encoding::Pcm(SampleConverter::for_export(n_channels)),
+            LiveAudio::Opus(settings) => Encoding::Opus(OpusEncoder::new(format, settings)?),
+        };
+        Ok(LiveWriter {
+            format,
+            hub,
+            encoding,
+            phantom_data: PhantomData,
+        })
+    }
+}
+
+impl<T> AudioWriter<T> for LiveWriter<T>
+    where
+        T: hound::Sample + stream_format::Sample + Serialize + Copy,
+{
+    fn new(format: StreamFormat) -> Result<Self, Error> {
+        LiveWriter::with_hub(LiveHub::new(format, LiveAudio::Pcm))
+    }
+
+    fn write(&mut self, data: &ExtensibleBuffer<T>, frames_available: usize) -> Result<(), Error> {
+        if !self.hub.has_clients() {
+            return Ok(());
+        }
+        let n_channels = self.format.n_channels as usize;
+        let samples = data.latest(frames_available * n_channels);
+        match self.encoding {
+            Encoding::Pcm(ref mut converter) => {
+                self.hub.publish(LiveEvent::Pcm(converter.convert(samples)));
+            }
+            Encoding::Opus(ref mut encoder) => {
+                for packet in encoder.encode(samples)? {
+                    self.hub.publish(LiveEvent::Opus(packet.data));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn close(&mut self) -> Result<(), Error> {
+        if let Encoding::Opus(ref mut encoder) = self.encoding {
+            for packet in encoder.flush()? {
+                self.hub.publish(LiveEvent::Opus(packet.data));
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,288 @@
+use std::marker::PhantomData;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use anyhow::Error;
+use log::{info, warn};
+use serde::Serialize;
+
+use crate::buffer::ExtensibleBuffer;
+use crate::dsp::meter::{ChannelLevels, Meter};
+use crate::live::{LiveEvent, LiveHub};
+use crate::stream_format;
+use crate::stream_format::StreamFormat;
+use crate::writer::channel_split_writer::channel_name;
+use crate::writer::AudioWriter;
+
+/// Metering settings.
+///
+/// Written as comma separated `key=value` pairs, e.g. `window=400ms,log=10s`. `window` is how
+/// long each reading covers and `log` how often readings are logged, or `off`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct MeterSettings {
+    pub(crate) window: Duration,
+    pub(crate) log_interval: Option<Duration>,
+}
+
+impl Default for MeterSettings {
+    fn default() -> Self {
+        MeterSettings {
+            window: Duration::from_millis(400),
+            log_interval: Some(Duration::from_secs(10)),
+        }
+    }
+}
+
+impl FromStr for MeterSettings {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut settings = MeterSettings::default();
+        for setting in s.split(',').map(str::trim).filter(|setting| !setting.is_empty()) {
+            let (key, value) = match setting.find('=') {
+                Some(split) => (&setting[..split], setting[split + 1..].trim()),
+                None => bail!("Meter setting `{}` should be `key=value`", setting),
+            };
+            match key.trim().to_ascii_lowercase().as_str() {
+                "window" => settings.window = parse_duration(value)?,
+                "log" if value.eq_ignore_ascii_case("off") => settings.log_interval = None,
+                "log" => settings.log_interval = Some(parse_duration(value)?),
+                _ => bail!("Unknown meter setting `{}`", key),
+            }
+        }
+        Ok(settings)
+    }
+}
+
+/// A duration in `ms` or `s`, e.g. `400ms`.
+fn parse_duration(value: &str) -> Result<Duration, Error> {
+    let lower = value.to_ascii_lowercase();
+    let duration = if let Some(ms) = lower.strip_suffix("ms") {
+        ms.trim().parse().map(Duration::from_millis)
+    } else if let Some(secs) = lower.strip_suffix('s') {
+        secs.trim().parse().map(Duration::from_secs)
+    } else {
+        bail!("`{}` needs a unit, ms or s", value);
+    }
+    .map_err(|_| anyhow!("`{}` isn't a whole number of ms or s", value))?;
+    if duration == Duration::from_secs(0) {
+        bail!("Meter durations must be greater than zero");
+    }
+    Ok(duration)
+}
+
+/// Measures each channel's RMS, sample peak and true peak, logging them periodically and
+/// warning when the capture is silent. Readings are also published to live clients.
+pub(crate) struct MeterWriter<T>
+    where
+        T: hound::Sample + stream_format::Sample + Serialize + Copy,
+{
+    format: StreamFormat,
+    meter: Meter,
+    log_interval: Option<Duration>,
+    last_log: Instant,
+    /// Whether every window since the last log was digital silence.
+    silent: bool,
+    /// Speaker names of the channels, e.g. `FL`.
+    names: Vec<String>,
+    /// The most recent reading, one entry per channel.
+    latest: Arc<Mutex<Option<Vec<ChannelLevels>>>>,
+    /// Highest true peak of each channel since metering started, in dBFS.
+    max_true_peak: Vec<f32>,
+    /// Where readings are published for live clients, if anywhere.
+    live: Option<LiveHub>,
+    phantom_data: PhantomData<T>,
+}
+
+impl<T> MeterWriter<T>
+    where
+        T: hound::Sample + stream_format::Sample + Serialize + Copy,
+{
+    pub(crate) fn with_settings(
+        format: StreamFormat,
+        settings: MeterSettings,
+        live: Option<LiveHub>,
+    ) -> Self {
+        let n_channels = format.n_channels as usize;
+        let window_frames =
+            (settings.window.as_secs_f64() * format.n_sample_per_sec as f64).round() as usize;
+        let layout = format.channel_layout();
+        MeterWriter {
+            format,
+            meter: Meter::new(n_channels, window_frames.max(1)),
+            log_interval: settings.log_interval,
+            last_log: Instant::now(),
+            silent: true,
+            names: (0..n_channels).map(|index| channel_name(&layout, index)).collect(),
+            latest: Arc::new(Mutex::new(None)),
+            max_true_peak: vec![f32::NEG_INFINITY; n_channels],
+            live,
+            phantom_data: PhantomData,
+        }
+    }
+
+    pub(crate) fn handle(&self) -> MeterHandle {
+        MeterHandle {
+            names: self.names.clone(),
+            latest: Arc::clone(&self.latest),
+        }
+    }
+
+    fn log(&mut self) {
+        let handle = self.handle();
+        let levels = match handle.describe() {
+            Some(levels) => levels,
+            None => return,
+        };
+        if self.silent {
+            warn!("No signal for the last {}s", self.last_log.elapsed().as_secs());
+        } else {
+            info!("Levels (RMS/peak/true peak dBFS): {}", levels);
+        }
+        self.last_log = Instant::now();
+        self.silent = true;
+    }
+}
+
+/// Cloneable handle for reading a `MeterWriter`'s latest levels from another thread.
+#[derive(Clone)]
+pub(crate) struct MeterHandle {
+    names: Vec<String>,
+    latest: Arc<Mutex<Option<Vec<ChannelLevels>>>>,
+}
+
+impl MeterHandle {
+    /// The most recent reading, one entry per channel, or None before the first window ends.
+    pub(crate) fn latest(&self) -> Option<Vec<ChannelLevels>> {
+        self.latest.lock().ok()?.clone()
+    }
+
+    /// The most recent reading as text, e.g. `FL -20.1/-6.0/-5.8, FR -21.3/-7.2/-7.0`.
+    pub(crate) fn describe(&self) -> Option<String> {
+        let levels: Vec<String> = self
+            .names
+            .iter()
+            .zip(self.latest()?.iter())
+            .map(|(name, levels)| {
+                format!("{} {:.1}/{:.1}/{:.1}", name, levels.rms, levels.peak, levels.true_peak)
+            })
+            .collect();
+        Some(levels.join(", "))
+    }
+}
+
+impl<T> AudioWriter<T> for MeterWriter<T>
+    where
+        T: hound::Sample + stream_format::Sample + Serialize + Copy,
+{
+    fn new(format: StreamFormat) -> Result<Self, Error> {
+        Ok(MeterWriter::with_settings(format, MeterSettings::default(), None))
+    }
+
+    fn write(&mut self, data: &ExtensibleBuffer<T>, frames_available: usize) -> Result<(), Error> {
+        let samples = data.latest(frames_available * self.format.n_channels as usize);
+        for channels in self.meter.push(samples) {
+            for (max, levels) in self.max_true_peak.iter_mut().zip(&channels) {
+                *max = max.max(levels.true_peak);
+            }
+            self.silent &= channels.iter().all(|levels| levels.peak == f32::NEG_INFINITY);
+            if let Some(live) = self.live.as_ref().filter(|live| live.has_clients()) {
+                live.publish(LiveEvent::Levels(channels.clone()));
+            }
+            *self
+                .latest
+                .lock()
+                .map_err(|_| anyhow!("Meter reading lock poisoned"))? = Some(channels);
+        }
+        if let Some(interval) = self.log_interval {
+            if self.last_log.elapsed() >= interval {
+                self.log();
+            }
+        }
+        Ok(())
+    }
+
+    fn close(&mut self) -> Result<(), Error> {
+        if self.log_interval.is_some() {
+            let max_true_peak: Vec<String> = self
+                .names
+                .iter()
+                .zip(self.max_true_peak.iter())
+                .map(|(name, true_peak)| format!("{} {:.1}", name, true_peak))
+                .collect();
+            info!("Highest true peak (dBFS): {}", max_true_peak.join(", "));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::channel_layout::ChannelLayout;
+    use crate::live::LiveAudio;
+    use crate::stream_format::SampleFormat;
+
+    #[test]
+    fn parses_settings() {
+        let settings: MeterSettings = "window=100ms, log=off".parse().unwrap();
+        assert_eq!(settings.window, Duration::from_millis(100));
+        assert_eq!(settings.log_interval, None);
+        let settings: MeterSettings = "log=2s".parse().unwrap();
+        assert_eq!(settings.window, MeterSettings::default().window);
+        assert_eq!(settings.log_interval, Some(Duration::from_secs(2)));
+        assert!("window=0ms".parse::<MeterSettings>().is_err());
+        assert!("window=5".parse::<MeterSettings>().is_err());
+        assert!("speed=fast".parse::<MeterSettings>().is_err());
+    }
+
+    #[test]
+    fn publishes_readings_to_live_clients() {
+        let format = StreamFormat::new(SampleFormat::F32, 48000, ChannelLayout::new(2, 0x3));
+        let hub = LiveHub::new(format, LiveAudio::Pcm);
+        let mut events = hub.subscribe();
+        let settings = "window=100ms,log=off".parse().unwrap();
+        let mut writer = MeterWriter::<f32>::with_settings(format, settings, Some(hub));
+
+        // A half scale square wave on the left, silence on the right.
+        let samples: Vec<f32> =
+            (0..9600).flat_map(|frame| vec![0.5 - (frame % 2) as f32, 0.0]).collect();
+        writer.write(&ExtensibleBuffer::new(samples, SampleFormat::F32), 9600).unwrap();
+        writer.close().unwrap();
+
+        for _ in 0..2 {
+            match *events.try_recv().unwrap() {
+                LiveEvent::Levels(ref levels) => {
+                    assert!((levels[0].rms + 6.02).abs() < 0.01);
+                    assert!((levels[0].peak + 6.02).abs() < 0.01);
+                    assert_eq!(levels[1].peak, f32::NEG_INFINITY);
+                }
+                ref event => panic!("Expected levels, got {:?}", event),
+            }
+        }
+        assert!(events.try_recv().is_err());
+        assert_eq!(writer.handle().latest().map(|levels| levels.len()), Some(2));
+    }
+
+    #[test]
+    fn shares_the_latest_reading() {
+        let format = StreamFormat::new(SampleFormat::I16, 1000, ChannelLayout::STEREO);
+        let settings = "window=100ms,log=off".parse().unwrap();
+        let mut writer = MeterWriter::<i16>::with_settings(format, settings, None);
+        let handle = writer.handle();
+        assert_eq!(handle.latest(), None);
+        assert_eq!(handle.describe(), None);
+
+        let loud = ExtensibleBuffer::new([16384i16, 0].repeat(100), SampleFormat::I16);
+        writer.write(&loud, 100).unwrap();
+        let levels = handle.latest().unwrap();
+        assert!((levels[0].rms + 6.02).abs() < 0.01);
+        assert_eq!(levels[1].rms, f32::NEG_INFINITY);
+        assert!(handle.describe().unwrap().starts_with("FL -6.0/-6.0/"));
+
+        let quiet = ExtensibleBuffer::new([1638i16, 0].repeat(100), SampleFormat::I16);
+        writer.write(&quiet, 100).unwrap();
+        assert!((handle.clone().latest().unwrap()[0].rms + 26.02).abs() < 0.01);
+    }
+}
@@ -1,17 +1,39 @@
+use std::mem;
+use std::time::Duration;
+
 use serde::Serialize;
 
-use crate::buffer::ExtensibleBuffer;
+use crate::buffer::{ExtensibleBuffer, BUFFER_LENGTH};
 use crate::stream_format;
 use crate::stream_format::StreamFormat;
 
-mod asr_connector;
-pub(crate) mod hound_writer;
+pub(crate) mod asr_connector;
+pub(crate) mod channel_split_writer;
+#[cfg(test)]
+pub(crate) mod collecting_writer;
+pub(crate) mod converting_writer;
+pub(crate) mod flac_writer;
+pub(crate) mod live_writer;
+pub(crate) mod meter_writer;
+pub(crate) mod network_writer;
+pub(crate) mod opus_writer;
+pub(crate) mod output_path;
+pub(crate) mod raw_pcm_writer;
+pub(crate) mod replay_writer;
+pub(crate) mod rf64_writer;
+pub(crate) mod rtp_writer;
+pub(crate) mod segmented_writer;
+pub(crate) mod tee_writer;
+
+/// How often WAV writers patch their headers, bounding what's lost if the process dies.
+pub(crate) const HEADER_FLUSH_INTERVAL: Duration = Duration::from_secs(5);
 
 pub(crate) trait AudioWriter<T>
     where
         T: hound::Sample + stream_format::Sample + Serialize + Copy,
 {
-    fn new(format: StreamFormat) -> Self
+    /// Create a writer for `format` with default settings.
+    fn new(format: StreamFormat) -> Result<Self, anyhow::Error>
         where
             Self: Sized;
     fn write(
@@ -21,3 +43,52 @@ pub(crate) trait AudioWriter<T>
     ) -> Result<(), anyhow::Error>;
     fn close(&mut self) -> Result<(), anyhow::Error>;
 }
+
+/// Hand interleaved `samples` to `sink` through the rolling buffer it expects, in chunks the
+/// buffer can hold.
+pub(crate) fn feed<T>(
+    buffer: &mut Option<ExtensibleBuffer<T>>,
+    samples: &[T],
+    n_channels: usize,
+    sink: &mut dyn AudioWriter<T>,
+) -> Result<(), anyhow::Error>
+    where
+        T: hound::Sample + stream_format::Sample + Serialize + Copy,
+{
+    for chunk in samples.chunks(BUFFER_LENGTH / n_channels * n_channels) {
+        match buffer {
+            Some(ref mut buffer) => buffer.extend(chunk, chunk.len()),
+            None => *buffer = Some(ExtensibleBuffer::new(chunk.to_vec(), T::FORMAT)),
+        }
+        sink.write(buffer.as_ref().unwrap(), chunk.len() / n_channels)?;
+    }
+    Ok(())
+}
+
+/// WAV header describing samples delivered in `format`.
+///
+/// hound can't write companded WAV files, so G.711 is stored decoded as 16-bit PCM.
+pub(crate) fn wav_spec(format: &StreamFormat) -> hound::WavSpec {
+    let bits_per_sample = if format.sample_format.is_companded() {
+        16
+    } else {
+        format.sample_format.valid_bits() as u16
+    };
+    hound::WavSpec {
+        channels: format.n_channels as u16,
+        sample_rate: format.n_sample_per_sec,
+        bits_per_sample,
+        sample_format: if format.sample_format.is_float() {
+            hound::SampleFormat::Float
+        } else {
+            hound::SampleFormat::Int
+        },
+    }
+}
+
+/// Samples as the little-endian bytes they're stored as in WAV files and raw PCM.
+pub(crate) fn sample_bytes<S: stream_format::Sample>(samples: &[S]) -> &[u8] {
+    // Every sample type is a plain value with no padding, and WASAPI platforms are
+    // little-endian.
+    unsafe { std::slice::from_raw_parts(samples.as_ptr() as *const u8, mem::size_of_val(samples)) }
+}
@@ -0,0 +1,162 @@
+use std::io::{ErrorKind, Write};
+use std::marker::PhantomData;
+use std::net::{Shutdown, SocketAddr, TcpStream, ToSocketAddrs, UdpSocket};
+
+use anyhow::Error;
+use log::{debug, info, warn};
+use serde::Serialize;
+
+use crate::buffer::ExtensibleBuffer;
+use crate::network::{encode_packet, Endpoint, Transport, DEFAULT_PORT, MAX_DATAGRAM_PAYLOAD};
+use crate::stream_format;
+use crate::stream_format::StreamFormat;
+use crate::writer::{sample_bytes, AudioWriter};
+
+enum Connection {
+    Tcp(TcpStream),
+    Udp(UdpSocket),
+}
+
+/// Streams the capture to another machine, which receives it with `NetworkSource`.
+///
+/// Samples are sent in their captured format. Over TCP each capture packet is sent whole; over
+/// UDP it's split into datagrams of whole frames, and the receiver detects what was lost from
+/// the sequence numbers. Losing the receiver stops the stream without failing the capture.
+pub(crate) struct NetworkWriter<T>
+    where
+        T: hound::Sample + stream_format::Sample + Serialize + Copy,
+{
+    format: StreamFormat,
+    endpoint: Endpoint,
+    /// None once the receiver has gone away.
+    connection: Option<Connection>,
+    sequence: u32,
+    frames_sent: u64,
+    phantom_data: PhantomData<T>,
+}
+
+impl<T> NetworkWriter<T>
+    where
+        T: hound::Sample + stream_format::Sample + Serialize + Copy,
+{
+    /// Connect to a receiver at `endpoint`. UDP needs nothing listening yet.
+    pub(crate) fn connect(format: StreamFormat, endpoint: &Endpoint) -> Result<Self, Error> {
+        let address = endpoint
+            .address
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| anyhow!("Couldn't resolve {}", endpoint))?;
+        let connection = match endpoint.transport {
+            Transport::Tcp => {
+                let stream = TcpStream::connect(address)
+                    .map_err(|e| anyhow!("Couldn't connect to {}: {}", endpoint, e))?;
+                stream.set_nodelay(true)?;
+                Connection::Tcp(stream)
+            }
+            Transport::Udp => {
+                let local: SocketAddr = match address {
+                    SocketAddr::V4(_) => ([0, 0, 0, 0], 0).into(),
+                    SocketAddr::V6(_) => ([0u16; 8], 0).into(),
+                };
+                let socket = UdpSocket::bind(local)?;
+                socket.connect(address)?;
+                Connection::Udp(socket)
+            }
+        };
+        info!("Streaming {} to {}", format, endpoint);
+        Ok(NetworkWriter {
+            format,
+            endpoint: endpoint.clone(),
+            connection: Some(connection),
+            sequence: 0,
+            frames_sent: 0,
+            phantom_data: PhantomData,
+        })
+    }
+
+    /// Send `n_frames` frames of `samples` as one packet.
+    fn send(&mut self, samples: &[u8], n_frames: usize, end_of_stream: bool) -> Result<(), Error> {
+        let packet =
+            encode_packet(&self.format, self.sequence, self.frames_sent, end_of_stream, samples);
+        self.sequence = self.sequence.wrapping_add(1);
+        self.frames_sent += n_frames as u64;
+        let sent = match self.connection {
+            Some(Connection::Tcp(ref mut stream)) => stream.write_all(&packet),
+            Some(Connection::Udp(ref socket)) => match socket.send(&packet) {
+                // Nothing listening yet; the receiver picks the stream up when it starts.
+                Err(e) if e.kind() == ErrorKind::ConnectionRefused => {
+                    debug!("Nothing receiving on {}", self.endpoint);
+                    Ok(())
+                }
+                sent => sent.map(|_| ()),
+            },
+            None => return Ok(()),
+        };
+        match sent {
+            Ok(()) => Ok(()),
+            Err(e) if is_disconnect(e.kind()) => {
+                info!("{} closed the connection, no longer streaming to it", self.endpoint);
+                self.connection = None;
+                Ok(())
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+impl<T> AudioWriter<T> for NetworkWriter<T>
+    where
+        T: hound::Sample + stream_format::Sample + Serialize + Copy,
+{
+    fn new(format: StreamFormat) -> Result<Self, Error> {
+        let endpoint = Endpoint {
+            transport: Transport::Tcp,
+            address: format!("127.0.0.1:{}", DEFAULT_PORT),
+        };
+        NetworkWriter::connect(format, &endpoint)
+    }
+
+    fn write(&mut self, data: &ExtensibleBuffer<T>, frames_available: usize) -> Result<(), Error> {
+        if self.connection.is_none() {
+            return Ok(());
+        }
+        let block_align = self.format.n_block_align as usize;
+        let samples = data.latest(frames_available * self.format.n_channels as usize);
+        let bytes = sample_bytes(samples);
+        let frames_per_packet = match self.connection {
+            Some(Connection::Udp(_)) => MAX_DATAGRAM_PAYLOAD / block_align,
+            _ => bytes.len() / block_align,
+        };
+        for packet in bytes.chunks(frames_per_packet.max(1) * block_align) {
+            self.send(packet, packet.len() / block_align, false)?;
+        }
+        Ok(())
+    }
+
+    fn close(&mut self) -> Result<(), Error> {
+        if self.connection.is_none() {
+            return Ok(());
+        }
+        self.send(&[], 0, true)?;
+        if let Some(Connection::Tcp(stream)) = self.connection.take() {
+            match stream.shutdown(Shutdown::Write) {
+                Err(e) if !is_disconnect(e.kind()) && e.kind() != ErrorKind::NotConnected => {
+                    warn!("Couldn't close the connection to {}: {}", self.endpoint, e)
+                }
+                _ => {}
+            }
+        }
+        info!(
+            "Sent {} frames in {} packets to {}",
+            self.frames_sent, self.sequence, self.endpoint
+        );
+        Ok(())
+    }
+}
+
+fn is_disconnect(kind: ErrorKind) -> bool {
+    matches!(
+        kind,
+        ErrorKind::BrokenPipe | ErrorKind::ConnectionReset | ErrorKind::ConnectionAborted
+    )
+}
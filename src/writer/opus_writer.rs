@@ -0,0 +1,243 @@
+use std::{fs, io};
+use std::marker::PhantomData;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use anyhow::Error;
+use log::info;
+use ogg::writing::{PacketWriteEndInfo, PacketWriter};
+use serde::Serialize;
+
+use crate::buffer::ExtensibleBuffer;
+use crate::opus::{OpusEncoder, OpusPacket, OpusSettings, OPUS_RATE};
+use crate::stream_format;
+use crate::stream_format::StreamFormat;
+use crate::writer::output_path::{iso_timestamp, OutputPath};
+use crate::writer::AudioWriter;
+
+const VENDOR: &str = concat!("Audia ", env!("CARGO_PKG_VERSION"));
+
+/// Opus in an Ogg container, as described by RFC 7845.
+pub(crate) struct OpusWriter<T>
+    where
+        T: hound::Sample + stream_format::Sample + Serialize + Copy,
+{
+    format: StreamFormat,
+    encoder: OpusEncoder,
+    path: PathBuf,
+    ogg: Option<PacketWriter<io::BufWriter<fs::File>>>,
+    serial: u32,
+    pre_skip: u64,
+    /// Granule position at the end of the packets written so far, in 48kHz samples. It
+    /// includes the pre-skip, which the encoder flushes out as trailing silence.
+    granule: u64,
+    /// The newest packet is held back so the last one can be flagged as the end of stream.
+    held: Option<OpusPacket>,
+    phantom_data: PhantomData<T>,
+}
+
+impl<T> OpusWriter<T>
+    where
+        T: hound::Sample + stream_format::Sample + Serialize + Copy,
+{
+    /// Create an Ogg Opus file at `path`, tagged with the capture device, start time and
+    /// format.
+    pub(crate) fn create(
+        format: StreamFormat,
+        path: PathBuf,
+        settings: OpusSettings,
+        device: Option<&str>,
+    ) -> Result<Self, Error> {
+        let encoder = OpusEncoder::new(format, settings)?;
+        if let Some(parent) = path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+            fs::create_dir_all(parent)?;
+        }
+        let file = io::BufWriter::new(fs::File::create(&path)?);
+        let mut ogg = PacketWriter::new(file);
+        // Any value works, but distinct serials let streams be chained.
+        let serial = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map(|since_epoch| since_epoch.subsec_nanos())
+            .unwrap_or(0);
+        let pre_skip = encoder.pre_skip()?;
+
+        let mut tags = vec![
+            format!("DATE={}", iso_timestamp(SystemTime::now())),
+            format!("SOURCE_FORMAT={}", format),
+        ];
+        if let Some(device) = device {
+            tags.push(format!("DEVICE={}", device));
+        }
+        let head = opus_head(encoder.n_channels() as u8, pre_skip as u16, format.n_sample_per_sec);
+        ogg.write_packet(head.into_boxed_slice(), serial, PacketWriteEndInfo::EndPage, 0)?;
+        ogg.write_packet(
+            opus_tags(&tags).into_boxed_slice(),
+            serial,
+            PacketWriteEndInfo::EndPage,
+            0,
+        )?;
+        info!("Recording to {} as Opus {:?}", path.display(), settings);
+        Ok(OpusWriter {
+            format,
+            encoder,
+            path,
+            ogg: Some(ogg),
+            serial,
+            pre_skip: pre_skip as u64,
+            granule: 0,
+            held: None,
+            phantom_data: PhantomData,
+        })
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn write_packets(&mut self, packets: Vec<OpusPacket>) -> Result<(), Error> {
+        let ogg = match self.ogg {
+            Some(ref mut ogg) => ogg,
+            None => return Err(anyhow!("Writer not initialised")),
+        };
+        for packet in packets {
+            if let Some(previous) = self.held.replace(packet) {
+                self.granule += previous.samples as u64;
+                ogg.write_packet(
+                    previous.data.into_boxed_slice(),
+                    self.serial,
+                    PacketWriteEndInfo::NormalPacket,
+                    self.granule,
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<T> AudioWriter<T> for OpusWriter<T>
+    where
+        T: hound::Sample + stream_format::Sample + Serialize + Copy,
+{
+    fn new(format: StreamFormat) -> Result<Self, Error> {
+        let path = OutputPath::default().render(SystemTime::now()).with_extension("opus");
+        OpusWriter::create(format, path, OpusSettings::default(), None)
+    }
+
+    fn write(&mut self, data: &ExtensibleBuffer<T>, frames_available: usize) -> Result<(), Error> {
+        let samples = data.latest(frames_available * self.format.n_channels as usize);
+        let packets = self.encoder.encode(samples)?;
+        self.write_packets(packets)
+    }
+
+    fn close(&mut self) -> Result<(), Error> {
+        let packets = self.encoder.flush()?;
+        self.write_packets(packets)?;
+        let mut ogg = match self.ogg.take() {
+            Some(ogg) => ogg,
+            None => return Ok(()),
+        };
+        // The final granule position tells the decoder how much of the padded last frame is
+        // real audio. With no audio at all, the file is left with just its headers rather than
+        // ending on an empty packet, which isn't valid Opus.
+        if let Some(last) = self.held.take() {
+            self.granule += last.samples as u64;
+            ogg.write_packet(
+                last.data.into_boxed_slice(),
+                self.serial,
+                PacketWriteEndInfo::EndStream,
+                self.granule,
+            )?;
+        }
+        ogg.into_inner().into_inner()?;
+        info!(
+            "Wrote {:.1}s of Opus",
+            self.granule.saturating_sub(self.pre_skip) as f64 / OPUS_RATE as f64
+        );
+        Ok(())
+    }
+}
+
+/// Identification header, the first packet of the stream.
+fn opus_head(n_channels: u8, pre_skip: u16, input_rate: u32) -> Vec<u8> {
+    let mut head = b"OpusHead".to_vec();
+    head.push(1);
+    head.push(n_channels);
+    head.extend_from_slice(&pre_skip.to_le_bytes());
+    head.extend_from_slice(&input_rate.to_le_bytes());
+    // Output gain, then channel mapping family 0: mono or stereo, no mapping table.
+    head.extend_from_slice(&0i16.to_le_bytes());
+    head.push(0);
+    head
+}
+
+/// Comment header, Vorbis comments after an `OpusTags` magic.
+fn opus_tags(comments: &[String]) -> Vec<u8> {
+    let mut tags = b"OpusTags".to_vec();
+    tags.extend_from_slice(&(VENDOR.len() as u32).to_le_bytes());
+    tags.extend_from_slice(VENDOR.as_bytes());
+    tags.extend_from_slice(&(comments.len() as u32).to_le_bytes());
+    for comment in comments {
+        tags.extend_from_slice(&(comment.len() as u32).to_le_bytes());
+        tags.extend_from_slice(comment.as_bytes());
+    }
+    tags
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::channel_layout::ChannelLayout;
+    use crate::stream_format::SampleFormat;
+    use ogg::reading::PacketReader;
+
+    /// Record `n_frames` of mono 48kHz audio and read back every packet in the file.
+    fn record(name: &str, n_frames: usize) -> Vec<ogg::Packet> {
+        let format = StreamFormat::new(SampleFormat::I16, 48000, ChannelLayout::new(1, 0x4));
+        let path = std::env::temp_dir()
+            .join(format!("audia-{}-{}.opus", name, std::process::id()));
+        let mut writer =
+            OpusWriter::<i16>::create(format, path.clone(), OpusSettings::default(), None)
+                .unwrap();
+        if n_frames > 0 {
+            let samples: Vec<i16> = (0..n_frames).map(|sample| (sample % 100) as i16).collect();
+            writer.write(&ExtensibleBuffer::new(samples, SampleFormat::I16), n_frames).unwrap();
+        }
+        writer.close().unwrap();
+
+        let mut reader = PacketReader::new(fs::File::open(&path).unwrap());
+        let mut packets = Vec::new();
+        while let Some(packet) = reader.read_packet().unwrap() {
+            packets.push(packet);
+        }
+        fs::remove_file(&path).unwrap();
+        packets
+    }
+
+    #[test]
+    fn ends_the_stream_on_the_last_packet() {
+        let packets = record("opus-eos", 4800);
+        let last = packets.last().unwrap();
+        assert!(packets.len() > 2);
+        assert!(!last.data.is_empty());
+        assert!(last.last_in_stream());
+        assert!(packets.iter().all(|packet| !packet.data.is_empty()));
+    }
+
+    #[test]
+    fn final_granule_counts_the_pre_skip_and_every_frame() {
+        for n_frames in [4800, 4801, 960] {
+            let packets = record("opus-granule", n_frames);
+            let pre_skip = u16::from_le_bytes([packets[0].data[10], packets[0].data[11]]);
+            let last = packets.last().unwrap();
+            assert_eq!(last.absgp_page(), pre_skip as u64 + n_frames as u64);
+        }
+    }
+
+    #[test]
+    fn writes_no_empty_packet_without_audio() {
+        let packets = record("opus-empty", 0);
+        assert_eq!(packets.len(), 2);
+        assert!(packets[0].data.starts_with(b"OpusHead"));
+        assert!(packets[1].data.starts_with(b"OpusTags"));
+    }
+}
@@ -0,0 +1,119 @@
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Used when no template is configured.
+pub(crate) const DEFAULT_TEMPLATE: &str = "audia-{device}-{timestamp}.wav";
+
+/// Template for the files a writer creates.
+///
+/// `{timestamp}` is replaced with the UTC time the file was opened, e.g. `20210413T120000Z`,
+/// `{device}` with the capture device name, made safe to use in a file name, and `{segment}`
+/// with the index of the file when a recording is split, e.g. `0003`. Everything else is used
+/// as written, so the template can include directories.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct OutputPath {
+    template: String,
+    device: String,
+}
+
+impl OutputPath {
+    pub(crate) fn new(template: &str, device: &str) -> Self {
+        OutputPath {
+            template: template.to_string(),
+            device: sanitise(device),
+        }
+    }
+
+    pub(crate) fn template(&self) -> &str {
+        &self.template
+    }
+
+    /// Path for a file opened at `time`.
+    pub(crate) fn render(&self, time: SystemTime) -> PathBuf {
+        self.render_segment(time, 0)
+    }
+
+    /// Path for the `segment`th file of a split recording, opened at `time`.
+    pub(crate) fn render_segment(&self, time: SystemTime, segment: u64) -> PathBuf {
+        PathBuf::from(
+            self.template
+                .replace("{timestamp}", &timestamp(time))
+                .replace("{device}", &self.device)
+                .replace("{segment}", &format!("{:04}", segment)),
+        )
+    }
+}
+
+impl Default for OutputPath {
+    fn default() -> Self {
+        OutputPath::new(DEFAULT_TEMPLATE, "capture")
+    }
+}
+
+/// Replace characters Windows doesn't allow in file names.
+fn sanitise(name: &str) -> String {
+    let name: String = name
+        .trim()
+        .chars()
+        .map(|c| match c {
+            '<' | '>' | ':' | '"' | '/' | '\\' | '|' | '?' | '*' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect();
+    if name.is_empty() {
+        "unknown".to_string()
+    } else {
+        name
+    }
+}
+
+/// Basic ISO 8601 UTC time, without separators that aren't valid in file names.
+pub(crate) fn timestamp(time: SystemTime) -> String {
+    let (year, month, day, hour, minute, second) = utc(time);
+    format!(
+        "{:04}{:02}{:02}T{:02}{:02}{:02}Z",
+        year, month, day, hour, minute, second
+    )
+}
+
+/// Extended ISO 8601 UTC time, e.g. `2021-04-13T12:00:00Z`, for metadata.
+pub(crate) fn iso_timestamp(time: SystemTime) -> String {
+    let (year, month, day, hour, minute, second) = utc(time);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year, month, day, hour, minute, second
+    )
+}
+
+/// UTC (year, month, day, hour, minute, second) of `time`.
+pub(crate) fn utc(time: SystemTime) -> (i64, u32, u32, u64, u64, u64) {
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .map(|since_epoch| since_epoch.as_secs())
+        .unwrap_or(0);
+    let (year, month, day) = civil_from_days((secs / 86_400) as i64);
+    let secs_of_day = secs % 86_400;
+    (year, month, day, secs_of_day / 3600, secs_of_day / 60 % 60, secs_of_day % 60)
+}
+
+/// Gregorian (year, month, day) of a count of days since 1970-01-01.
+///
+/// Howard Hinnant's `civil_from_days`, working in 400 year eras starting on 1 March.
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * month_index + 2) / 5 + 1) as u32;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    } as u32;
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
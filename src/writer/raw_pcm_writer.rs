@@ -0,0 +1,217 @@
+use std::{fs, io};
+use std::io::{ErrorKind, Write};
+use std::marker::PhantomData;
+use std::path::Path;
+
+use anyhow::Error;
+use log::{info, warn};
+use serde::Serialize;
+
+use crate::buffer::ExtensibleBuffer;
+use crate::dsp::conversion::SampleConverter;
+use crate::stream_format;
+use crate::stream_format::{
+    ALaw, I24, I24In32, MuLaw, Sample, SampleFormat, StreamFormat, U8,
+};
+use crate::writer::{sample_bytes, AudioWriter};
+
+/// Headerless interleaved samples written to stdout or a named pipe, for feeding ffmpeg, sox
+/// or an external ASR engine.
+///
+/// Samples are little-endian, the same layout `RawPcmSource` reads. When the reader goes away
+/// the writer stops quietly instead of failing the capture.
+pub(crate) struct RawPcmWriter<T>
+    where
+        T: hound::Sample + stream_format::Sample + Serialize + Copy,
+{
+    format: StreamFormat,
+    /// Format of the samples written. Only the sample format differs from `format`.
+    output_format: StreamFormat,
+    converter: SampleConverter,
+    /// None once the reader has closed the pipe.
+    output: Option<io::BufWriter<Box<dyn Write>>>,
+    phantom_data: PhantomData<T>,
+}
+
+impl<T> RawPcmWriter<T>
+    where
+        T: hound::Sample + stream_format::Sample + Serialize + Copy,
+{
+    /// Write to stdout. Nothing else may print to stdout while this is in use.
+    pub(crate) fn stdout(format: StreamFormat, output: Option<SampleFormat>) -> Self {
+        RawPcmWriter::with_output(format, Box::new(io::stdout()), output)
+    }
+
+    /// Write to an existing FIFO, or a `\\.\pipe\` named pipe on Windows.
+    ///
+    /// Opening a FIFO blocks until something opens it for reading.
+    pub(crate) fn pipe(
+        format: StreamFormat,
+        path: &Path,
+        output: Option<SampleFormat>,
+    ) -> Result<Self, Error> {
+        let pipe = fs::OpenOptions::new()
+            .write(true)
+            .open(path)
+            .map_err(|e| anyhow!("Couldn't open {} for raw output: {}", path.display(), e))?;
+        info!("Writing raw PCM to {}", path.display());
+        Ok(RawPcmWriter::with_output(format, Box::new(pipe), output))
+    }
+
+    /// Samples are written in their captured format unless `output` asks for another, which is
+    /// dithered as for recordings.
+    pub(crate) fn with_output(
+        format: StreamFormat,
+        output: Box<dyn Write>,
+        sample_format: Option<SampleFormat>,
+    ) -> Self {
+        let sample_format = sample_format.unwrap_or(T::FORMAT);
+        let output_format =
+            StreamFormat::new(sample_format, format.n_sample_per_sec, format.channel_layout());
+        info!("Raw PCM output is {}", output_format);
+        RawPcmWriter {
+            format,
+            output_format,
+            converter: SampleConverter::for_export(format.n_channels as usize),
+            output: Some(io::BufWriter::new(output)),
+            phantom_data: PhantomData,
+        }
+    }
+
+    pub(crate) fn output_format(&self) -> StreamFormat {
+        self.output_format
+    }
+}
+
+impl<T> AudioWriter<T> for RawPcmWriter<T>
+    where
+        T: hound::Sample + stream_format::Sample + Serialize + Copy,
+{
+    fn new(format: StreamFormat) -> Result<Self, Error> {
+        Ok(RawPcmWriter::stdout(format, None))
+    }
+
+    fn write(&mut self, data: &ExtensibleBuffer<T>, frames_available: usize) -> Result<(), Error> {
+        if self.output.is_none() {
+            return Ok(());
+        }
+        let samples = data.latest(frames_available * self.format.n_channels as usize);
+        let converter = &mut self.converter;
+        let output = &mut self.output;
+        match self.output_format.sample_format {
+            format if format == T::FORMAT => write_samples(output, samples),
+            SampleFormat::U8 => write_samples(output, &converter.convert::<T, U8>(samples)),
+            SampleFormat::I16 => write_samples(output, &converter.convert::<T, i16>(samples)),
+            SampleFormat::I24 => write_samples(output, &converter.convert::<T, I24>(samples)),
+            SampleFormat::I24In32 => {
+                write_samples(output, &converter.convert::<T, I24In32>(samples))
+            }
+            SampleFormat::I32 => write_samples(output, &converter.convert::<T, i32>(samples)),
+            SampleFormat::U16 => write_samples(output, &converter.convert::<T, u16>(samples)),
+            SampleFormat::F32 => write_samples(output, &converter.convert::<T, f32>(samples)),
+            SampleFormat::F64 => write_samples(output, &converter.convert::<T, f64>(samples)),
+            SampleFormat::ALaw => write_samples(output, &converter.convert::<T, ALaw>(samples)),
+            SampleFormat::MuLaw => write_samples(output, &converter.convert::<T, MuLaw>(samples)),
+        }
+    }
+
+    fn close(&mut self) -> Result<(), Error> {
+        if let Some(mut output) = self.output.take() {
+            match output.flush() {
+                Err(e) if e.kind() != ErrorKind::BrokenPipe => return Err(e.into()),
+                _ => drop(output.into_parts()),
+            }
+        }
+        if self.converter.clipped_samples() > 0 {
+            warn!(
+                "{} samples were clipped converting to {}",
+                self.converter.clipped_samples(),
+                self.output_format.sample_format
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Write `samples` to `output`, dropping it if the reader has gone away.
+fn write_samples<S: Sample>(
+    output: &mut Option<io::BufWriter<Box<dyn Write>>>,
+    samples: &[S],
+) -> Result<(), Error> {
+    let writer = match output {
+        Some(writer) => writer,
+        None => return Ok(()),
+    };
+    let bytes = sample_bytes(samples);
+    // Flush every packet so readers see audio as soon as it's captured.
+    match writer.write_all(bytes).and_then(|_| writer.flush()) {
+        Ok(()) => Ok(()),
+        Err(e) if e.kind() == ErrorKind::BrokenPipe => {
+            info!("Raw PCM reader closed the pipe, no longer writing to it");
+            // The buffered bytes have nowhere to go, so drop them rather than flushing.
+            if let Some(writer) = output.take() {
+                drop(writer.into_parts());
+            }
+            Ok(())
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use crate::channel_layout::ChannelLayout;
+
+    use super::*;
+
+    /// A pipe whose reader goes away after `open_for` writes.
+    struct ClosingPipe {
+        open_for: usize,
+        calls: Arc<Mutex<usize>>,
+        received: Arc<Mutex<Vec<u8>>>,
+    }
+
+    impl Write for ClosingPipe {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            let mut calls = self.calls.lock().unwrap();
+            *calls += 1;
+            if *calls > self.open_for {
+                return Err(io::Error::new(ErrorKind::BrokenPipe, "reader closed"));
+            }
+            self.received.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn stops_quietly_when_the_reader_goes_away() {
+        let calls = Arc::new(Mutex::new(0));
+        let received = Arc::new(Mutex::new(vec![]));
+        let pipe = ClosingPipe {
+            open_for: 1,
+            calls: calls.clone(),
+            received: received.clone(),
+        };
+        let format = StreamFormat::new(SampleFormat::I16, 48000, ChannelLayout::MONO);
+        let mut writer = RawPcmWriter::<i16>::with_output(format, Box::new(pipe), None);
+
+        let packet = ExtensibleBuffer::new(vec![1i16, -2], SampleFormat::I16);
+        writer.write(&packet, 2).unwrap();
+        assert_eq!(*received.lock().unwrap(), vec![1, 0, 0xFE, 0xFF]);
+
+        writer.write(&packet, 2).unwrap();
+        assert!(writer.output.is_none());
+        let calls_when_closed = *calls.lock().unwrap();
+
+        writer.write(&packet, 2).unwrap();
+        writer.close().unwrap();
+        assert_eq!(*calls.lock().unwrap(), calls_when_closed);
+        assert_eq!(received.lock().unwrap().len(), 4);
+    }
+}
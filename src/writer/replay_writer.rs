@@ -0,0 +1,197 @@
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::Error;
+use log::info;
+use serde::Serialize;
+
+use crate::buffer::ExtensibleBuffer;
+use crate::stream_format;
+use crate::stream_format::StreamFormat;
+use crate::writer::{wav_spec, AudioWriter};
+
+const DEFAULT_HISTORY_SECS: u64 = 600;
+
+struct ReplayHistory<T> {
+    samples: VecDeque<T>,
+    max_samples: usize,
+    /// Wall clock time of the last sample in `samples`.
+    last_write: SystemTime,
+}
+
+/// Keeps a rolling history of the capture in memory so that it can be saved after the fact.
+///
+/// Nothing touches the disk until `ReplayHandle::dump` is called.
+pub(crate) struct ReplayWriter<T> {
+    format: StreamFormat,
+    history: Arc<Mutex<ReplayHistory<T>>>,
+}
+
+/// Cloneable handle used to dump a `ReplayWriter`'s history from another thread.
+#[derive(Clone)]
+pub(crate) struct ReplayHandle<T> {
+    format: StreamFormat,
+    history: Arc<Mutex<ReplayHistory<T>>>,
+}
+
+impl<T> ReplayWriter<T>
+    where
+        T: hound::Sample + stream_format::Sample + Serialize + Copy,
+{
+    /// Keep the last `history` of audio.
+    pub(crate) fn with_history(format: StreamFormat, history: Duration) -> Self {
+        let frames = history.as_secs_f64() * format.n_sample_per_sec as f64;
+        let max_samples = frames as usize * format.n_channels as usize;
+        ReplayWriter {
+            format,
+            history: Arc::new(Mutex::new(ReplayHistory {
+                // Grown as audio arrives, so a long history isn't allocated before it's needed.
+                samples: VecDeque::new(),
+                max_samples,
+                last_write: SystemTime::now(),
+            })),
+        }
+    }
+
+    pub(crate) fn handle(&self) -> ReplayHandle<T> {
+        ReplayHandle {
+            format: self.format,
+            history: Arc::clone(&self.history),
+        }
+    }
+}
+
+impl<T> AudioWriter<T> for ReplayWriter<T>
+    where
+        T: hound::Sample + stream_format::Sample + Serialize + Copy,
+{
+    fn new(format: StreamFormat) -> Result<Self, Error> {
+        Ok(ReplayWriter::with_history(format, Duration::from_secs(DEFAULT_HISTORY_SECS)))
+    }
+
+    fn write(&mut self, data: &ExtensibleBuffer<T>, frames_available: usize) -> Result<(), Error> {
+        let packet = data.latest(frames_available * self.format.n_channels as usize);
+        let mut history = self
+            .history
+            .lock()
+            .map_err(|_| anyhow!("Replay history lock poisoned"))?;
+        history.samples.extend(packet.iter().copied());
+        let excess = history.samples.len().saturating_sub(history.max_samples);
+        history.samples.drain(..excess);
+        history.last_write = SystemTime::now();
+        Ok(())
+    }
+
+    fn close(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+impl<T> ReplayHandle<T>
+    where
+        T: hound::Sample + stream_format::Sample + Serialize + Copy,
+{
+    /// Write the current history to a WAV file.
+    ///
+    /// Without a `path` the file is named after the wall clock time of its first sample, e.g.
+    /// `replay-1618317000.250.wav`. Returns the path written to.
+    pub(crate) fn dump(&self, path: Option<&Path>) -> Result<PathBuf, Error> {
+        // Copy the history out so capture isn't blocked while the file is written.
+        let (samples, last_write) = {
+            let history = self
+                .history
+                .lock()
+                .map_err(|_| anyhow!("Replay history lock poisoned"))?;
+            (history.samples.iter().copied().collect::<Vec<T>>(), history.last_write)
+        };
+        let frames = samples.len() / self.format.n_channels as usize;
+        let start = last_write
+            - Duration::from_secs_f64(frames as f64 / self.format.n_sample_per_sec as f64);
+
+        let path = match path {
+            Some(path) => path.to_path_buf(),
+            None => {
+                let since_epoch = start.duration_since(UNIX_EPOCH)?;
+                PathBuf::from(format!(
+                    "replay-{}.{:03}.wav",
+                    since_epoch.as_secs(),
+                    since_epoch.subsec_millis()
+                ))
+            }
+        };
+        let mut writer = hound::WavWriter::create(&path, wav_spec(&self.format))?;
+        for sample in samples {
+            writer.write_sample(sample)?;
+        }
+        writer.finalize()?;
+        info!(
+            "Saved {:.1}s replay starting at {:?} to {}",
+            frames as f64 / self.format.n_sample_per_sec as f64,
+            start,
+            path.display()
+        );
+        Ok(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::channel_layout::ChannelLayout;
+    use crate::stream_format::SampleFormat;
+
+    use super::*;
+
+    fn stereo() -> StreamFormat {
+        StreamFormat::new(SampleFormat::I16, 1000, ChannelLayout::STEREO)
+    }
+
+    /// Write `n_frames` stereo frames counting up from `first`, in packets of 100 frames.
+    fn record(writer: &mut ReplayWriter<i16>, first: i16, n_frames: i16) {
+        let frames: Vec<i16> = (first..first + n_frames).flat_map(|i| vec![i, -i]).collect();
+        for packet in frames.chunks(200) {
+            let data = ExtensibleBuffer::new(packet.to_vec(), SampleFormat::I16);
+            writer.write(&data, packet.len() / 2).unwrap();
+        }
+    }
+
+    #[test]
+    fn keeps_only_the_most_recent_history() {
+        let mut writer = ReplayWriter::<i16>::with_history(stereo(), Duration::from_millis(500));
+        record(&mut writer, 0, 1200);
+        let history = writer.history.lock().unwrap();
+        assert_eq!(history.max_samples, 1000);
+        assert_eq!(history.samples.len(), 1000);
+        assert_eq!(history.samples.front(), Some(&700));
+        assert_eq!(history.samples.back(), Some(&-1199));
+    }
+
+    #[test]
+    fn dumps_the_most_recent_frames() {
+        let mut writer = ReplayWriter::<i16>::with_history(stereo(), Duration::from_millis(300));
+        record(&mut writer, 0, 1000);
+        let path = std::env::temp_dir().join(format!("audia-replay-{}.wav", std::process::id()));
+        assert_eq!(writer.handle().dump(Some(&path)).unwrap(), path);
+
+        let mut reader = hound::WavReader::open(&path).unwrap();
+        assert_eq!(reader.spec().channels, 2);
+        assert_eq!(reader.duration(), 300);
+        let samples: Vec<i16> = reader.samples().map(Result::unwrap).collect();
+        let expected: Vec<i16> = (700..1000).flat_map(|i| vec![i, -i]).collect();
+        assert_eq!(samples, expected);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn names_dumps_after_their_first_sample() {
+        let mut writer = ReplayWriter::<i16>::with_history(stereo(), Duration::from_secs(1));
+        record(&mut writer, 0, 300);
+        // 300 frames at 1kHz, so the replay started 0.3s before the last write.
+        writer.history.lock().unwrap().last_write =
+            UNIX_EPOCH + Duration::from_millis(1_618_317_000_550);
+        let path = writer.handle().dump(None).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(path, PathBuf::from("replay-1618317000.250.wav"));
+    }
+}
@@ -0,0 +1,195 @@
+use std::{fs, io};
+use std::marker::PhantomData;
+use std::path::{Path, PathBuf};
+use std::time::{Instant, SystemTime};
+
+use anyhow::Error;
+use log::{info, warn};
+use serde::Serialize;
+
+use crate::buffer::ExtensibleBuffer;
+use crate::dsp::conversion::SampleConverter;
+use crate::stream_format;
+use crate::stream_format::{
+    ALaw, I24, I24In32, MuLaw, SampleFormat, StreamFormat, U8,
+};
+use crate::wav::metadata::WavMetadata;
+use crate::wav::writer::WavWriter;
+use crate::writer::output_path::OutputPath;
+use crate::writer::{sample_bytes, AudioWriter, HEADER_FLUSH_INTERVAL};
+
+/// WAV recording with no 4GB limit.
+///
+/// Files start out as ordinary WAV and only become RF64 if they grow past 4GB, so short
+/// recordings open anywhere. The header is patched every few seconds, so a crash loses at most
+/// the last few seconds. G.711 captures are kept companded.
+pub(crate) struct Rf64Writer<T>
+    where
+        T: hound::Sample + stream_format::Sample + Serialize + Copy,
+{
+    format: StreamFormat,
+    /// Format of the samples in the file.
+    output_format: StreamFormat,
+    converter: SampleConverter,
+    path: PathBuf,
+    writer: Option<WavWriter<io::BufWriter<fs::File>>>,
+    last_flush: Instant,
+    phantom_data: PhantomData<T>,
+}
+
+impl<T> Rf64Writer<T>
+    where
+        T: hound::Sample + stream_format::Sample + Serialize + Copy,
+{
+    /// Create a WAV file at `path`, embedding `metadata` ahead of the samples.
+    ///
+    /// Samples are written in their captured format unless `output` asks for another; reducing
+    /// the bit depth is dithered. WAV has no unsigned 16-bit samples, so those are made signed.
+    pub(crate) fn create(
+        format: StreamFormat,
+        path: PathBuf,
+        output: Option<SampleFormat>,
+        metadata: &WavMetadata,
+    ) -> Result<Self, Error> {
+        let output = match output.unwrap_or(T::FORMAT) {
+            SampleFormat::U16 => SampleFormat::I16,
+            output => output,
+        };
+        let output_format =
+            StreamFormat::new(output, format.n_sample_per_sec, format.channel_layout());
+        if let Some(parent) = path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+            fs::create_dir_all(parent)?;
+        }
+        let file = io::BufWriter::new(fs::File::create(&path)?);
+        let mut metadata = metadata.clone();
+        if let Some(bext) = metadata.bext.as_mut() {
+            bext.set_coding_history(&output_format);
+        }
+        let writer = WavWriter::with_chunks(file, output_format, &metadata.chunks())?;
+        info!("Recording to {}", path.display());
+        Ok(Rf64Writer {
+            format,
+            output_format,
+            converter: SampleConverter::for_export(format.n_channels as usize),
+            path,
+            writer: Some(writer),
+            last_flush: Instant::now(),
+            phantom_data: PhantomData,
+        })
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub(crate) fn output_format(&self) -> StreamFormat {
+        self.output_format
+    }
+}
+
+impl<T> AudioWriter<T> for Rf64Writer<T>
+    where
+        T: hound::Sample + stream_format::Sample + Serialize + Copy,
+{
+    fn new(format: StreamFormat) -> Result<Self, Error> {
+        let start = SystemTime::now();
+        let metadata = WavMetadata::for_recording(&format, None, None, start);
+        Rf64Writer::create(format, OutputPath::default().render(start), None, &metadata)
+    }
+
+    fn write(&mut self, data: &ExtensibleBuffer<T>, frames_available: usize) -> Result<(), Error> {
+        let samples = data.latest(frames_available * self.format.n_channels as usize);
+        let writer = match self.writer {
+            Some(ref mut writer) => writer,
+            None => return Err(anyhow!("Writer not initialised")),
+        };
+        let converter = &mut self.converter;
+        let written = match writer.format().sample_format {
+            output if output == T::FORMAT => writer.write(sample_bytes(samples)),
+            SampleFormat::U8 => writer.write(sample_bytes(&converter.convert::<T, U8>(samples))),
+            SampleFormat::I16 => {
+                writer.write(sample_bytes(&converter.convert::<T, i16>(samples)))
+            }
+            SampleFormat::I24 => {
+                writer.write(sample_bytes(&converter.convert::<T, I24>(samples)))
+            }
+            SampleFormat::I24In32 => {
+                writer.write(sample_bytes(&converter.convert::<T, I24In32>(samples)))
+            }
+            SampleFormat::I32 => {
+                writer.write(sample_bytes(&converter.convert::<T, i32>(samples)))
+            }
+            SampleFormat::F32 => {
+                writer.write(sample_bytes(&converter.convert::<T, f32>(samples)))
+            }
+            SampleFormat::F64 => {
+                writer.write(sample_bytes(&converter.convert::<T, f64>(samples)))
+            }
+            SampleFormat::ALaw => {
+                writer.write(sample_bytes(&converter.convert::<T, ALaw>(samples)))
+            }
+            SampleFormat::MuLaw => {
+                writer.write(sample_bytes(&converter.convert::<T, MuLaw>(samples)))
+            }
+            output => Err(anyhow!("Can't write {} samples to a WAV file", output)),
+        };
+        written?;
+        if self.last_flush.elapsed() >= HEADER_FLUSH_INTERVAL {
+            writer.flush()?;
+            self.last_flush = Instant::now();
+        }
+        Ok(())
+    }
+
+    fn close(&mut self) -> Result<(), Error> {
+        let writer = match self.writer.take() {
+            Some(writer) => writer,
+            None => return Ok(()),
+        };
+        let output_format = writer.format();
+        if writer.data_len() > u32::MAX as u64 {
+            info!("{} is over 4GB, finishing it as RF64", self.path.display());
+        }
+        writer.finish()?.into_inner()?;
+        if self.converter.clipped_samples() > 0 {
+            warn!(
+                "{} samples were clipped converting to {}",
+                self.converter.clipped_samples(),
+                output_format.sample_format
+            );
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::channel_layout::ChannelLayout;
+    use crate::wav::reader::WavReader;
+
+    #[test]
+    fn records_files_readers_can_parse() {
+        let format = StreamFormat::new(SampleFormat::F32, 44100, ChannelLayout::new(2, 0x3));
+        let path = std::env::temp_dir().join(format!("audia-rf64-{}.wav", std::process::id()));
+        let metadata = WavMetadata::for_recording(&format, Some("Mic"), None, SystemTime::now());
+        let mut writer =
+            Rf64Writer::<f32>::create(format, path.clone(), Some(SampleFormat::I16), &metadata)
+                .unwrap();
+        let samples: Vec<f32> = (0..200).map(|sample| sample as f32 / 200.0 - 0.5).collect();
+        writer.write(&ExtensibleBuffer::new(samples, SampleFormat::F32), 100).unwrap();
+        writer.close().unwrap();
+
+        let mut reader = WavReader::new(fs::File::open(&path).unwrap()).unwrap();
+        fs::remove_file(&path).unwrap();
+        let output = StreamFormat::new(SampleFormat::I16, 44100, ChannelLayout::new(2, 0x3));
+        assert_eq!(reader.format(), output);
+        assert_eq!(reader.n_frames(), 100);
+        let bext = reader.metadata().unwrap().bext.unwrap();
+        assert_eq!(bext.description, "Recorded from Mic");
+        assert!(bext.coding_history.contains("A=PCM,F=44100,W=16,M=stereo"));
+        let read = reader.read::<i16>(100).unwrap();
+        assert_eq!(read.len(), 200);
+        assert!(read[0] <= -16383 && read[0] >= -16385);
+    }
+}
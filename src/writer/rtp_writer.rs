@@ -0,0 +1,367 @@
+use std::io::ErrorKind;
+use std::marker::PhantomData;
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::time::{Duration, Instant, SystemTime};
+
+use anyhow::Error;
+use log::{debug, info, warn};
+use serde::Serialize;
+
+use crate::buffer::ExtensibleBuffer;
+use crate::dsp::conversion::SampleConverter;
+use crate::opus::{OpusEncoder, OpusPacket};
+use crate::rtp::{
+    ntp_timestamp, random_u32, rtcp_report, rtp_header, sdp, RtpPayload, SenderStats,
+    MAX_PAYLOAD,
+};
+use crate::stream_format;
+use crate::stream_format::StreamFormat;
+use crate::writer::AudioWriter;
+
+/// How often RTCP sender reports go out. RFC 3550 suggests no more often than every 5s.
+const REPORT_INTERVAL: Duration = Duration::from_secs(5);
+
+enum Encoding {
+    L16 {
+        converter: SampleConverter,
+        /// Converted samples not yet making up a whole packet.
+        pending: Vec<i16>,
+    },
+    Opus(OpusEncoder),
+}
+
+/// Streams the capture as RTP, so VoIP and media tools can receive it.
+///
+/// RTP goes to the given port and RTCP to the port above it. `sdp` describes the stream for
+/// receivers that can't be told about it any other way.
+pub(crate) struct RtpWriter<T>
+    where
+        T: hound::Sample + stream_format::Sample + Serialize + Copy,
+{
+    format: StreamFormat,
+    payload: RtpPayload,
+    encoding: Encoding,
+    /// Frames per packet, at the RTP clock rate.
+    packet_frames: usize,
+    rtp: UdpSocket,
+    rtcp: UdpSocket,
+    destination: SocketAddr,
+    cname: String,
+    ssrc: u32,
+    sequence: u16,
+    /// RTP timestamp of the next packet.
+    timestamp: u32,
+    packet_count: u32,
+    octet_count: u32,
+    /// The first packet carries the marker bit, as the start of a talkspurt.
+    marker: bool,
+    last_report: Option<Instant>,
+    phantom_data: PhantomData<T>,
+}
+
+impl<T> RtpWriter<T>
+    where
+        T: hound::Sample + stream_format::Sample + Serialize + Copy,
+{
+    /// Stream to `address`, a `host:port` whose port should be even.
+    pub(crate) fn connect(
+        format: StreamFormat,
+        address: &str,
+        payload: RtpPayload,
+    ) -> Result<Self, Error> {
+        let destination = address
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| anyhow!("Couldn't resolve {}", address))?;
+        if destination.port() % 2 == 1 {
+            warn!("RTP should go to an even port, {} may not be received", destination.port());
+        }
+        let mut rtcp_destination = destination;
+        rtcp_destination.set_port(destination.port().wrapping_add(1));
+        let rtp = connect_udp(destination)?;
+        let rtcp = connect_udp(rtcp_destination)?;
+        let (encoding, packet_frames) = match payload {
+            RtpPayload::L16 => (
+                Encoding::L16 {
+                    converter: SampleConverter::for_export(format.n_channels as usize),
+                    pending: Vec::new(),
+                },
+                l16_packet_frames(&format),
+            ),
+            RtpPayload::Opus(settings) => {
+                (Encoding::Opus(OpusEncoder::new(format, settings)?), settings.frame_size)
+            }
+        };
+        let cname = format!("audia@{}", rtp.local_addr()?.ip());
+        info!("Streaming RTP to {}", destination);
+        Ok(RtpWriter {
+            format,
+            payload,
+            encoding,
+            packet_frames,
+            rtp,
+            rtcp,
+            destination,
+            cname,
+            ssrc: random_u32(),
+            sequence: random_u32() as u16,
+            timestamp: random_u32(),
+            packet_count: 0,
+            octet_count: 0,
+            marker: true,
+            last_report: None,
+            phantom_data: PhantomData,
+        })
+    }
+
+    /// Session description for receivers.
+    pub(crate) fn sdp(&self, session_name: &str) -> Result<String, Error> {
+        Ok(sdp(
+            &self.payload,
+            &self.format,
+            self.packet_frames,
+            self.rtp.local_addr()?,
+            self.destination,
+            session_name,
+        ))
+    }
+
+    /// Send one packet whose payload lasts `frames` ticks of the RTP clock.
+    fn send_packet(&mut self, payload: &[u8], frames: usize) -> Result<(), Error> {
+        let header = rtp_header(
+            self.payload.payload_type(&self.format),
+            self.marker,
+            self.sequence,
+            self.timestamp,
+            self.ssrc,
+        );
+        let mut packet = Vec::with_capacity(header.len() + payload.len());
+        packet.extend_from_slice(&header);
+        packet.extend_from_slice(payload);
+        send(&self.rtp, &packet)?;
+        self.marker = false;
+        self.sequence = self.sequence.wrapping_add(1);
+        self.timestamp = self.timestamp.wrapping_add(frames as u32);
+        self.packet_count = self.packet_count.wrapping_add(1);
+        self.octet_count = self.octet_count.wrapping_add(payload.len() as u32);
+        if self.last_report.map_or(true, |last| last.elapsed() >= REPORT_INTERVAL) {
+            self.send_report(false)?;
+        }
+        Ok(())
+    }
+
+    fn send_opus(&mut self, packets: Vec<OpusPacket>) -> Result<(), Error> {
+        packets
+            .into_iter()
+            .try_for_each(|packet| self.send_packet(&packet.data, packet.samples))
+    }
+
+    /// Send whole L16 packets from the pending samples, or everything left when `flush`ing.
+    fn send_l16(&mut self, flush: bool) -> Result<(), Error> {
+        let packet_len = self.packet_frames * self.format.n_channels as usize;
+        loop {
+            let pending = match self.encoding {
+                Encoding::L16 { ref mut pending, .. } => pending,
+                Encoding::Opus(_) => return Ok(()),
+            };
+            let len = match pending.len() {
+                0 => return Ok(()),
+                len if len >= packet_len => packet_len,
+                len if flush => len,
+                _ => return Ok(()),
+            };
+            // L16 is big-endian, unlike everything else we write.
+            let payload: Vec<u8> = pending.drain(..len).flat_map(i16::to_be_bytes).collect();
+            self.send_packet(&payload, len / self.format.n_channels as usize)?;
+        }
+    }
+
+    fn send_report(&mut self, leaving: bool) -> Result<(), Error> {
+        let stats = SenderStats {
+            ssrc: self.ssrc,
+            rtp_time: self.timestamp,
+            ntp_time: ntp_timestamp(SystemTime::now()),
+            packet_count: self.packet_count,
+            octet_count: self.octet_count,
+        };
+        send(&self.rtcp, &rtcp_report(&stats, &self.cname, leaving))?;
+        self.last_report = Some(Instant::now());
+        Ok(())
+    }
+}
+
+impl<T> AudioWriter<T> for RtpWriter<T>
+    where
+        T: hound::Sample + stream_format::Sample + Serialize + Copy,
+{
+    fn new(format: StreamFormat) -> Result<Self, Error> {
+        RtpWriter::connect(format, "127.0.0.1:5004", RtpPayload::L16)
+    }
+
+    fn write(&mut self, data: &ExtensibleBuffer<T>, frames_available: usize) -> Result<(), Error> {
+        let samples = data.latest(frames_available * self.format.n_channels as usize);
+        match self.encoding {
+            Encoding::L16 {
+                ref mut converter,
+                ref mut pending,
+            } => {
+                let converted: Vec<i16> = converter.convert(samples);
+                pending.extend(converted);
+                self.send_l16(false)
+            }
+            Encoding::Opus(ref mut encoder) => {
+                let packets = encoder.encode(samples)?;
+                self.send_opus(packets)
+            }
+        }
+    }
+
+    fn close(&mut self) -> Result<(), Error> {
+        match self.encoding {
+            Encoding::L16 { ref converter, .. } => {
+                if converter.clipped_samples() > 0 {
+                    warn!("{} samples were clipped converting to L16", converter.clipped_samples());
+                }
+                self.send_l16(true)?;
+            }
+            Encoding::Opus(ref mut encoder) => {
+                let packets = encoder.flush()?;
+                self.send_opus(packets)?;
+            }
+        }
+        self.send_report(true)?;
+        info!(
+            "Sent {} RTP packets, {} bytes of payload, to {}",
+            self.packet_count, self.octet_count, self.destination
+        );
+        Ok(())
+    }
+}
+
+/// The most frames per L16 packet that fit in `MAX_PAYLOAD`, preferring common packet times.
+fn l16_packet_frames(format: &StreamFormat) -> usize {
+    let rate = format.n_sample_per_sec as usize;
+    let max_frames = (MAX_PAYLOAD / (2 * format.n_channels as usize)).max(1);
+    [20, 10, 5, 2, 1]
+        .iter()
+        .filter(|&&ms| rate * ms % 1000 == 0)
+        .map(|&ms| rate * ms / 1000)
+        .find(|&frames| frames <= max_frames)
+        .unwrap_or(max_frames)
+}
+
+fn connect_udp(destination: SocketAddr) -> Result<UdpSocket, Error> {
+    let local: SocketAddr = match destination {
+        SocketAddr::V4(_) => ([0, 0, 0, 0], 0).into(),
+        SocketAddr::V6(_) => ([0u16; 8], 0).into(),
+    };
+    let socket = UdpSocket::bind(local)?;
+    socket.connect(destination)?;
+    Ok(socket)
+}
+
+/// Send a datagram. Nothing listening isn't an error; receivers can join at any time.
+fn send(socket: &UdpSocket, packet: &[u8]) -> Result<(), Error> {
+    match socket.send(packet) {
+        Ok(_) => Ok(()),
+        Err(e) if e.kind() == ErrorKind::ConnectionRefused => {
+            debug!("Nothing receiving on {:?}", socket.peer_addr());
+            Ok(())
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::channel_layout::ChannelLayout;
+    use crate::stream_format::SampleFormat;
+
+    /// Sockets receiving RTP on an even port and RTCP on the port above it.
+    fn receivers() -> (UdpSocket, UdpSocket) {
+        loop {
+            let rtp = UdpSocket::bind("127.0.0.1:0").unwrap();
+            let port = rtp.local_addr().unwrap().port();
+            if port % 2 == 1 {
+                continue;
+            }
+            if let Ok(rtcp) = UdpSocket::bind(("127.0.0.1", port + 1)) {
+                for socket in [&rtp, &rtcp].iter() {
+                    socket.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+                }
+                return (rtp, rtcp);
+            }
+        }
+    }
+
+    fn receive(socket: &UdpSocket) -> Vec<u8> {
+        let mut datagram = vec![0u8; 2048];
+        let len = socket.recv(&mut datagram).unwrap();
+        datagram.truncate(len);
+        datagram
+    }
+
+    fn be_u16(bytes: &[u8]) -> u16 {
+        u16::from_be_bytes([bytes[0], bytes[1]])
+    }
+
+    fn be_u32(bytes: &[u8]) -> u32 {
+        u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+    }
+
+    #[test]
+    fn streams_l16_over_loopback() {
+        let format = StreamFormat::new(SampleFormat::I16, 48000, ChannelLayout::new(2, 0x3));
+        let (rtp, rtcp) = receivers();
+        let address = rtp.local_addr().unwrap().to_string();
+        let mut writer = RtpWriter::<i16>::connect(format, &address, RtpPayload::L16).unwrap();
+        // 5ms is the longest common packet time that fits in `MAX_PAYLOAD` at 48kHz stereo.
+        assert_eq!(writer.packet_frames, 240);
+
+        let samples: Vec<i16> = (0..600 * 2).map(|sample| (sample * 17 - 9000) as i16).collect();
+        let buffer = ExtensibleBuffer::new(samples.clone(), SampleFormat::I16);
+        writer.write(&buffer, 600).unwrap();
+        writer.close().unwrap();
+
+        let packets: Vec<Vec<u8>> = (0..3).map(|_| receive(&rtp)).collect();
+        let lengths: Vec<usize> = packets.iter().map(Vec::len).collect();
+        assert_eq!(lengths, [12 + 960, 12 + 960, 12 + 480]);
+        let first_sequence = be_u16(&packets[0][2..]);
+        let first_timestamp = be_u32(&packets[0][4..]);
+        let mut payload = vec![];
+        for (index, packet) in packets.iter().enumerate() {
+            assert_eq!(packet[0], 0x80);
+            // Only the first packet is marked, and 48kHz stereo has no static payload type.
+            assert_eq!(packet[1], ((index == 0) as u8 * 0x80) | 96);
+            assert_eq!(be_u16(&packet[2..]), first_sequence.wrapping_add(index as u16));
+            assert_eq!(be_u32(&packet[4..]), first_timestamp.wrapping_add(240 * index as u32));
+            assert_eq!(be_u32(&packet[8..]), writer.ssrc);
+            payload.extend(packet[12..].chunks_exact(2).map(|sample| be_u16(sample) as i16));
+        }
+        assert_eq!(payload, samples);
+
+        // A report with the first packet, and a last one saying goodbye.
+        let cname_len = writer.cname.len();
+        let sdes_len = 4 + (4 + 2 + cname_len + 1 + 3) / 4 * 4;
+        let first = receive(&rtcp);
+        assert_eq!(first.len(), 28 + sdes_len);
+        let last = receive(&rtcp);
+        assert_eq!(last.len(), 28 + sdes_len + 8);
+        assert_eq!(last[1], 200);
+        assert_eq!(be_u16(&last[2..]), 6);
+        assert_eq!(be_u32(&last[4..]), writer.ssrc);
+        assert_eq!(be_u32(&last[16..]), first_timestamp.wrapping_add(600));
+        assert_eq!(be_u32(&last[20..]), 3);
+        assert_eq!(be_u32(&last[24..]), 600 * 4);
+        assert_eq!(last[29], 202);
+        assert_eq!(be_u16(&last[30..]) as usize, (sdes_len - 4) / 4);
+        assert_eq!(last[28 + sdes_len..][..4], [0x81, 203, 0, 1]);
+
+        let sdp = writer.sdp("Test").unwrap();
+        let port = rtp.local_addr().unwrap().port();
+        assert!(sdp.contains("s=Test\r\nc=IN IP4 127.0.0.1\r\n"));
+        assert!(sdp.contains(&format!("m=audio {} RTP/AVP 96\r\n", port)));
+        assert!(sdp.contains("a=rtpmap:96 L16/48000/2\r\na=ptime:5\r\n"));
+    }
+}
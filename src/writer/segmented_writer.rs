@@ -0,0 +1,304 @@
+use std::fmt;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::Error;
+use log::info;
+use serde::Serialize;
+
+use crate::buffer::ExtensibleBuffer;
+use crate::stream_format;
+use crate::stream_format::{SampleFormat, StreamFormat};
+use crate::wav::metadata::{Bext, WavMetadata};
+use crate::writer::output_path::OutputPath;
+use crate::writer::rf64_writer::Rf64Writer;
+use crate::writer::AudioWriter;
+
+/// When a segmented recording moves on to a new file.
+///
+/// Written as `<n>s`, `<n>m` or `<n>h` for a length of audio, `<n>MB` or `<n>GB` for a file
+/// size, or `@<n>m`/`@<n>h` to split on wall-clock boundaries, e.g. `@1h` on the hour.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Rollover {
+    Duration(Duration),
+    Bytes(u64),
+    WallClock(Duration),
+}
+
+impl fmt::Display for Rollover {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Rollover::Duration(duration) => write!(f, "{}s", duration.as_secs()),
+            Rollover::Bytes(bytes) => write!(f, "{}MB", bytes / 1_000_000),
+            Rollover::WallClock(interval) => write!(f, "@{}s", interval.as_secs()),
+        }
+    }
+}
+
+impl FromStr for Rollover {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let (wall_clock, s) = match s.strip_prefix('@') {
+            Some(rest) => (true, rest),
+            None => (false, s),
+        };
+        let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+        let (number, unit) = s.split_at(split);
+        let number: u64 = number
+            .parse()
+            .map_err(|_| anyhow!("Rollover `{}` doesn't start with a number", s))?;
+        if number == 0 {
+            bail!("Rollover must be greater than zero");
+        }
+        let secs = match unit.to_ascii_lowercase().as_str() {
+            "s" => number,
+            "m" => number * 60,
+            "h" => number * 3600,
+            "mb" | "gb" if wall_clock => bail!("Wall-clock rollover needs a time, got `{}`", s),
+            "mb" => return Ok(Rollover::Bytes(number * 1_000_000)),
+            "gb" => return Ok(Rollover::Bytes(number * 1_000_000_000)),
+            _ => bail!("Unknown rollover unit `{}`, expected s, m, h, MB or GB", unit),
+        };
+        let duration = Duration::from_secs(secs);
+        if wall_clock {
+            Ok(Rollover::WallClock(duration))
+        } else {
+            Ok(Rollover::Duration(duration))
+        }
+    }
+}
+
+/// Records to a series of WAV files, starting a new one whenever the rollover is reached.
+///
+/// Segments are measured in frames rather than by the clock, so every captured frame lands in
+/// exactly one file and consecutive files join up without a gap. Each segment carries the
+/// recording's metadata, with its `bext` times set to the segment's own start.
+pub(crate) struct SegmentedWriter<T>
+    where
+        T: hound::Sample + stream_format::Sample + Serialize + Copy,
+{
+    format: StreamFormat,
+    output: Option<SampleFormat>,
+    path: OutputPath,
+    rollover: Rollover,
+    metadata: WavMetadata,
+    current: Rf64Writer<T>,
+    /// Frames still to go into the current segment.
+    frames_left: u64,
+    segment: u64,
+}
+
+impl<T> SegmentedWriter<T>
+    where
+        T: hound::Sample + stream_format::Sample + Serialize + Copy,
+{
+    pub(crate) fn create(
+        format: StreamFormat,
+        path: OutputPath,
+        output: Option<SampleFormat>,
+        rollover: Rollover,
+        metadata: &WavMetadata,
+    ) -> Result<Self, Error> {
+        let now = SystemTime::now();
+        let current = Rf64Writer::create(
+            format,
+            unused_path(path.render_segment(now, 0), 0),
+            output,
+            &segment_metadata(&format, metadata, now),
+        )?;
+        let mut writer = SegmentedWriter {
+            format,
+            output,
+            path,
+            rollover,
+            metadata: metadata.clone(),
+            frames_left: 0,
+            segment: 0,
+            current,
+        };
+        writer.frames_left = writer.segment_frames(now);
+        info!("Splitting the recording every {}", rollover);
+        Ok(writer)
+    }
+
+    /// Length of a segment opened at `opened`.
+    fn segment_frames(&self, opened: SystemTime) -> u64 {
+        let rate = self.format.n_sample_per_sec as u64;
+        let frames = match self.rollover {
+            Rollover::Duration(duration) => duration_frames(duration, rate),
+            Rollover::Bytes(bytes) => bytes / self.frame_bytes(),
+            // The first segment runs up to the next boundary, the rest are a whole interval.
+            Rollover::WallClock(interval) if self.segment == 0 => {
+                let interval_ms = interval.as_millis().max(1) as u64;
+                let since_epoch = opened.duration_since(UNIX_EPOCH).unwrap_or_default();
+                let into_interval = since_epoch.as_millis() as u64 % interval_ms;
+                (interval_ms - into_interval) * rate / 1000
+            }
+            Rollover::WallClock(interval) => duration_frames(interval, rate),
+        };
+        frames.max(1)
+    }
+
+    /// Bytes per frame in the file.
+    fn frame_bytes(&self) -> u64 {
+        self.current.output_format().n_block_align.max(1) as u64
+    }
+
+    fn roll_over(&mut self) -> Result<(), Error> {
+        self.current.close()?;
+        self.segment += 1;
+        let now = SystemTime::now();
+        let path = unused_path(self.path.render_segment(now, self.segment), self.segment);
+        let metadata = segment_metadata(&self.format, &self.metadata, now);
+        self.current = Rf64Writer::create(self.format, path, self.output, &metadata)?;
+        self.frames_left = self.segment_frames(now);
+        Ok(())
+    }
+}
+
+impl<T> AudioWriter<T> for SegmentedWriter<T>
+    where
+        T: hound::Sample + stream_format::Sample + Serialize + Copy,
+{
+    fn new(format: StreamFormat) -> Result<Self, Error> {
+        let metadata = WavMetadata::for_recording(&format, None, None, SystemTime::now());
+        SegmentedWriter::create(
+            format,
+            OutputPath::default(),
+            None,
+            Rollover::Duration(Duration::from_secs(3600)),
+            &metadata,
+        )
+    }
+
+    fn write(&mut self, data: &ExtensibleBuffer<T>, frames_available: usize) -> Result<(), Error> {
+        if (frames_available as u64) < self.frames_left {
+            self.frames_left -= frames_available as u64;
+            return self.current.write(data, frames_available);
+        }
+
+        let n_channels = self.format.n_channels as usize;
+        let mut samples = data.latest(frames_available * n_channels);
+        while !samples.is_empty() {
+            let frames = std::cmp::min((samples.len() / n_channels) as u64, self.frames_left);
+            let (head, rest) = samples.split_at(frames as usize * n_channels);
+            if !head.is_empty() {
+                let buffer = ExtensibleBuffer::new(head.to_vec(), T::FORMAT);
+                self.current.write(&buffer, frames as usize)?;
+                self.frames_left -= frames;
+            }
+            if self.frames_left == 0 {
+                self.roll_over()?;
+            }
+            samples = rest;
+        }
+        Ok(())
+    }
+
+    fn close(&mut self) -> Result<(), Error> {
+        self.current.close()
+    }
+}
+
+fn duration_frames(duration: Duration, rate: u64) -> u64 {
+    duration.as_millis() as u64 * rate / 1000
+}
+
+/// `path`, or a numbered variant of it if that's taken.
+///
+/// Short segments can open within the same second, the template may not distinguish segments
+/// at all, and an earlier recording may have used the same name, so never overwrite a file.
+fn unused_path(mut path: PathBuf, segment: u64) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy().into_owned();
+    let extension = path.extension().map(|extension| extension.to_string_lossy().into_owned());
+    let mut n = segment;
+    while path.exists() {
+        let name = match &extension {
+            Some(extension) => format!("{}-{:04}.{}", stem, n, extension),
+            None => format!("{}-{:04}", stem, n),
+        };
+        path.set_file_name(name);
+        n += 1;
+    }
+    path
+}
+
+/// `metadata` for a segment whose first sample was captured at `start`.
+fn segment_metadata(
+    format: &StreamFormat,
+    metadata: &WavMetadata,
+    start: SystemTime,
+) -> WavMetadata {
+    let mut metadata = metadata.clone();
+    if let Some(bext) = metadata.bext.as_mut() {
+        let timing = Bext::new(format, start, &bext.description);
+        bext.origination_date = timing.origination_date;
+        bext.origination_time = timing.origination_time;
+        bext.time_reference = timing.time_reference;
+    }
+    metadata
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    use crate::channel_layout::ChannelLayout;
+    use crate::wav::reader::WavReader;
+
+    #[test]
+    fn every_segment_carries_the_metadata() {
+        let format = StreamFormat::new(SampleFormat::I16, 8000, ChannelLayout::new(1, 0x4));
+        let template = std::env::temp_dir()
+            .join(format!("audia-segment-{}-{{segment}}.wav", std::process::id()));
+        let path = OutputPath::new(&template.to_string_lossy(), "Mic");
+        let metadata = WavMetadata::for_recording(&format, Some("Mic"), None, SystemTime::now());
+        let rollover = Rollover::Duration(Duration::from_secs(1));
+        let mut writer =
+            SegmentedWriter::<i16>::create(format, path.clone(), None, rollover, &metadata)
+                .unwrap();
+        let samples: Vec<i16> = (0..12000).map(|sample| sample as i16).collect();
+        writer.write(&ExtensibleBuffer::new(samples, SampleFormat::I16), 12000).unwrap();
+        writer.close().unwrap();
+
+        let mut first_samples = Vec::new();
+        for (segment, n_frames) in [(0, 8000), (1, 4000)] {
+            let segment_path = path.render_segment(SystemTime::now(), segment);
+            let mut reader = WavReader::new(fs::File::open(&segment_path).unwrap()).unwrap();
+            fs::remove_file(&segment_path).unwrap();
+            assert_eq!(reader.n_frames(), n_frames);
+            let bext = reader.metadata().unwrap().bext.unwrap();
+            assert_eq!(bext.description, "Recorded from Mic");
+            first_samples.push(reader.read::<i16>(1).unwrap()[0]);
+        }
+        assert_eq!(first_samples, vec![0, 8000]);
+    }
+
+    #[test]
+    fn never_overwrites_existing_files() {
+        let format = StreamFormat::new(SampleFormat::I16, 8000, ChannelLayout::new(1, 0x4));
+        let existing =
+            std::env::temp_dir().join(format!("audia-existing-{}.wav", std::process::id()));
+        fs::write(&existing, b"keep").unwrap();
+        let path = OutputPath::new(&existing.to_string_lossy(), "Mic");
+        let metadata = WavMetadata::for_recording(&format, None, None, SystemTime::now());
+        let rollover = Rollover::Duration(Duration::from_secs(1));
+        let mut writer =
+            SegmentedWriter::<i16>::create(format, path, None, rollover, &metadata).unwrap();
+        writer.write(&ExtensibleBuffer::new(vec![0; 12000], SampleFormat::I16), 12000).unwrap();
+        writer.close().unwrap();
+
+        assert_eq!(fs::read(&existing).unwrap(), b"keep");
+        fs::remove_file(&existing).unwrap();
+        for segment in 0..2 {
+            let name = format!("audia-existing-{}-{:04}.wav", std::process::id(), segment);
+            let segment_path = existing.with_file_name(name);
+            assert!(segment_path.exists(), "{} is missing", segment_path.display());
+            fs::remove_file(&segment_path).unwrap();
+        }
+    }
+}
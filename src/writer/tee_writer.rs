@@ -0,0 +1,52 @@
+use anyhow::Error;
+use serde::Serialize;
+
+use crate::buffer::ExtensibleBuffer;
+use crate::stream_format;
+use crate::stream_format::StreamFormat;
+use crate::writer::AudioWriter;
+
+/// Forwards every packet to several writers.
+pub(crate) struct TeeWriter<T>
+    where
+        T: hound::Sample + stream_format::Sample + Serialize + Copy,
+{
+    writers: Vec<Box<dyn AudioWriter<T>>>,
+}
+
+impl<T> TeeWriter<T>
+    where
+        T: hound::Sample + stream_format::Sample + Serialize + Copy,
+{
+    pub(crate) fn with_writers(writers: Vec<Box<dyn AudioWriter<T>>>) -> Self {
+        TeeWriter { writers }
+    }
+
+    pub(crate) fn push(&mut self, writer: Box<dyn AudioWriter<T>>) {
+        self.writers.push(writer);
+    }
+}
+
+impl<T> AudioWriter<T> for TeeWriter<T>
+    where
+        T: hound::Sample + stream_format::Sample + Serialize + Copy,
+{
+    fn new(_format: StreamFormat) -> Result<Self, Error> {
+        Ok(TeeWriter { writers: vec![] })
+    }
+
+    /// Every writer sees every packet; the first error is returned once all have been written.
+    fn write(&mut self, data: &ExtensibleBuffer<T>, frames_available: usize) -> Result<(), Error> {
+        self.writers
+            .iter_mut()
+            .map(|writer| writer.write(data, frames_available))
+            .fold(Ok(()), |result, next| result.and(next))
+    }
+
+    fn close(&mut self) -> Result<(), Error> {
+        self.writers
+            .iter_mut()
+            .map(|writer| writer.close())
+            .fold(Ok(()), |result, next| result.and(next))
+    }
+}